@@ -0,0 +1,98 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::cfg::{Alert, AlertSeverity};
+
+/// Notifications for inserted and removed storage media, like USB sticks and
+/// SD cards.
+///
+/// Available variables: ${device}, ${label}, ${size}, ${fs_type}, ${model},
+/// ${mounted} ("yes" or "no") and ${mountpoint}
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlockConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Only announce removable media, not eg internal disks.
+    #[serde(default = "default_true")]
+    pub removable_only: bool,
+    /// Seconds to wait for an automounter before looking up whether inserted
+    /// media was mounted.
+    #[serde(default = "BlockConfig::default_mount_delay_seconds")]
+    pub mount_delay_seconds: u64,
+    /// Add an "Open" button to the insertion alert if the media was mounted,
+    /// or a "Mount" button otherwise.
+    #[serde(default = "default_true")]
+    pub actions: bool,
+    #[serde(default = "BlockConfig::default_mount_command")]
+    pub mount_command: Vec<String>,
+    #[serde(default = "BlockConfig::default_open_command")]
+    pub open_command: Vec<String>,
+
+    #[serde(default = "BlockConfig::default_alert_inserted")]
+    pub alert_inserted: Option<Alert>,
+    #[serde(default = "BlockConfig::default_alert_removed")]
+    pub alert_removed: Option<Alert>,
+}
+
+impl BlockConfig {
+    pub fn validate(self) -> Result<Self, anyhow::Error> {
+        if self.actions && (self.mount_command.is_empty() || self.open_command.is_empty()) {
+            anyhow::bail!("block.mount_command and block.open_command must not be empty");
+        }
+        Ok(self)
+    }
+
+    fn default_mount_delay_seconds() -> u64 {
+        2
+    }
+
+    fn default_mount_command() -> Vec<String> {
+        ["udisksctl", "mount", "--block-device", "${device}"]
+            .map(String::from)
+            .to_vec()
+    }
+
+    fn default_open_command() -> Vec<String> {
+        ["xdg-open", "${mountpoint}"].map(String::from).to_vec()
+    }
+
+    fn default_alert_inserted() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Info,
+            on_startup: false,
+            repeat_after_seconds: None,
+            expire_after_seconds: Some(10),
+            summary: "${label} inserted (${size})".to_string(),
+            message: Some("${fs_type} on ${device}, mounted: ${mounted} ${mountpoint}".to_string()),
+        })
+    }
+
+    fn default_alert_removed() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Info,
+            on_startup: false,
+            repeat_after_seconds: None,
+            expire_after_seconds: Some(10),
+            summary: "${label} removed".to_string(),
+            message: None,
+        })
+    }
+}
+
+impl Default for BlockConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            removable_only: true,
+            mount_delay_seconds: Self::default_mount_delay_seconds(),
+            actions: true,
+            mount_command: Self::default_mount_command(),
+            open_command: Self::default_open_command(),
+            alert_inserted: Self::default_alert_inserted(),
+            alert_removed: Self::default_alert_removed(),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
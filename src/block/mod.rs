@@ -0,0 +1,535 @@
+//! Notifications for removable storage media, based on udev `block` events.
+
+pub mod cfg;
+
+use std::{collections::HashMap, path::Path, time::Duration};
+
+use anyhow::Context;
+use futures::{Stream, StreamExt};
+
+use crate::{
+    fs::{load_proc_mounts, Mount},
+    notify::{Notifier, NotifyAction},
+    udev::{resync_events, Action, DeviceEvent, Lagged, Subscriber, UdevHub},
+};
+
+use self::cfg::BlockConfig;
+
+const ALERT_GROUP_BLOCK_PREFIX: &str = "panorama.block.";
+
+const SUBSYSTEM: &str = "block";
+/// The size in 512 byte sectors, regardless of the logical block size.
+const ATTRIBUTES: &[&str] = &["size"];
+const SECTOR_SIZE: u64 = 512;
+
+/// A block device with a filesystem.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Media {
+    /// The device node, eg /dev/sdb1.
+    device: String,
+    /// Alternative device paths, eg /dev/disk/by-uuid/...
+    links: Vec<String>,
+    label: String,
+    size_bytes: Option<u64>,
+    fs_type: String,
+    model: String,
+    removable: bool,
+}
+
+impl Media {
+    /// Returns `None` for devices without a filesystem, like partitioned disks
+    /// or empty card readers.
+    fn from_event(event: &DeviceEvent) -> Option<Self> {
+        if event.property("ID_FS_USAGE") != Some("filesystem") {
+            return None;
+        }
+        let device = event.property("DEVNAME")?.to_string();
+        let property = |name: &str| event.property(name).unwrap_or_default().replace('_', " ");
+
+        let model = format!("{} {}", property("ID_VENDOR"), property("ID_MODEL"))
+            .trim()
+            .to_string();
+        let label = event
+            .property("ID_FS_LABEL_ENC")
+            .map(decode_udev_string)
+            .filter(|label| !label.is_empty())
+            .or_else(|| (!model.is_empty()).then(|| model.clone()))
+            .unwrap_or_else(|| device.clone());
+
+        Some(Self {
+            links: event
+                .property("DEVLINKS")
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(String::from)
+                .collect(),
+            label,
+            size_bytes: event
+                .attribute("size")
+                .and_then(|sectors| sectors.parse::<u64>().ok())
+                .map(|sectors| sectors * SECTOR_SIZE),
+            fs_type: event.property("ID_FS_TYPE").unwrap_or_default().to_string(),
+            model,
+            removable: is_removable(event),
+            device,
+        })
+    }
+
+    fn find_mount<'a>(&self, mounts: &'a [Mount]) -> Option<&'a Mount> {
+        mounts
+            .iter()
+            .find(|mount| mount.device == self.device || self.links.contains(&mount.device))
+    }
+
+    fn variables(&self, mount: Option<&Mount>) -> HashMap<String, String> {
+        HashMap::from([
+            ("device".to_string(), self.device.clone()),
+            ("label".to_string(), self.label.clone()),
+            (
+                "size".to_string(),
+                self.size_bytes.map(format_size).unwrap_or_default(),
+            ),
+            ("fs_type".to_string(), self.fs_type.clone()),
+            ("model".to_string(), self.model.clone()),
+            (
+                "mounted".to_string(),
+                if mount.is_some() { "yes" } else { "no" }.to_string(),
+            ),
+            (
+                "mountpoint".to_string(),
+                mount.map(|m| m.mountpoint.clone()).unwrap_or_default(),
+            ),
+        ])
+    }
+}
+
+/// Whether the device is a USB drive or a memory card.
+///
+/// The sysfs `removable` flag alone is insufficient: many USB sticks claim to
+/// be fixed disks, and SD cards in built-in readers are reported as fixed
+/// too.
+fn is_removable(event: &DeviceEvent) -> bool {
+    if event.property("ID_BUS") == Some("usb")
+        || event.property("ID_DRIVE_FLASH_SD") == Some("1")
+        || event.property("ID_DRIVE_MEDIA_FLASH_SD") == Some("1")
+    {
+        return true;
+    }
+
+    let syspath = Path::new(&event.syspath);
+    let disk = match event.devtype.as_deref() {
+        Some("partition") => syspath.parent().unwrap_or(syspath),
+        _ => syspath,
+    };
+    let read = |name: &str| {
+        std::fs::read_to_string(disk.join(name))
+            .map(|value| value.trim().to_string())
+            .unwrap_or_default()
+    };
+    read("removable") == "1" || read("device/type") == "SD"
+}
+
+/// Decode the `\xNN` escapes of udev `*_ENC` properties.
+fn decode_udev_string(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .strip_prefix(b"x")
+            .and_then(|hex| hex.get(..2))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(decoded) if byte == b'\\' => {
+                bytes.push(decoded);
+                rest = &tail[3..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).trim().to_string()
+}
+
+/// Format a size with decimal units, as printed on the media.
+fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["kB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for next in UNITS {
+        if size < 1000.0 {
+            break;
+        }
+        size /= 1000.0;
+        unit = next;
+    }
+    format!("{size:.1} {unit}")
+}
+
+pub struct BlockManager {
+    config: BlockConfig,
+    notifier: Notifier,
+    /// Present media by syspath, since remove events lack the details.
+    media: HashMap<String, Media>,
+    /// Insertion alerts waiting for the mount delay, by syspath.
+    pending: HashMap<String, tokio::task::JoinHandle<()>>,
+    load_mounts: fn() -> Result<Vec<Mount>, anyhow::Error>,
+}
+
+impl BlockManager {
+    pub fn subscribe(hub: &UdevHub) -> Subscriber {
+        hub.subscribe(vec![(SUBSYSTEM.to_string(), None)], ATTRIBUTES)
+    }
+
+    pub async fn start(
+        config: BlockConfig,
+        notifier: Notifier,
+        events: Subscriber,
+    ) -> Result<(), anyhow::Error> {
+        let manager = Self::new(config, notifier)?;
+        tokio::task::spawn_local(async move { manager.run(events).await })
+            .await
+            .context("BlockManager task failed")?
+            .context("BlockManager failed")?;
+
+        Ok(())
+    }
+
+    fn new(config: BlockConfig, notifier: Notifier) -> Result<Self, anyhow::Error> {
+        let config = config.validate()?;
+        Ok(Self {
+            config,
+            notifier,
+            media: HashMap::new(),
+            pending: HashMap::new(),
+            load_mounts: load_proc_mounts,
+        })
+    }
+
+    async fn run(mut self, events: Subscriber) -> Result<(), anyhow::Error> {
+        for event in crate::udev::enumerate(SUBSYSTEM, None, ATTRIBUTES)? {
+            if let Some(media) = self.announced_media(&event) {
+                self.media.insert(event.syspath, media);
+            }
+        }
+        tracing::debug!(count = self.media.len(), "found present storage media");
+
+        self.run_events(events.into_stream()).await
+    }
+
+    async fn run_events(
+        mut self,
+        mut events: impl Stream<Item = Result<DeviceEvent, anyhow::Error>> + Unpin,
+    ) -> Result<(), anyhow::Error> {
+        while let Some(event) = events.next().await {
+            match event {
+                Ok(event) => self.handle(event).await?,
+                Err(err) if err.is::<Lagged>() => {
+                    tracing::warn!(error = &*err, "re-enumerating storage media");
+                    let current = crate::udev::enumerate(SUBSYSTEM, None, ATTRIBUTES)?;
+                    let known = self.media.keys().cloned().collect();
+                    for event in resync_events(current, &known) {
+                        self.handle(event).await?;
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        anyhow::bail!("udev event stream ended")
+    }
+
+    async fn handle(&mut self, event: DeviceEvent) -> Result<(), anyhow::Error> {
+        match event.action {
+            // Card readers report inserted and removed cards as changes.
+            Action::Add | Action::Change => match self.announced_media(&event) {
+                Some(media) if !self.media.contains_key(&event.syspath) => {
+                    self.inserted(event.syspath, media).await?;
+                }
+                Some(_) => {}
+                None => self.removed(&event.syspath).await?,
+            },
+            Action::Remove => self.removed(&event.syspath).await?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn announced_media(&self, event: &DeviceEvent) -> Option<Media> {
+        Media::from_event(event).filter(|media| media.removable || !self.config.removable_only)
+    }
+
+    async fn inserted(&mut self, syspath: String, media: Media) -> Result<(), anyhow::Error> {
+        tracing::debug!(?media, "storage media inserted");
+        self.media.insert(syspath.clone(), media.clone());
+        let Some(alert) = self.config.alert_inserted.clone() else {
+            return Ok(());
+        };
+
+        // Give automounters a chance to mount the media first, without
+        // holding up other events meanwhile.
+        let delay = Duration::from_secs(self.config.mount_delay_seconds);
+        let load_mounts = self.load_mounts;
+        let actions = self.config.actions;
+        let open_command = self.config.open_command.clone();
+        let mount_command = self.config.mount_command.clone();
+        let notifier = self.notifier.clone();
+        let group = format!("{ALERT_GROUP_BLOCK_PREFIX}{syspath}");
+        let task = tokio::task::spawn_local(async move {
+            tokio::time::sleep(delay).await;
+            let mounts = match tokio::task::spawn_blocking(load_mounts).await {
+                Ok(Ok(mounts)) => mounts,
+                Ok(Err(err)) => {
+                    tracing::warn!(error = &*err, "could not load active mounts");
+                    Vec::new()
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        error = &err as &dyn std::error::Error,
+                        "load_mounts task failed"
+                    );
+                    Vec::new()
+                }
+            };
+            let mount = media.find_mount(&mounts);
+
+            let action = actions.then(|| match mount {
+                Some(_) => NotifyAction {
+                    label: "Open".to_string(),
+                    command: open_command,
+                },
+                None => NotifyAction {
+                    label: "Mount".to_string(),
+                    command: mount_command,
+                },
+            });
+            let full = alert.prepare(group, media.variables(mount)).action(action);
+            if let Err(err) = notifier.notify(full).await {
+                tracing::warn!(error = &*err, "could not send storage media alert");
+            }
+        });
+        if let Some(previous) = self.pending.insert(syspath, task) {
+            previous.abort();
+        }
+        Ok(())
+    }
+
+    async fn removed(&mut self, syspath: &str) -> Result<(), anyhow::Error> {
+        let Some(media) = self.media.remove(syspath) else {
+            return Ok(());
+        };
+        // Media pulled within the mount delay was never announced as
+        // inserted, so its removal is not announced either.
+        if let Some(task) = self.pending.remove(syspath) {
+            if !task.is_finished() {
+                task.abort();
+                tracing::debug!(?media, "storage media removed within the mount delay");
+                return Ok(());
+            }
+        }
+        tracing::debug!(?media, "storage media removed");
+        if let Some(alert) = &self.config.alert_removed {
+            let group = format!("{ALERT_GROUP_BLOCK_PREFIX}{syspath}");
+            let full = alert.prepare(group, media.variables(None));
+            self.notifier.notify(full).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    const SYSPATH: &str = "/sys/devices/pci0000:00/usb2/2-1/host6/block/sdb/sdb1";
+
+    /// A udev event of the partition of a USB stick.
+    fn partition_event(action: Action) -> DeviceEvent {
+        let mut properties = HashMap::from(
+            [
+                ("DEVNAME", "/dev/sdb1"),
+                (
+                    "DEVLINKS",
+                    "/dev/disk/by-uuid/1234-ABCD /dev/disk/by-label/MY\\x20STICK",
+                ),
+                ("ID_BUS", "usb"),
+                ("ID_VENDOR", "SanDisk"),
+                ("ID_MODEL", "Ultra_Fit"),
+            ]
+            .map(|(k, v)| (k.to_string(), v.to_string())),
+        );
+        if action != Action::Remove {
+            properties.extend(
+                [
+                    ("ID_FS_USAGE", "filesystem"),
+                    ("ID_FS_TYPE", "vfat"),
+                    ("ID_FS_LABEL", "MY_STICK"),
+                    ("ID_FS_LABEL_ENC", "MY\\x20STICK"),
+                ]
+                .map(|(k, v)| (k.to_string(), v.to_string())),
+            );
+        }
+        DeviceEvent {
+            action,
+            syspath: SYSPATH.to_string(),
+            subsystem: Some(SUBSYSTEM.to_string()),
+            devtype: Some("partition".to_string()),
+            properties,
+            attributes: HashMap::from([("size".to_string(), "31266816".to_string())]),
+        }
+    }
+
+    fn manager(
+        load_mounts: fn() -> Result<Vec<Mount>, anyhow::Error>,
+    ) -> (
+        BlockManager,
+        tokio::sync::mpsc::Receiver<crate::notify::PreparedAlert>,
+    ) {
+        let config = BlockConfig {
+            mount_delay_seconds: 0,
+            ..Default::default()
+        };
+        let (notifier, alerts) = Notifier::test_channel();
+        let mut manager = BlockManager::new(config, notifier).unwrap();
+        manager.load_mounts = load_mounts;
+        (manager, alerts)
+    }
+
+    #[tokio::test]
+    async fn test_insert_and_remove() {
+        tokio::task::LocalSet::new()
+            .run_until(async move {
+                let (mut manager, mut alerts) = manager(|| Ok(Vec::new()));
+
+                manager.handle(partition_event(Action::Add)).await.unwrap();
+                let alert = alerts.recv().await.unwrap();
+                assert_eq!(alert.alert.summary, "${label} inserted (${size})");
+                assert_eq!(alert.variables["label"], "MY STICK");
+                assert_eq!(alert.variables["size"], "16.0 GB");
+                assert_eq!(alert.variables["fs_type"], "vfat");
+                assert_eq!(alert.variables["model"], "SanDisk Ultra Fit");
+                assert_eq!(alert.variables["mounted"], "no");
+                assert_eq!(
+                    alert.action,
+                    Some(NotifyAction {
+                        label: "Mount".to_string(),
+                        command: BlockConfig::default().mount_command,
+                    })
+                );
+
+                // Repeated change events of present media are not announced.
+                manager
+                    .handle(partition_event(Action::Change))
+                    .await
+                    .unwrap();
+                assert!(alerts.try_recv().is_err());
+
+                manager
+                    .handle(partition_event(Action::Remove))
+                    .await
+                    .unwrap();
+                let alert = alerts.try_recv().unwrap();
+                assert_eq!(alert.alert.summary, "${label} removed");
+                assert_eq!(alert.variables["label"], "MY STICK");
+                assert!(manager.media.is_empty());
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_mounted_media() {
+        tokio::task::LocalSet::new()
+            .run_until(async move {
+                let (mut manager, mut alerts) = manager(|| {
+                    Ok(vec![Mount {
+                        device: "/dev/disk/by-uuid/1234-ABCD".to_string(),
+                        mountpoint: "/run/media/me/MY STICK".to_string(),
+                        fstype: "vfat".to_string(),
+                        options: vec!["rw".to_string()],
+                    }])
+                });
+                manager.handle(partition_event(Action::Add)).await.unwrap();
+                let alert = alerts.recv().await.unwrap();
+                assert_eq!(alert.variables["mounted"], "yes");
+                assert_eq!(alert.variables["mountpoint"], "/run/media/me/MY STICK");
+                assert_eq!(alert.action.unwrap().label, "Open");
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_removed_within_mount_delay() {
+        tokio::task::LocalSet::new()
+            .run_until(async move {
+                let (mut manager, mut alerts) = manager(|| Ok(Vec::new()));
+                manager.config.mount_delay_seconds = 60;
+
+                // Events are handled meanwhile.
+                manager.handle(partition_event(Action::Add)).await.unwrap();
+                manager
+                    .handle(partition_event(Action::Remove))
+                    .await
+                    .unwrap();
+                tokio::task::yield_now().await;
+                assert!(alerts.try_recv().is_err());
+                assert!(manager.pending.is_empty());
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_ignored_devices() {
+        let (mut manager, mut alerts) = manager(|| Ok(Vec::new()));
+
+        // A partitioned disk, without a filesystem of its own.
+        let mut disk = partition_event(Action::Add);
+        disk.properties.remove("ID_FS_USAGE");
+        manager.handle(disk).await.unwrap();
+
+        // An internal disk.
+        let mut internal = partition_event(Action::Add);
+        internal.properties.remove("ID_BUS");
+        internal.syspath = "/sys/devices/nonexistent/block/nvme0n1/nvme0n1p1".to_string();
+        manager.handle(internal).await.unwrap();
+
+        assert!(alerts.try_recv().is_err());
+        assert!(manager.media.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_card_reader() {
+        tokio::task::LocalSet::new()
+            .run_until(async move {
+                let dir = crate::testutil::TempDir::new("block");
+                dir.write("mmcblk0/device/type", "SD\n");
+                let syspath = dir.path().join("mmcblk0").display().to_string();
+                let (mut manager, mut alerts) = manager(|| Ok(Vec::new()));
+
+                let mut card = partition_event(Action::Change);
+                card.properties.remove("ID_BUS");
+                card.syspath = syspath.clone();
+                card.devtype = Some("disk".to_string());
+                manager.handle(card.clone()).await.unwrap();
+                assert_eq!(alerts.recv().await.unwrap().variables["label"], "MY STICK");
+
+                // The card was pulled, the reader remains.
+                card.properties.remove("ID_FS_USAGE");
+                manager.handle(card).await.unwrap();
+                assert_eq!(alerts.try_recv().unwrap().alert.summary, "${label} removed");
+            })
+            .await;
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1_500_000), "1.5 MB");
+        assert_eq!(format_size(2_000_000_000_000), "2.0 TB");
+    }
+}
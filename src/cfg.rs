@@ -4,7 +4,11 @@ use anyhow::Context;
 use serde_derive::{Deserialize, Serialize};
 
 use crate::{
-    fs::cfg::FsConfig, internet::cfg::OnlineConfig, notify::PreparedAlert, power::cfg::PowerConfig,
+    block::cfg::BlockConfig, data_usage::cfg::DataUsageConfig, display::cfg::DisplayConfig,
+    fs::cfg::FsConfig, internet::cfg::OnlineConfig, ipc::cfg::IpcConfig,
+    network::cfg::NetworkConfig, notify::PreparedAlert, power::cfg::PowerConfig,
+    public_ip::cfg::PublicIpConfig, udev::cfg::UdevConfig, usb::cfg::UsbConfig,
+    vpn::cfg::VpnConfig, wifi::cfg::WifiConfig,
 };
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -18,6 +22,8 @@ pub enum AlertSeverity {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Alert {
     pub severity: AlertSeverity,
+    /// Whether to send the alert if the condition is already present when
+    /// panorama starts.
     pub on_startup: bool,
     pub repeat_after_seconds: Option<u64>,
     pub expire_after_seconds: Option<u64>,
@@ -35,6 +41,8 @@ impl Alert {
             alert: self.clone(),
             group: group.into(),
             variables: variables.into(),
+            initial: false,
+            action: None,
         }
     }
 }
@@ -47,6 +55,26 @@ pub struct Config {
     pub online: OnlineConfig,
     #[serde(default)]
     pub fs: FsConfig,
+    #[serde(default)]
+    pub public_ip: PublicIpConfig,
+    #[serde(default)]
+    pub network: NetworkConfig,
+    #[serde(default)]
+    pub wifi: WifiConfig,
+    #[serde(default)]
+    pub data_usage: DataUsageConfig,
+    #[serde(default)]
+    pub vpn: VpnConfig,
+    #[serde(default)]
+    pub usb: UsbConfig,
+    #[serde(default)]
+    pub block: BlockConfig,
+    #[serde(default)]
+    pub display: DisplayConfig,
+    #[serde(default)]
+    pub udev: UdevConfig,
+    #[serde(default)]
+    pub ipc: IpcConfig,
 }
 
 impl Config {
@@ -0,0 +1,152 @@
+use std::path::PathBuf;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::cfg::{Alert, AlertSeverity};
+
+/// Data usage tracking for metered connections.
+///
+/// Available variables: ${budget}, ${period} ("day" or "month"),
+/// ${percent}, ${used_mb}, ${limit_mb}
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DataUsageConfig {
+    #[serde(default = "DataUsageConfig::default_check_interval_seconds")]
+    pub check_interval_seconds: u64,
+    /// File the counters are persisted in across restarts.
+    /// Defaults to `$XDG_STATE_HOME/panorama/data_usage.json`.
+    #[serde(default)]
+    pub state_path: Option<PathBuf>,
+    /// Budgets to track. Tracking is disabled if empty.
+    #[serde(default)]
+    pub budgets: Vec<DataBudget>,
+
+    /// Sent when the usage of a budget crosses one of its thresholds.
+    #[serde(default = "DataUsageConfig::default_alert_threshold")]
+    pub alert_threshold: Option<Alert>,
+}
+
+impl DataUsageConfig {
+    pub fn validate(self) -> Result<Self, anyhow::Error> {
+        if self.check_interval_seconds == 0 {
+            anyhow::bail!("'data_usage.check_interval_seconds' must be greater than 0");
+        }
+        let mut names = std::collections::HashSet::new();
+        for budget in &self.budgets {
+            if !names.insert(&budget.name) {
+                anyhow::bail!("data budget '{}' is defined multiple times", budget.name);
+            }
+            if budget.interfaces.is_empty() {
+                anyhow::bail!(
+                    "data budget '{}' must match at least one interface",
+                    budget.name
+                );
+            }
+            if budget.limit_mb == 0 {
+                anyhow::bail!(
+                    "data budget '{}': 'limit_mb' must be greater than 0",
+                    budget.name
+                );
+            }
+            if !(1..=28).contains(&budget.month_start_day) {
+                anyhow::bail!(
+                    "data budget '{}': 'month_start_day' must be between 1 and 28",
+                    budget.name
+                );
+            }
+            if budget.thresholds_percent.contains(&0) {
+                anyhow::bail!(
+                    "data budget '{}': thresholds must be greater than 0",
+                    budget.name
+                );
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn state_path(&self) -> PathBuf {
+        if let Some(path) = &self.state_path {
+            return path.clone();
+        }
+
+        let dir = match std::env::var_os("XDG_STATE_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => match std::env::var_os("HOME") {
+                Some(home) => PathBuf::from(home).join(".local/state"),
+                None => std::env::temp_dir(),
+            },
+        };
+        dir.join("panorama").join("data_usage.json")
+    }
+
+    fn default_check_interval_seconds() -> u64 {
+        60
+    }
+
+    fn default_alert_threshold() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Warning,
+            on_startup: true,
+            repeat_after_seconds: None,
+            expire_after_seconds: None,
+            summary: "Data budget ${budget}: ${percent}% used".to_string(),
+            message: Some("${used_mb} of ${limit_mb} MB used this ${period}".to_string()),
+        })
+    }
+}
+
+impl Default for DataUsageConfig {
+    fn default() -> Self {
+        Self {
+            check_interval_seconds: Self::default_check_interval_seconds(),
+            state_path: None,
+            budgets: Vec::new(),
+            alert_threshold: Self::default_alert_threshold(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DataBudget {
+    pub name: String,
+    /// Interfaces counted towards the budget. Patterns may end with a `*`
+    /// wildcard (eg "wwan*").
+    pub interfaces: Vec<String>,
+    #[serde(default)]
+    pub period: BudgetPeriod,
+    /// Received and transmitted megabytes (1 MB = 1,000,000 bytes) allowed
+    /// per period.
+    pub limit_mb: u64,
+    /// Day of the month on which monthly budgets reset.
+    #[serde(default = "DataBudget::default_month_start_day")]
+    pub month_start_day: u8,
+    /// Usage percentages at which to alert, once per period.
+    #[serde(default = "DataBudget::default_thresholds_percent")]
+    pub thresholds_percent: Vec<u8>,
+}
+
+impl DataBudget {
+    fn default_month_start_day() -> u8 {
+        1
+    }
+
+    fn default_thresholds_percent() -> Vec<u8> {
+        vec![80, 100]
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetPeriod {
+    Daily,
+    #[default]
+    Monthly,
+}
+
+impl BudgetPeriod {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Daily => "day",
+            Self::Monthly => "month",
+        }
+    }
+}
@@ -0,0 +1,541 @@
+pub mod cfg;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{network::cfg::interface_matches, notify::Notifier};
+
+use self::cfg::{BudgetPeriod, DataBudget, DataUsageConfig};
+
+const ALERT_GROUP_DATA_USAGE_PREFIX: &str = "panorama.data_usage.";
+
+const BYTES_PER_MB: u64 = 1_000_000;
+
+/// A local calendar date.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Date {
+    year: i32,
+    month: u32,
+    day: u32,
+}
+
+impl Date {
+    fn today() -> Self {
+        // SAFETY: localtime_r only writes to the provided struct.
+        let tm = unsafe {
+            let now = libc::time(std::ptr::null_mut());
+            let mut tm = std::mem::zeroed::<libc::tm>();
+            libc::localtime_r(&now, &mut tm);
+            tm
+        };
+        Self {
+            year: tm.tm_year + 1900,
+            month: tm.tm_mon as u32 + 1,
+            day: tm.tm_mday as u32,
+        }
+    }
+}
+
+/// Identifies the period of `budget` that contains `date`, eg "2024-03-15"
+/// for daily or "2024-03" for monthly budgets.
+fn period_key(budget: &DataBudget, date: Date) -> String {
+    match budget.period {
+        BudgetPeriod::Daily => format!("{:04}-{:02}-{:02}", date.year, date.month, date.day),
+        BudgetPeriod::Monthly => {
+            let (year, month) = if date.day >= budget.month_start_day as u32 {
+                (date.year, date.month)
+            } else if date.month == 1 {
+                (date.year - 1, 12)
+            } else {
+                (date.year, date.month - 1)
+            };
+            format!("{year:04}-{month:02}")
+        }
+    }
+}
+
+/// Raw interface counters.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+struct Counters {
+    rx_bytes: u64,
+    tx_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq, Debug)]
+struct BudgetUsage {
+    period: String,
+    rx_bytes: u64,
+    tx_bytes: u64,
+    /// Highest threshold already alerted in this period.
+    alerted_percent: u8,
+}
+
+impl BudgetUsage {
+    fn total(&self) -> u64 {
+        self.rx_bytes + self.tx_bytes
+    }
+}
+
+/// Counters of an interface instance.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+struct InterfaceCounters {
+    #[serde(flatten)]
+    counters: Counters,
+    /// Changes when the interface is recreated, eg by a modem reconnecting.
+    #[serde(default)]
+    ifindex: Option<u32>,
+}
+
+/// Unknown boot IDs and interface indexes, eg from older state files, are
+/// assumed to be unchanged.
+fn changed<T: PartialEq>(previous: &Option<T>, current: &Option<T>) -> bool {
+    previous.is_some() && current.is_some() && previous != current
+}
+
+/// Persisted state.
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct State {
+    /// Boot the counters were seen in, since they restart on reboot.
+    #[serde(default)]
+    boot_id: Option<String>,
+    /// Last seen counters per interface.
+    #[serde(default)]
+    counters: BTreeMap<String, InterfaceCounters>,
+    #[serde(default)]
+    budgets: BTreeMap<String, BudgetUsage>,
+}
+
+impl State {
+    fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => {
+                return Err(err).with_context(|| format!("could not read '{}'", path.display()))
+            }
+        };
+        serde_json::from_str(&content)
+            .with_context(|| format!("invalid state in '{}'", path.display()))
+    }
+
+    /// Write the state, replacing the file atomically.
+    fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("could not create '{}'", dir.display()))?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("could not write '{}'", tmp.display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("could not write '{}'", path.display()))?;
+        Ok(())
+    }
+}
+
+pub struct DataUsageManager {
+    config: DataUsageConfig,
+    notifier: Notifier,
+    sysfs_root: PathBuf,
+    boot_id_path: PathBuf,
+    state_path: PathBuf,
+    state: State,
+    initial_observed: bool,
+}
+
+impl DataUsageManager {
+    pub async fn start(config: DataUsageConfig, notifier: Notifier) -> Result<(), anyhow::Error> {
+        let manager = Self::new(config, notifier)?;
+        tokio::task::spawn_local(async move { manager.run().await })
+            .await
+            .context("DataUsageManager task failed")?
+            .context("DataUsageManager failed")?;
+
+        Ok(())
+    }
+
+    fn new(config: DataUsageConfig, notifier: Notifier) -> Result<Self, anyhow::Error> {
+        let config = config.validate()?;
+        let state_path = config.state_path();
+        let state = State::load(&state_path).unwrap_or_else(|err| {
+            tracing::warn!(
+                error = &*err,
+                "could not load data usage state - starting over"
+            );
+            State::default()
+        });
+        Ok(Self {
+            config,
+            notifier,
+            sysfs_root: PathBuf::from("/sys"),
+            boot_id_path: PathBuf::from("/proc/sys/kernel/random/boot_id"),
+            state_path,
+            state,
+            initial_observed: false,
+        })
+    }
+
+    async fn run(mut self) -> Result<(), anyhow::Error> {
+        let interval = Duration::from_secs(self.config.check_interval_seconds);
+        loop {
+            self.tick(Date::today()).await?;
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    async fn tick(&mut self, today: Date) -> Result<(), anyhow::Error> {
+        let initial = !self.initial_observed;
+        self.initial_observed = true;
+
+        let counters = self.read_counters()?;
+        let boot_id = std::fs::read_to_string(&self.boot_id_path)
+            .map(|id| id.trim().to_string())
+            .map_err(|err| {
+                tracing::debug!(
+                    error = &err as &dyn std::error::Error,
+                    "could not read boot ID"
+                );
+            })
+            .ok();
+        let deltas = self.update_counters(counters, boot_id);
+
+        let mut alerts = Vec::new();
+        for budget in &self.config.budgets {
+            let period = period_key(budget, today);
+            let usage = self.state.budgets.entry(budget.name.clone()).or_default();
+            if usage.period != period {
+                *usage = BudgetUsage {
+                    period,
+                    ..Default::default()
+                };
+            }
+            for (interface, delta) in &deltas {
+                if budget
+                    .interfaces
+                    .iter()
+                    .any(|pattern| interface_matches(pattern, interface))
+                {
+                    usage.rx_bytes += delta.rx_bytes;
+                    usage.tx_bytes += delta.tx_bytes;
+                }
+            }
+
+            let limit = budget.limit_mb * BYTES_PER_MB;
+            let percent = (usage.total() as u128 * 100 / limit as u128).min(u8::MAX as u128) as u8;
+            let crossed = budget
+                .thresholds_percent
+                .iter()
+                .copied()
+                .filter(|threshold| *threshold > usage.alerted_percent && percent >= *threshold)
+                .max();
+            if let Some(threshold) = crossed {
+                usage.alerted_percent = threshold;
+                alerts.push((
+                    budget.name.clone(),
+                    budget.period,
+                    budget.limit_mb,
+                    percent,
+                    usage.total(),
+                ));
+            }
+        }
+
+        if let Err(err) = self.state.save(&self.state_path) {
+            tracing::warn!(error = &*err, "could not persist data usage");
+        }
+
+        let Some(alert) = &self.config.alert_threshold else {
+            return Ok(());
+        };
+        for (budget, period, limit_mb, percent, used) in alerts {
+            tracing::info!(%budget, percent, "data budget threshold reached");
+            let variables = HashMap::from([
+                ("budget".to_string(), budget.clone()),
+                ("period".to_string(), period.name().to_string()),
+                ("percent".to_string(), percent.to_string()),
+                ("used_mb".to_string(), (used / BYTES_PER_MB).to_string()),
+                ("limit_mb".to_string(), limit_mb.to_string()),
+            ]);
+            let group = format!("{ALERT_GROUP_DATA_USAGE_PREFIX}{budget}");
+            let full = alert.prepare(group, variables).initial(initial);
+            self.notifier.notify(full).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Update the last seen counters, returning the traffic since the last
+    /// check per interface.
+    fn update_counters(
+        &mut self,
+        counters: Vec<(String, InterfaceCounters)>,
+        boot_id: Option<String>,
+    ) -> Vec<(String, Counters)> {
+        let rebooted = changed(&self.state.boot_id, &boot_id);
+        if boot_id.is_some() {
+            self.state.boot_id = boot_id;
+        }
+
+        let mut deltas = Vec::new();
+        for (interface, current) in counters {
+            let previous = self.state.counters.insert(interface.clone(), current);
+            // Interfaces seen for the first time only establish a baseline.
+            let Some(previous) = previous else {
+                continue;
+            };
+            // Counters restart from zero when the interface is recreated or
+            // the system rebooted. A reset is not always visible as a
+            // decrease, since the new counters may already be higher.
+            let reset = rebooted || changed(&previous.ifindex, &current.ifindex);
+            let delta = |current: u64, previous: u64| match reset {
+                true => current,
+                false => current.checked_sub(previous).unwrap_or(current),
+            };
+            let (current, previous) = (current.counters, previous.counters);
+            deltas.push((
+                interface,
+                Counters {
+                    rx_bytes: delta(current.rx_bytes, previous.rx_bytes),
+                    tx_bytes: delta(current.tx_bytes, previous.tx_bytes),
+                },
+            ));
+        }
+        deltas
+    }
+
+    /// Read the counters of all interfaces matched by any budget.
+    fn read_counters(&self) -> Result<Vec<(String, InterfaceCounters)>, anyhow::Error> {
+        let dir = self.sysfs_root.join("class/net");
+        let entries = std::fs::read_dir(&dir)
+            .with_context(|| format!("could not read '{}'", dir.display()))?;
+
+        let mut counters = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let interface = entry.file_name().to_string_lossy().to_string();
+            let tracked = self.config.budgets.iter().any(|budget| {
+                budget
+                    .interfaces
+                    .iter()
+                    .any(|pattern| interface_matches(pattern, &interface))
+            });
+            if !tracked {
+                continue;
+            }
+
+            let read = |path: PathBuf| -> Result<u64, anyhow::Error> {
+                let content = std::fs::read_to_string(&path)
+                    .with_context(|| format!("could not read '{}'", path.display()))?;
+                content
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("invalid counter in '{}'", path.display()))
+            };
+            let statistics = entry.path().join("statistics");
+            match (
+                read(statistics.join("rx_bytes")),
+                read(statistics.join("tx_bytes")),
+            ) {
+                (Ok(rx_bytes), Ok(tx_bytes)) => {
+                    let ifindex = read(entry.path().join("ifindex"))
+                        .ok()
+                        .and_then(|index| u32::try_from(index).ok());
+                    let current = InterfaceCounters {
+                        counters: Counters { rx_bytes, tx_bytes },
+                        ifindex,
+                    };
+                    counters.push((interface, current));
+                }
+                // The interface may have vanished in the meantime.
+                (Err(err), _) | (_, Err(err)) => {
+                    tracing::debug!(error = &*err, %interface, "could not read interface counters");
+                }
+            }
+        }
+        Ok(counters)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{notify::PreparedAlert, testutil::TempDir};
+
+    const DAY: Date = Date {
+        year: 2024,
+        month: 3,
+        day: 15,
+    };
+
+    fn budget(period: BudgetPeriod, limit_mb: u64) -> DataBudget {
+        DataBudget {
+            name: "lte".to_string(),
+            interfaces: vec!["wwan*".to_string()],
+            period,
+            limit_mb,
+            month_start_day: 1,
+            thresholds_percent: vec![80, 100],
+        }
+    }
+
+    fn manager(
+        dir: &TempDir,
+        budget: DataBudget,
+    ) -> (DataUsageManager, mpsc::Receiver<PreparedAlert>) {
+        let config = DataUsageConfig {
+            state_path: Some(dir.path().join("state/data_usage.json")),
+            budgets: vec![budget],
+            ..Default::default()
+        };
+        let (notifier, alerts) = Notifier::test_channel();
+        let mut manager = DataUsageManager::new(config, notifier).unwrap();
+        manager.sysfs_root = dir.path().to_path_buf();
+        manager.boot_id_path = dir.path().join("boot_id");
+        (manager, alerts)
+    }
+
+    fn set_counters(dir: &TempDir, interface: &str, rx_mb: u64, tx_mb: u64) {
+        let stats = format!("class/net/{interface}/statistics");
+        dir.write(
+            &format!("{stats}/rx_bytes"),
+            format!("{}\n", rx_mb * BYTES_PER_MB),
+        );
+        dir.write(
+            &format!("{stats}/tx_bytes"),
+            format!("{}\n", tx_mb * BYTES_PER_MB),
+        );
+    }
+
+    #[test]
+    fn test_period_key() {
+        let mut budget = budget(BudgetPeriod::Monthly, 1);
+        assert_eq!(period_key(&budget, DAY), "2024-03");
+        budget.month_start_day = 20;
+        assert_eq!(period_key(&budget, DAY), "2024-02");
+        let january = Date {
+            year: 2024,
+            month: 1,
+            day: 5,
+        };
+        assert_eq!(period_key(&budget, january), "2023-12");
+
+        budget.period = BudgetPeriod::Daily;
+        assert_eq!(period_key(&budget, january), "2024-01-05");
+    }
+
+    #[tokio::test]
+    async fn test_budget_thresholds() {
+        let dir = TempDir::new("data-usage-thresholds");
+        set_counters(&dir, "wwan0", 500, 100);
+        set_counters(&dir, "eth0", 0, 0);
+        let (mut manager, mut alerts) = manager(&dir, budget(BudgetPeriod::Monthly, 1000));
+
+        // Traffic before the first observation is not counted.
+        manager.tick(DAY).await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        set_counters(&dir, "wwan0", 1100, 300);
+        set_counters(&dir, "eth0", 5000, 5000);
+        manager.tick(DAY).await.unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(
+            alert.alert.summary,
+            "Data budget ${budget}: ${percent}% used"
+        );
+        assert_eq!(alert.group.as_deref(), Some("panorama.data_usage.lte"));
+        assert_eq!(alert.variables["percent"], "80");
+        assert_eq!(alert.variables["used_mb"], "800");
+        assert_eq!(alert.variables["limit_mb"], "1000");
+        assert_eq!(alert.variables["period"], "month");
+
+        // Each threshold alerts once.
+        set_counters(&dir, "wwan0", 1150, 300);
+        manager.tick(DAY).await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        // A reset counter, eg after reconnecting, counts from zero.
+        set_counters(&dir, "wwan0", 200, 0);
+        manager.tick(DAY).await.unwrap();
+        assert_eq!(alerts.try_recv().unwrap().variables["percent"], "105");
+
+        // A new period starts over.
+        set_counters(&dir, "wwan0", 300, 0);
+        let next_month = Date { month: 4, ..DAY };
+        manager.tick(next_month).await.unwrap();
+        assert!(alerts.try_recv().is_err());
+        assert_eq!(manager.state.budgets["lte"].total(), 100 * BYTES_PER_MB);
+    }
+
+    #[tokio::test]
+    async fn test_state_persisted() {
+        let dir = TempDir::new("data-usage-persisted");
+        set_counters(&dir, "wwan0", 0, 0);
+        let (mut first, _alerts) = manager(&dir, budget(BudgetPeriod::Daily, 100));
+        first.tick(DAY).await.unwrap();
+        set_counters(&dir, "wwan0", 60, 0);
+        first.tick(DAY).await.unwrap();
+        drop(first);
+
+        // Traffic while panorama was not running is counted after a restart.
+        set_counters(&dir, "wwan0", 90, 0);
+        let (mut manager, mut alerts) = manager(&dir, budget(BudgetPeriod::Daily, 100));
+        assert_eq!(manager.state.budgets["lte"].total(), 60 * BYTES_PER_MB);
+        manager.tick(DAY).await.unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.variables["percent"], "90");
+        assert!(alert.initial);
+    }
+
+    #[tokio::test]
+    async fn test_counter_resets() {
+        let dir = TempDir::new("data-usage-resets");
+        dir.write("boot_id", "3f1c\n");
+        dir.write("class/net/wwan0/ifindex", "5\n");
+        set_counters(&dir, "wwan0", 100, 0);
+        let (mut first, _alerts) = manager(&dir, budget(BudgetPeriod::Daily, 1000));
+        first.tick(DAY).await.unwrap();
+        drop(first);
+
+        // Rebooted with higher counters than before.
+        dir.write("boot_id", "9a2e\n");
+        set_counters(&dir, "wwan0", 150, 0);
+        let (mut manager, _alerts) = manager(&dir, budget(BudgetPeriod::Daily, 1000));
+        manager.tick(DAY).await.unwrap();
+        assert_eq!(manager.state.budgets["lte"].total(), 150 * BYTES_PER_MB);
+
+        // The interface was recreated.
+        dir.write("class/net/wwan0/ifindex", "6\n");
+        set_counters(&dir, "wwan0", 200, 0);
+        manager.tick(DAY).await.unwrap();
+        assert_eq!(manager.state.budgets["lte"].total(), 350 * BYTES_PER_MB);
+
+        set_counters(&dir, "wwan0", 250, 0);
+        manager.tick(DAY).await.unwrap();
+        assert_eq!(manager.state.budgets["lte"].total(), 400 * BYTES_PER_MB);
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = DataUsageConfig {
+            budgets: vec![DataBudget {
+                month_start_day: 31,
+                ..budget(BudgetPeriod::Monthly, 1)
+            }],
+            ..Default::default()
+        };
+        let err = config.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "data budget 'lte': 'month_start_day' must be between 1 and 28"
+        );
+    }
+}
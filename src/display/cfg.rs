@@ -0,0 +1,71 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::cfg::{Alert, AlertSeverity};
+
+/// Display hotplug notifications.
+///
+/// Available variables: ${connector} (eg "HDMI-A-1"), ${name} (the monitor
+/// model) and ${status} ("connected" or "disconnected").
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DisplayConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Command to run after connectors changed, eg. to switch the output
+    /// layout with `["autorandr", "--change"]`. It runs once for all changes
+    /// seen at the same time, and the variables substituted in the arguments
+    /// list each of them, separated by ", ". It is killed if it runs longer
+    /// than 30 seconds.
+    #[serde(default)]
+    pub command: Option<Vec<String>>,
+
+    #[serde(default = "DisplayConfig::default_alert_connected")]
+    pub alert_connected: Option<Alert>,
+    #[serde(default = "DisplayConfig::default_alert_disconnected")]
+    pub alert_disconnected: Option<Alert>,
+}
+
+impl DisplayConfig {
+    pub fn validate(self) -> Result<Self, anyhow::Error> {
+        if self.command.as_ref().is_some_and(|cmd| cmd.is_empty()) {
+            anyhow::bail!("'display.command' must not be empty");
+        }
+        Ok(self)
+    }
+
+    fn default_alert_connected() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Info,
+            on_startup: false,
+            repeat_after_seconds: None,
+            expire_after_seconds: Some(10),
+            summary: "Display connected: ${name}".to_string(),
+            message: Some("on ${connector}".to_string()),
+        })
+    }
+
+    fn default_alert_disconnected() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Info,
+            on_startup: false,
+            repeat_after_seconds: None,
+            expire_after_seconds: Some(10),
+            summary: "Display disconnected: ${name}".to_string(),
+            message: Some("on ${connector}".to_string()),
+        })
+    }
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            command: None,
+            alert_connected: Self::default_alert_connected(),
+            alert_disconnected: Self::default_alert_disconnected(),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
@@ -0,0 +1,140 @@
+//! Minimal EDID parser for the monitor identification.
+//! See https://en.wikipedia.org/wiki/Extended_Display_Identification_Data
+
+const HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+const BLOCK_SIZE: usize = 128;
+const DESCRIPTOR_OFFSETS: [usize; 4] = [54, 72, 90, 108];
+const DESCRIPTOR_SIZE: usize = 18;
+
+const TAG_SERIAL: u8 = 0xff;
+const TAG_TEXT: u8 = 0xfe;
+const TAG_NAME: u8 = 0xfc;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Edid {
+    /// Three letter PNP ID, eg "DEL".
+    pub manufacturer: String,
+    pub product_code: u16,
+    /// From the monitor name descriptor, which laptop panels usually lack.
+    pub name: Option<String>,
+    pub serial: Option<String>,
+    /// From the last unspecified text descriptor, where laptop panels put
+    /// the model after the vendor, eg "NV156FHM-N61" after "BOE CQ".
+    pub text: Option<String>,
+}
+
+impl Edid {
+    pub fn parse(data: &[u8]) -> Result<Self, anyhow::Error> {
+        let Some(block) = data.get(..BLOCK_SIZE) else {
+            anyhow::bail!("EDID too short: {} bytes", data.len());
+        };
+        if block[..8] != HEADER {
+            anyhow::bail!("invalid EDID header");
+        }
+        if block.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            anyhow::bail!("invalid EDID checksum");
+        }
+
+        let id = u16::from_be_bytes([block[8], block[9]]);
+        let manufacturer = [10, 5, 0]
+            .map(|shift| char::from(b'@' + ((id >> shift) & 0x1f) as u8))
+            .iter()
+            .collect();
+        let product_code = u16::from_le_bytes([block[10], block[11]]);
+
+        let mut name = None;
+        let mut serial = None;
+        let mut text = None;
+        for offset in DESCRIPTOR_OFFSETS {
+            let descriptor = &block[offset..offset + DESCRIPTOR_SIZE];
+            // Display descriptors have a zero pixel clock, unlike timings.
+            if descriptor[..2] != [0, 0] {
+                continue;
+            }
+            let target = match descriptor[3] {
+                TAG_NAME => &mut name,
+                TAG_SERIAL => &mut serial,
+                TAG_TEXT => &mut text,
+                _ => continue,
+            };
+            let value = descriptor_text(&descriptor[5..]);
+            if !value.is_empty() {
+                *target = Some(value);
+            }
+        }
+
+        Ok(Self {
+            manufacturer,
+            product_code,
+            name,
+            serial,
+            text,
+        })
+    }
+
+    /// The monitor name, or the panel text, or the manufacturer and product
+    /// code if neither is available.
+    pub fn display_name(&self) -> String {
+        match self.name.as_ref().or(self.text.as_ref()) {
+            Some(name) => name.clone(),
+            None => format!("{} {:04X}", self.manufacturer, self.product_code),
+        }
+    }
+}
+
+/// Descriptor text is terminated by a newline and padded with spaces.
+fn descriptor_text(data: &[u8]) -> String {
+    let end = data.iter().position(|b| *b == b'\n').unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_monitor() {
+        let edid = Edid::parse(include_bytes!("testdata/dell-u2415.bin")).unwrap();
+        assert_eq!(
+            edid,
+            Edid {
+                manufacturer: "DEL".to_string(),
+                product_code: 0xa0a4,
+                name: Some("DELL U2415".to_string()),
+                serial: Some("7MT0167B0T8L".to_string()),
+                text: None,
+            }
+        );
+        assert_eq!(edid.display_name(), "DELL U2415");
+    }
+
+    #[test]
+    fn test_parse_unnamed_panel() {
+        let edid = Edid::parse(include_bytes!("testdata/boe-nv156fhm.bin")).unwrap();
+        assert_eq!(edid.manufacturer, "BOE");
+        assert_eq!(edid.name, None);
+        assert_eq!(edid.serial, None);
+        assert_eq!(edid.text.as_deref(), Some("NV156FHM-N61"));
+        assert_eq!(edid.display_name(), "NV156FHM-N61");
+
+        // Without the text descriptors, the product code is used.
+        let edid = Edid { text: None, ..edid };
+        assert_eq!(edid.display_name(), "BOE 0791");
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        let err = Edid::parse(include_bytes!("testdata/corrupt.bin")).unwrap_err();
+        assert_eq!(err.to_string(), "invalid EDID checksum");
+
+        let err = Edid::parse(&[]).unwrap_err();
+        assert_eq!(err.to_string(), "EDID too short: 0 bytes");
+
+        let mut data = include_bytes!("testdata/dell-u2415.bin").to_vec();
+        data[0] = 0xff;
+        let err = Edid::parse(&data).unwrap_err();
+        assert_eq!(err.to_string(), "invalid EDID header");
+    }
+}
@@ -0,0 +1,438 @@
+//! Display hotplug notifications, based on udev `drm` events.
+
+pub mod cfg;
+mod edid;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use futures::{Stream, StreamExt};
+
+use crate::{
+    notify::{render, Notifier},
+    udev::{DeviceEvent, Lagged, Subscriber, UdevHub},
+};
+
+use self::{cfg::DisplayConfig, edid::Edid};
+
+const ALERT_GROUP_DISPLAY_PREFIX: &str = "panorama.display.";
+
+const SUBSYSTEM: &str = "drm";
+const SYSFS_DRM: &str = "/sys/class/drm";
+
+/// How long the command may run before it is killed, so that a hanging
+/// command does not hold up the commands of later changes forever.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Connector {
+    /// The connector without the card prefix, eg "HDMI-A-1".
+    connector: String,
+    connected: bool,
+    /// The monitor name from the EDID, or the connector if unavailable.
+    name: String,
+}
+
+/// Read the connectors of all cards, by sysfs name (eg "card0-HDMI-A-1").
+fn read_connectors(root: &Path) -> Result<BTreeMap<String, Connector>, anyhow::Error> {
+    let entries = std::fs::read_dir(root)
+        .with_context(|| format!("could not read directory '{}'", root.display()))?;
+
+    let mut connectors = BTreeMap::new();
+    for entry in entries {
+        let entry = entry?;
+        let dir_name = entry.file_name().to_string_lossy().to_string();
+        // Skips the cards themselves and render nodes.
+        let Some((card, connector)) = dir_name.split_once('-') else {
+            continue;
+        };
+        if !card.starts_with("card") {
+            continue;
+        }
+        let Ok(status) = std::fs::read_to_string(entry.path().join("status")) else {
+            continue;
+        };
+        let connected = status.trim() == "connected";
+        let name = if connected {
+            read_edid_name(&entry.path().join("edid")).unwrap_or_else(|err| {
+                tracing::debug!(error = &*err, connector, "could not read EDID");
+                connector.to_string()
+            })
+        } else {
+            connector.to_string()
+        };
+        connectors.insert(
+            dir_name.clone(),
+            Connector {
+                connector: connector.to_string(),
+                connected,
+                name,
+            },
+        );
+    }
+    Ok(connectors)
+}
+
+fn read_edid_name(path: &Path) -> Result<String, anyhow::Error> {
+    let data =
+        std::fs::read(path).with_context(|| format!("could not read '{}'", path.display()))?;
+    Ok(Edid::parse(&data)?.display_name())
+}
+
+pub struct DisplayManager {
+    config: DisplayConfig,
+    notifier: Notifier,
+    sysfs_root: PathBuf,
+    /// `None` until the first scan.
+    connectors: Option<BTreeMap<String, Connector>>,
+    /// The last run of the command, so runs don't overlap.
+    command_task: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl DisplayManager {
+    pub fn subscribe(hub: &UdevHub) -> Subscriber {
+        hub.subscribe(vec![(SUBSYSTEM.to_string(), None)], &[])
+    }
+
+    pub async fn start(
+        config: DisplayConfig,
+        notifier: Notifier,
+        events: Subscriber,
+    ) -> Result<(), anyhow::Error> {
+        let manager = Self::new(config, notifier)?;
+        tokio::task::spawn_local(async move { manager.run(events).await })
+            .await
+            .context("DisplayManager task failed")?
+            .context("DisplayManager failed")?;
+
+        Ok(())
+    }
+
+    fn new(config: DisplayConfig, notifier: Notifier) -> Result<Self, anyhow::Error> {
+        let config = config.validate()?;
+        Ok(Self {
+            config,
+            notifier,
+            sysfs_root: PathBuf::from(SYSFS_DRM),
+            connectors: None,
+            command_task: None,
+        })
+    }
+
+    async fn run(mut self, events: Subscriber) -> Result<(), anyhow::Error> {
+        self.scan().await?;
+        self.run_events(events.into_stream()).await
+    }
+
+    async fn run_events(
+        mut self,
+        mut events: impl Stream<Item = Result<DeviceEvent, anyhow::Error>> + Unpin,
+    ) -> Result<(), anyhow::Error> {
+        while let Some(event) = events.next().await {
+            // Hotplugs are reported as change events of the card, and MST
+            // connectors are added and removed. The event data is not needed,
+            // the connectors are re-read from /sys/, which also recovers
+            // from missed events.
+            match event {
+                Ok(event) => {
+                    tracing::trace!(syspath=%event.syspath, action=?event.action, "drm event");
+                }
+                Err(err) if err.is::<Lagged>() => {
+                    tracing::warn!(error = &*err, "rescanning display connectors");
+                }
+                Err(err) => return Err(err),
+            }
+            self.scan().await?;
+        }
+        anyhow::bail!("udev event stream ended")
+    }
+
+    async fn scan(&mut self) -> Result<(), anyhow::Error> {
+        let root = self.sysfs_root.clone();
+        let res = tokio::task::spawn_blocking(move || read_connectors(&root))
+            .await
+            .context("display connector task failed")?;
+        match res {
+            Ok(connectors) => self.update(connectors).await,
+            Err(err) => {
+                tracing::warn!(error = &*err, "could not read display connectors");
+                Ok(())
+            }
+        }
+    }
+
+    async fn update(
+        &mut self,
+        connectors: BTreeMap<String, Connector>,
+    ) -> Result<(), anyhow::Error> {
+        let initial = self.connectors.is_none();
+        let previous = self
+            .connectors
+            .replace(connectors.clone())
+            .unwrap_or_default();
+
+        let mut changes = Vec::new();
+        for (key, connector) in &connectors {
+            let was_connected = previous.get(key).is_some_and(|c| c.connected);
+            if connector.connected && !was_connected {
+                changes.push((key, connector, true));
+            } else if !connector.connected && was_connected {
+                // Keep the name of the monitor that was unplugged.
+                changes.push((key, &previous[key], false));
+            }
+        }
+        // Removed MST connectors.
+        for (key, connector) in &previous {
+            if connector.connected && !connectors.contains_key(key) {
+                changes.push((key, connector, false));
+            }
+        }
+
+        let mut command_variables = Vec::new();
+        for (key, connector, connected) in changes {
+            tracing::debug!(?connector, connected, "display connector changed");
+            let (alert, status) = if connected {
+                (&self.config.alert_connected, "connected")
+            } else {
+                (&self.config.alert_disconnected, "disconnected")
+            };
+            let variables = HashMap::from([
+                ("connector".to_string(), connector.connector.clone()),
+                ("name".to_string(), connector.name.clone()),
+                ("status".to_string(), status.to_string()),
+            ]);
+
+            if let Some(alert) = alert {
+                let group = format!("{ALERT_GROUP_DISPLAY_PREFIX}{key}");
+                let full = alert.prepare(group, variables.clone()).initial(initial);
+                self.notifier.notify(full).await?;
+            }
+            command_variables.push(variables);
+        }
+
+        // The layout is assumed to be set up already on startup.
+        if let (Some(command), false) = (&self.config.command, initial) {
+            if let Some(variables) = join_variables(command_variables) {
+                let command = command.clone();
+                let previous = self.command_task.take();
+                let task = tokio::task::spawn_local(async move {
+                    if let Some(previous) = previous {
+                        previous.await.ok();
+                    }
+                    run_command(&command, &variables).await;
+                });
+                self.command_task = Some(task);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Combine the variables of all changes of a scan, separated by ", ".
+fn join_variables(changes: Vec<HashMap<String, String>>) -> Option<HashMap<String, String>> {
+    let mut changes = changes.into_iter();
+    let mut joined = changes.next()?;
+    for variables in changes {
+        for (key, value) in variables {
+            let entry = joined.entry(key).or_default();
+            entry.push_str(", ");
+            entry.push_str(&value);
+        }
+    }
+    Some(joined)
+}
+
+/// Run the configured command, which only logs failures and kills it after
+/// [`COMMAND_TIMEOUT`].
+async fn run_command(command: &[String], variables: &HashMap<String, String>) {
+    let args = command
+        .iter()
+        .map(|arg| render(arg, variables))
+        .collect::<Vec<_>>();
+    let Some((program, args)) = args.split_first() else {
+        return;
+    };
+    tracing::debug!(program, ?args, "running display command");
+    let output = tokio::process::Command::new(program)
+        .args(args)
+        .kill_on_drop(true)
+        .output();
+    match tokio::time::timeout(COMMAND_TIMEOUT, output).await {
+        Err(_) => tracing::warn!(program, ?COMMAND_TIMEOUT, "display command timed out"),
+        Ok(Ok(output)) if output.status.success() => {}
+        Ok(Ok(output)) => tracing::warn!(
+            program,
+            status=%output.status,
+            stderr=%String::from_utf8_lossy(&output.stderr).trim(),
+            "display command failed"
+        ),
+        Ok(Err(err)) => tracing::warn!(
+            program,
+            error = &err as &dyn std::error::Error,
+            "could not run display command"
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::{notify::PreparedAlert, testutil::TempDir};
+
+    fn plug(dir: &TempDir, connector: &str, edid: Option<&[u8]>) {
+        let status = if edid.is_some() {
+            "connected"
+        } else {
+            "disconnected"
+        };
+        dir.write(&format!("{connector}/status"), format!("{status}\n"));
+        dir.write(&format!("{connector}/edid"), edid.unwrap_or_default());
+    }
+
+    fn manager(
+        dir: &TempDir,
+        command: Option<Vec<String>>,
+    ) -> (DisplayManager, tokio::sync::mpsc::Receiver<PreparedAlert>) {
+        let (notifier, alerts) = Notifier::test_channel();
+        let config = DisplayConfig {
+            command,
+            ..Default::default()
+        };
+        let mut manager = DisplayManager::new(config, notifier).unwrap();
+        manager.sysfs_root = dir.path().to_path_buf();
+        (manager, alerts)
+    }
+
+    #[test]
+    fn test_read_connectors() {
+        let dir = TempDir::new("display");
+        dir.write("card0/dev", "226:0\n");
+        dir.write("renderD128/dev", "226:128\n");
+        dir.write("version", "drm 1.1.0\n");
+        plug(
+            &dir,
+            "card0-eDP-1",
+            Some(include_bytes!("testdata/boe-nv156fhm.bin")),
+        );
+        plug(&dir, "card0-HDMI-A-1", None);
+        plug(&dir, "card0-DP-1", Some(b"garbage"));
+
+        let connectors = read_connectors(dir.path()).unwrap();
+        let summary = connectors
+            .iter()
+            .map(|(key, c)| {
+                (
+                    key.as_str(),
+                    c.connector.as_str(),
+                    c.connected,
+                    c.name.as_str(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("card0-DP-1", "DP-1", true, "DP-1"),
+                ("card0-HDMI-A-1", "HDMI-A-1", false, "HDMI-A-1"),
+                ("card0-eDP-1", "eDP-1", true, "NV156FHM-N61"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hotplug() {
+        tokio::task::LocalSet::new().run_until(hotplug()).await;
+    }
+
+    async fn hotplug() {
+        let dir = TempDir::new("display");
+        plug(
+            &dir,
+            "card0-eDP-1",
+            Some(include_bytes!("testdata/boe-nv156fhm.bin")),
+        );
+        plug(&dir, "card0-HDMI-A-1", None);
+        let log = dir.path().join("command.log");
+        let command = ["sh", "-c", "echo ${connector} ${status} ${name} >> \"$0\""]
+            .map(String::from)
+            .into_iter()
+            .chain([log.display().to_string()])
+            .collect();
+        let (mut manager, mut alerts) = manager(&dir, Some(command));
+
+        // Displays connected on startup are not announced by default.
+        manager.scan().await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        plug(
+            &dir,
+            "card0-HDMI-A-1",
+            Some(include_bytes!("testdata/dell-u2415.bin")),
+        );
+        manager.scan().await.unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.alert.summary, "Display connected: ${name}");
+        assert_eq!(alert.variables["name"], "DELL U2415");
+        assert_eq!(alert.variables["connector"], "HDMI-A-1");
+
+        // Unrelated events.
+        manager.scan().await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        plug(&dir, "card0-HDMI-A-1", None);
+        manager.scan().await.unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.alert.summary, "Display disconnected: ${name}");
+        assert_eq!(alert.variables["name"], "DELL U2415");
+
+        // Docking stations connect several displays at once.
+        plug(
+            &dir,
+            "card0-HDMI-A-1",
+            Some(include_bytes!("testdata/dell-u2415.bin")),
+        );
+        plug(
+            &dir,
+            "card0-DP-2",
+            Some(include_bytes!("testdata/dell-u2415.bin")),
+        );
+        manager.scan().await.unwrap();
+        manager.command_task.take().unwrap().await.unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&log).unwrap(),
+            "HDMI-A-1 connected DELL U2415\n\
+             HDMI-A-1 disconnected DELL U2415\n\
+             DP-2, HDMI-A-1 connected, connected DELL U2415, DELL U2415\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mst_connector_removed() {
+        let dir = TempDir::new("display");
+        let (mut manager, mut alerts) = manager(&dir, None);
+        manager.scan().await.unwrap();
+
+        plug(
+            &dir,
+            "card1-DP-5",
+            Some(include_bytes!("testdata/dell-u2415.bin")),
+        );
+        manager.scan().await.unwrap();
+        assert_eq!(alerts.try_recv().unwrap().variables["connector"], "DP-5");
+
+        std::fs::remove_dir_all(dir.path().join("card1-DP-5")).unwrap();
+        manager.scan().await.unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.alert.summary, "Display disconnected: ${name}");
+        assert_eq!(alert.variables["connector"], "DP-5");
+        assert_eq!(alert.variables["status"], "disconnected");
+    }
+}
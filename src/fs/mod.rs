@@ -12,6 +12,8 @@ pub struct FsManager {
     config: FsConfig,
     /// Map recording whether a warning for a given disk was already sent.
     disk_full_warned: HashMap<String, bool>,
+    /// Whether the first check after startup has completed.
+    initial_observed: bool,
     notifier: Notifier,
 }
 
@@ -30,6 +32,7 @@ impl FsManager {
         Ok(Self {
             config,
             disk_full_warned: HashMap::new(),
+            initial_observed: false,
             notifier,
         })
     }
@@ -46,9 +49,12 @@ impl FsManager {
 
             if let Some(full) = self.config.disk_full_warning.clone() {
                 for mount in mounts {
-                    self.handle_mount(mount, &full).await?;
+                    // FIXME: determine actual usage!
+                    let usage = 0;
+                    self.handle_mount(mount, usage, &full).await?;
                 }
             }
+            self.initial_observed = true;
 
             tokio::time::sleep(interval).await;
         }
@@ -57,6 +63,7 @@ impl FsManager {
     async fn handle_mount(
         &mut self,
         mount: Mount,
+        usage: u8,
         cfg: &DiskUsageAlert,
     ) -> Result<(), anyhow::Error> {
         if let Some(paths) = &cfg.device_path_exclude {
@@ -76,8 +83,6 @@ impl FsManager {
             }
         }
 
-        // FIXME: determine actual usage!
-        let usage = 0;
         if usage < cfg.usage_percent_limit {
             return Ok(());
         }
@@ -101,22 +106,26 @@ impl FsManager {
             vars
         };
         let group = format!("fs-{}", mount.device);
-        let alert = cfg.alert.prepare(group, variables);
+        let alert = cfg
+            .alert
+            .prepare(group, variables)
+            .initial(!self.initial_observed);
         self.notifier.notify(alert).await?;
+        self.disk_full_warned.insert(mount.device, true);
 
         Ok(())
     }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
-struct Mount {
-    device: String,
-    mountpoint: String,
-    fstype: String,
-    options: Vec<String>,
+pub struct Mount {
+    pub device: String,
+    pub mountpoint: String,
+    pub fstype: String,
+    pub options: Vec<String>,
 }
 
-fn load_proc_mounts() -> Result<Vec<Mount>, anyhow::Error> {
+pub fn load_proc_mounts() -> Result<Vec<Mount>, anyhow::Error> {
     let input =
         std::fs::read_to_string("/proc/mounts").with_context(|| "could not read /proc/mounts")?;
 
@@ -138,13 +147,13 @@ fn parse_proc_mount_line(line: &str) -> Result<Mount, anyhow::Error> {
 
     let device = parts
         .next()
-        .with_context(|| format!("could not read device from line '{line}'"))?
-        .to_string();
+        .with_context(|| format!("could not read device from line '{line}'"))
+        .map(unescape)?;
 
     let mountpoint = parts
         .next()
-        .with_context(|| format!("could not read mountpoint from line '{line}'"))?
-        .to_string();
+        .with_context(|| format!("could not read mountpoint from line '{line}'"))
+        .map(unescape)?;
 
     let fstype = parts
         .next()
@@ -166,12 +175,89 @@ fn parse_proc_mount_line(line: &str) -> Result<Mount, anyhow::Error> {
     })
 }
 
+/// Decode the octal escapes of whitespace and backslashes in /proc/mounts
+/// fields, eg "\\040" for a space.
+fn unescape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(pos) = rest.find('\\') {
+        out.push_str(&rest[..pos]);
+        let escape = rest.get(pos + 1..pos + 4);
+        match escape.and_then(|digits| u8::from_str_radix(digits, 8).ok()) {
+            Some(byte) => {
+                out.push(char::from(byte));
+                rest = &rest[pos + 4..];
+            }
+            None => {
+                out.push('\\');
+                rest = &rest[pos + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
 #[cfg(test)]
 mod tests {
     use pretty_assertions::assert_eq;
 
     use super::*;
 
+    fn mount(device: &str) -> Mount {
+        Mount {
+            device: device.to_string(),
+            mountpoint: "/".to_string(),
+            fstype: "ext4".to_string(),
+            options: vec!["rw".to_string()],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_disk_full_on_startup() {
+        let config = FsConfig::default();
+        let mut cfg = config.disk_full_warning.clone().unwrap();
+
+        cfg.alert.on_startup = false;
+        let (notifier, mut alerts) = Notifier::test_channel();
+        let mut manager = FsManager::new(config.clone(), notifier).unwrap();
+        manager
+            .handle_mount(mount("/dev/sda1"), 99, &cfg)
+            .await
+            .unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        // Disks filling up after startup always alert.
+        manager.initial_observed = true;
+        manager
+            .handle_mount(mount("/dev/sda2"), 20, &cfg)
+            .await
+            .unwrap();
+        manager
+            .handle_mount(mount("/dev/sda2"), 97, &cfg)
+            .await
+            .unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.variables["device"], "/dev/sda2");
+
+        cfg.alert.on_startup = true;
+        let (notifier, mut alerts) = Notifier::test_channel();
+        let mut manager = FsManager::new(config, notifier).unwrap();
+        manager
+            .handle_mount(mount("/dev/sda1"), 99, &cfg)
+            .await
+            .unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert!(alert.initial);
+
+        // Only warned once.
+        manager
+            .handle_mount(mount("/dev/sda1"), 99, &cfg)
+            .await
+            .unwrap();
+        assert!(alerts.try_recv().is_err());
+    }
+
     #[test]
     fn test_parse_proc_mounts() {
         let input = r#"
@@ -259,4 +345,13 @@ ramfs /run/keys ramfs rw,nosuid,nodev,relatime,mode=750 0 0
             ]
         );
     }
+
+    #[test]
+    fn test_parse_escaped_mountpoint() {
+        let mount =
+            parse_proc_mount_line(r"/dev/sdb1 /run/media/me/MY\040STICK vfat rw,nosuid 0 0")
+                .unwrap();
+        assert_eq!(mount.mountpoint, "/run/media/me/MY STICK");
+        assert_eq!(unescape(r"a\134b\\x"), r"a\b\\x");
+    }
 }
@@ -1,4 +1,7 @@
-use std::net::IpAddr;
+use std::{
+    collections::{BTreeMap, HashSet},
+    net::{IpAddr, SocketAddr},
+};
 
 use serde_derive::{Deserialize, Serialize};
 
@@ -9,16 +12,43 @@ pub struct OnlineConfig {
     #[serde(default = "default_true")]
     pub enabled: bool,
 
+    /// DNS servers that are queried for `query_domain` on every check.
+    /// Defaults to the system resolvers, since many networks block queries to
+    /// public resolvers. These can be used with `!Custom [1.1.1.1, 8.8.8.8]`.
     #[serde(default = "OnlineConfig::default_dns_servers")]
     pub dns_servers: DnsServerSource,
 
-    /// HTTP URLs to check for internet connectivity.
+    /// Timeout for the DNS query to each server.
+    #[serde(default = "OnlineConfig::default_dns_timeout_secs")]
+    pub dns_timeout_secs: u64,
+
+    /// HTTP URLs to check.
+    /// `connectivity` checks determine whether the system is online, while
+    /// `service` checks alert individually when the service goes down.
     #[serde(default = "OnlineConfig::default_urls")]
     pub urls: Vec<CheckUrl>,
 
     #[serde(default = "OnlineConfig::default_http_timeout_secs")]
     pub http_timeout_secs: u64,
 
+    /// Proxy for the URL checks: "direct", "env" to honor `HTTP_PROXY`,
+    /// `HTTPS_PROXY`, `ALL_PROXY` and `NO_PROXY`, or a proxy URL like
+    /// "http://proxy:3128" or "socks5://proxy:1080".
+    /// Can be overridden per check.
+    #[serde(default)]
+    pub proxy: ProxySetting,
+
+    /// TCP connect and ICMP echo checks, used like `urls`.
+    #[serde(default)]
+    pub probes: Vec<Probe>,
+
+    /// Upper bound for each phase of a check run, including retries: the
+    /// connectivity diagnosis, the service checks and the address family
+    /// checks each get this much time. Checks still running at the deadline
+    /// are considered failed.
+    #[serde(default = "OnlineConfig::default_check_deadline_secs")]
+    pub check_deadline_secs: u64,
+
     #[serde(default = "OnlineConfig::default_query_domain")]
     pub query_domain: String,
 
@@ -28,49 +58,166 @@ pub struct OnlineConfig {
     #[serde(default = "OnlineConfig::default_check_interval_seconds_offline")]
     pub check_interval_seconds_offline: u64,
 
-    /// How often to retry failed checks before system is considered offline.
-    /// Also check retry_delay_seconds.
-    #[serde(default = "OnlineConfig::default_retry_count")]
-    pub retry_count: usize,
-    #[serde(default = "OnlineConfig::default_retry_interval_seconds")]
-    pub retry_interval_seconds: u64,
+    /// How failed DNS and HTTP probes are retried before the system is
+    /// considered offline.
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// Deprecated: replaced by `retry.attempts_per_target` and
+    /// `retry.max_total_attempts`.
+    #[serde(default, skip_serializing)]
+    pub retry_count: Option<u32>,
+    /// Deprecated: replaced by `retry.backoff_initial_ms`.
+    #[serde(default, skip_serializing)]
+    pub retry_interval_seconds: Option<u64>,
 
+    /// Check for a default route and a reachable gateway before running the
+    /// DNS and HTTP checks, to tell apart why the system is offline.
+    #[serde(default = "default_true")]
+    pub route_check: bool,
+
+    /// Used to detect captive portals (eg hotel Wi-Fi logins) when the other
+    /// checks fail.
+    #[serde(default = "OnlineConfig::default_captive_portal_check")]
+    pub captive_portal_check: Option<CaptivePortalCheck>,
+
+    /// Available variables: ${families_up} if `address_families` is enabled.
     pub alert_reconnected: Option<Alert>,
+    /// Sent when going offline, unless `state_alerts` has a more specific
+    /// alert.
+    /// Available variables: ${reason}
     pub alert_disconnected: Option<Alert>,
+    #[serde(default)]
+    pub state_alerts: StateAlerts,
+    /// Alerts for `service` URL checks.
+    #[serde(default)]
+    pub service_alerts: ServiceAlerts,
+    /// Alerts for proxies of URL checks with `also_direct` enabled.
+    #[serde(default)]
+    pub proxy_alerts: ProxyAlerts,
+    /// Alerts for high latency or packet loss while online, measured by the
+    /// connectivity `probes`.
+    #[serde(default)]
+    pub degradation: DegradationConfig,
+    /// Separate IPv4 and IPv6 connectivity tracking.
+    #[serde(default)]
+    pub address_families: AddressFamilyChecks,
 }
 
 impl OnlineConfig {
-    pub fn validate(self) -> Result<Self, anyhow::Error> {
+    pub fn validate(mut self) -> Result<Self, anyhow::Error> {
+        self.migrate_retry();
         match &self.dns_servers {
-            DnsServerSource::System => {}
+            DnsServerSource::Disabled | DnsServerSource::System => {}
             DnsServerSource::Custom(servers) => {
                 if servers.is_empty() {
                     anyhow::bail!("'online.dns_servers' must specify at least one server");
                 }
             }
         };
+        if self.check_deadline_secs == 0 {
+            anyhow::bail!("'online.check_deadline_secs' must be greater than 0");
+        }
+        self.retry.validate()?;
+        let mut services = HashSet::new();
+        for check in &self.urls {
+            check.validate()?;
+            if check.kind == CheckKind::Service && !services.insert(check.name().to_string()) {
+                anyhow::bail!("service check '{}' is defined multiple times", check.name());
+            }
+        }
+        self.degradation.validate()?;
+        for probe in &self.probes {
+            if probe.timeout_ms == 0 {
+                anyhow::bail!(
+                    "probe '{}': 'timeout_ms' must be greater than 0",
+                    probe.name()
+                );
+            }
+            if probe.kind == CheckKind::Service && !services.insert(probe.name()) {
+                anyhow::bail!("service check '{}' is defined multiple times", probe.name());
+            }
+        }
+        if self.dns_timeout_secs == 0 {
+            anyhow::bail!("'online.dns_timeout_secs' must be greater than 0");
+        }
+        if self.address_families.enabled {
+            for family in &self.address_families.families {
+                let has_check = self
+                    .urls
+                    .iter()
+                    .map(|c| (c.kind, c.family))
+                    .chain(self.probes.iter().map(|p| (p.kind, p.family)))
+                    .any(|(kind, f)| {
+                        kind == CheckKind::Connectivity && f.is_none_or(|f| f == *family)
+                    });
+                if !has_check {
+                    anyhow::bail!(
+                        "'online.address_families': no connectivity check can use {family}"
+                    );
+                }
+            }
+        }
         Ok(self)
     }
 
+    /// Map the retry options of older versions onto the retry policy.
+    fn migrate_retry(&mut self) {
+        if let Some(count) = self.retry_count.take() {
+            tracing::warn!(
+                "'online.retry_count' is deprecated - use 'online.retry.attempts_per_target' and 'online.retry.max_total_attempts'"
+            );
+            // The retries were shared by all checks of a run.
+            self.retry.attempts_per_target = count + 1;
+            self.retry.max_total_attempts = count + 1;
+        }
+        if let Some(seconds) = self.retry_interval_seconds.take() {
+            tracing::warn!(
+                "'online.retry_interval_seconds' is deprecated - use 'online.retry.backoff_initial_ms'"
+            );
+            // Retries used a fixed delay.
+            self.retry.backoff_initial_ms = seconds * 1000;
+            self.retry.backoff_multiplier = 1;
+            self.retry.backoff_max_ms = seconds * 1000;
+        }
+    }
+
     fn default_http_timeout_secs() -> u64 {
         20
     }
 
+    fn default_check_deadline_secs() -> u64 {
+        60
+    }
+
     fn default_urls() -> Vec<CheckUrl> {
         vec![
             CheckUrl {
-                url: "https://wikipedia.org".parse().unwrap(),
                 body_contains: Some("Wikimedia Foundation".to_string()),
+                ..CheckUrl::new("https://wikipedia.org".parse().unwrap())
             },
             CheckUrl {
-                url: "https://news.ycombinator.com".parse().unwrap(),
                 body_contains: Some("Hacker News".to_string()),
+                ..CheckUrl::new("https://news.ycombinator.com".parse().unwrap())
             },
         ]
     }
 
     fn default_dns_servers() -> DnsServerSource {
-        DnsServerSource::Custom(vec!["1.1.1.1".parse().unwrap(), "8.8.8.8".parse().unwrap()])
+        DnsServerSource::System
+    }
+
+    fn default_captive_portal_check() -> Option<CaptivePortalCheck> {
+        Some(CaptivePortalCheck {
+            url: "http://connectivitycheck.gstatic.com/generate_204"
+                .parse()
+                .unwrap(),
+            expected_status: 204,
+            body_contains: None,
+        })
+    }
+
+    fn default_dns_timeout_secs() -> u64 {
+        5
     }
 
     fn default_query_domain() -> String {
@@ -84,14 +231,6 @@ impl OnlineConfig {
     fn default_check_interval_seconds_offline() -> u64 {
         3
     }
-
-    fn default_retry_count() -> usize {
-        2
-    }
-
-    fn default_retry_interval_seconds() -> u64 {
-        5
-    }
 }
 
 impl Default for OnlineConfig {
@@ -99,12 +238,19 @@ impl Default for OnlineConfig {
         Self {
             enabled: true,
             http_timeout_secs: Self::default_http_timeout_secs(),
+            proxy: ProxySetting::default(),
+            probes: Vec::new(),
+            check_deadline_secs: Self::default_check_deadline_secs(),
             query_domain: Self::default_query_domain(),
             dns_servers: Self::default_dns_servers(),
+            dns_timeout_secs: Self::default_dns_timeout_secs(),
             check_interval_seconds_online: Self::default_check_interval_seconds_online(),
             check_interval_seconds_offline: Self::default_check_interval_seconds_offline(),
-            retry_count: Self::default_retry_count(),
-            retry_interval_seconds: Self::default_retry_interval_seconds(),
+            retry: RetryPolicy::default(),
+            retry_count: None,
+            retry_interval_seconds: None,
+            route_check: true,
+            captive_portal_check: Self::default_captive_portal_check(),
             alert_reconnected: Some(Alert {
                 severity: AlertSeverity::Info,
                 on_startup: false,
@@ -115,28 +261,759 @@ impl Default for OnlineConfig {
             }),
             alert_disconnected: Some(Alert {
                 severity: AlertSeverity::Critical,
-                on_startup: false,
+                on_startup: true,
                 repeat_after_seconds: None,
                 expire_after_seconds: None,
                 summary: "Internet is unreachable - system appears to be offline!".to_string(),
-                message: None,
+                message: Some("${reason}".to_string()),
             }),
+            state_alerts: StateAlerts::default(),
+            service_alerts: ServiceAlerts::default(),
+            proxy_alerts: ProxyAlerts::default(),
+            degradation: DegradationConfig::default(),
+            address_families: AddressFamilyChecks::default(),
             urls: Self::default_urls(),
         }
     }
 }
 
+/// HTTP request whose response must match the given expectations.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct CheckUrl {
     pub url: url::Url,
+    /// Name used in service alerts. Defaults to the URL.
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub kind: CheckKind,
+    #[serde(default)]
+    pub method: HttpMethod,
+    /// Request body to send.
+    #[serde(default)]
+    pub body: Option<String>,
+    /// Accepted response status codes.
+    /// Any status below 400 is accepted if empty.
+    #[serde(default)]
+    pub expected_status: Vec<u16>,
+    #[serde(default)]
+    pub body_contains: Option<String>,
+    #[serde(default)]
+    pub body_regex: Option<Pattern>,
+    /// Response headers that must be present, with a regex their value must
+    /// match.
+    #[serde(default)]
+    pub headers: BTreeMap<String, Pattern>,
+    /// Fail the check if the response takes longer than this.
+    #[serde(default)]
+    pub max_latency_ms: Option<u64>,
+    /// Only connect over this address family.
+    #[serde(default)]
+    pub family: Option<AddressFamily>,
+    /// Overrides `online.proxy` for this check.
+    #[serde(default)]
+    pub proxy: Option<ProxySetting>,
+    /// Repeat a failed proxied request without the proxy. If only the direct
+    /// request succeeds, the check passes but the proxy is reported as down.
+    #[serde(default)]
+    pub also_direct: bool,
+}
+
+impl CheckUrl {
+    pub fn new(url: url::Url) -> Self {
+        Self {
+            url,
+            name: None,
+            kind: CheckKind::default(),
+            method: HttpMethod::default(),
+            body: None,
+            expected_status: Vec::new(),
+            body_contains: None,
+            body_regex: None,
+            headers: BTreeMap::new(),
+            max_latency_ms: None,
+            family: None,
+            proxy: None,
+            also_direct: false,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(self.url.as_str())
+    }
+
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        if let Some(status) = self
+            .expected_status
+            .iter()
+            .find(|s| !(100..600).contains(*s))
+        {
+            anyhow::bail!(
+                "check '{}' has invalid expected status code {status}",
+                self.name()
+            );
+        }
+        if self.max_latency_ms == Some(0) {
+            anyhow::bail!(
+                "check '{}': 'max_latency_ms' must be greater than 0",
+                self.name()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Lightweight reachability check of a host.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Probe {
+    /// Name used in service alerts. Defaults to the target.
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub kind: CheckKind,
+    #[serde(default = "Probe::default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Only connect over this address family.
+    #[serde(default)]
+    pub family: Option<AddressFamily>,
+    #[serde(flatten)]
+    pub target: ProbeTarget,
+}
+
+impl Probe {
+    pub fn new(target: ProbeTarget) -> Self {
+        Self {
+            name: None,
+            kind: CheckKind::default(),
+            timeout_ms: Self::default_timeout_ms(),
+            family: None,
+            target,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.target.to_string())
+    }
+
+    fn default_timeout_ms() -> u64 {
+        3000
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProbeTarget {
+    /// Connect to a TCP port.
+    Tcp { host: String, port: u16 },
+    /// Send an ICMP echo request.
+    /// Requires unprivileged ping sockets, which are only available to groups
+    /// in the `net.ipv4.ping_group_range` sysctl.
+    Icmp { host: String },
+}
+
+impl std::fmt::Display for ProbeTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp { host, port } => write!(f, "tcp://{host}:{port}"),
+            Self::Icmp { host } => write!(f, "icmp://{host}"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckKind {
+    /// Used to determine if the system is online.
+    #[default]
+    Connectivity,
+    /// A single service that is monitored independently.
+    Service,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum HttpMethod {
+    #[default]
+    Get,
+    Head,
+    Post,
+    Put,
+    Patch,
+    Delete,
+    Options,
+}
+
+impl HttpMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Get => "GET",
+            Self::Head => "HEAD",
+            Self::Post => "POST",
+            Self::Put => "PUT",
+            Self::Patch => "PATCH",
+            Self::Delete => "DELETE",
+            Self::Options => "OPTIONS",
+        }
+    }
+}
+
+/// A regular expression.
+#[derive(Clone, Debug)]
+pub struct Pattern(pub regex::Regex);
+
+impl std::str::FromStr for Pattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        regex::Regex::new(s)
+            .map(Self)
+            .map_err(|err| anyhow::anyhow!("invalid regex '{s}': {err}"))
+    }
+}
+
+impl serde::Serialize for Pattern {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Pattern {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Alerts for `service` URL checks, grouped per service.
+///
+/// Available variables: ${service}, ${target}, ${url} for URL checks, plus
+/// ${error} for `down`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ServiceAlerts {
+    #[serde(default = "ServiceAlerts::default_down")]
+    pub down: Option<Alert>,
+    #[serde(default = "ServiceAlerts::default_up")]
+    pub up: Option<Alert>,
+}
+
+impl ServiceAlerts {
+    fn default_down() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Warning,
+            on_startup: true,
+            repeat_after_seconds: None,
+            expire_after_seconds: None,
+            summary: "Service ${service} is down".to_string(),
+            message: Some("${error}".to_string()),
+        })
+    }
+
+    fn default_up() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Info,
+            on_startup: false,
+            repeat_after_seconds: None,
+            expire_after_seconds: Some(10),
+            summary: "Service ${service} is back up".to_string(),
+            message: None,
+        })
+    }
+}
+
+impl Default for ServiceAlerts {
+    fn default() -> Self {
+        Self {
+            down: Self::default_down(),
+            up: Self::default_up(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AddressFamily {
+    Ipv4,
+    Ipv6,
+}
+
+impl AddressFamily {
+    pub fn matches(&self, ip: &IpAddr) -> bool {
+        match self {
+            Self::Ipv4 => ip.is_ipv4(),
+            Self::Ipv6 => ip.is_ipv6(),
+        }
+    }
+}
+
+impl std::fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ipv4 => write!(f, "IPv4"),
+            Self::Ipv6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+/// Connectivity tracking per address family.
+///
+/// While online, the connectivity checks are repeated for each family, with
+/// the family forced for every check. This detects eg broken IPv6 that
+/// otherwise only shows up as long stalls. The check targets must resolve to
+/// addresses of every family.
+///
+/// Available variables: ${family}, ${families_up} (eg "IPv4, IPv6" or
+/// "none"), plus ${error} for `alert_lost`.
+/// When enabled, ${families_up} is also available in the connectivity alerts.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AddressFamilyChecks {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "AddressFamilyChecks::default_families")]
+    pub families: Vec<AddressFamily>,
+    #[serde(default = "AddressFamilyChecks::default_alert_lost")]
+    pub alert_lost: Option<Alert>,
+    #[serde(default = "AddressFamilyChecks::default_alert_restored")]
+    pub alert_restored: Option<Alert>,
+}
+
+impl AddressFamilyChecks {
+    fn default_families() -> Vec<AddressFamily> {
+        vec![AddressFamily::Ipv4, AddressFamily::Ipv6]
+    }
+
+    fn default_alert_lost() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Warning,
+            on_startup: true,
+            repeat_after_seconds: None,
+            expire_after_seconds: None,
+            summary: "${family} connectivity lost".to_string(),
+            message: Some("Working: ${families_up}\n${error}".to_string()),
+        })
+    }
+
+    fn default_alert_restored() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Info,
+            on_startup: false,
+            repeat_after_seconds: None,
+            expire_after_seconds: Some(10),
+            summary: "${family} connectivity restored".to_string(),
+            message: Some("Working: ${families_up}".to_string()),
+        })
+    }
+}
+
+impl Default for AddressFamilyChecks {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            families: Self::default_families(),
+            alert_lost: Self::default_alert_lost(),
+            alert_restored: Self::default_alert_restored(),
+        }
+    }
+}
+
+/// Alerts for proxies, grouped per proxy.
+///
+/// Available variables: ${proxy} (without credentials), plus ${error} for
+/// `down`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ProxyAlerts {
+    #[serde(default = "ProxyAlerts::default_down")]
+    pub down: Option<Alert>,
+    #[serde(default = "ProxyAlerts::default_up")]
+    pub up: Option<Alert>,
+}
+
+impl ProxyAlerts {
+    fn default_down() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Warning,
+            on_startup: true,
+            repeat_after_seconds: None,
+            expire_after_seconds: None,
+            summary: "Proxy ${proxy} is down".to_string(),
+            message: Some("${error}".to_string()),
+        })
+    }
+
+    fn default_up() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Info,
+            on_startup: false,
+            repeat_after_seconds: None,
+            expire_after_seconds: Some(10),
+            summary: "Proxy ${proxy} is back up".to_string(),
+            message: None,
+        })
+    }
+}
+
+impl Default for ProxyAlerts {
+    fn default() -> Self {
+        Self {
+            down: Self::default_down(),
+            up: Self::default_up(),
+        }
+    }
+}
+
+/// How HTTP checks are routed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ProxySetting {
+    Direct,
+    /// Use the proxy from the environment, if any.
+    #[default]
+    Env,
+    Url(String),
+}
+
+impl std::str::FromStr for ProxySetting {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "direct" => Ok(Self::Direct),
+            "env" => Ok(Self::Env),
+            url => {
+                ureq::Proxy::new(url)
+                    .map_err(|err| anyhow::anyhow!("invalid proxy '{url}': {err}"))?;
+                Ok(Self::Url(url.to_string()))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ProxySetting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Direct => write!(f, "direct"),
+            Self::Env => write!(f, "env"),
+            Self::Url(url) => write!(f, "{url}"),
+        }
+    }
+}
+
+impl serde::Serialize for ProxySetting {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for ProxySetting {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Retry policy for connectivity probes.
+///
+/// Each target (the DNS check, or a single URL) is attempted up to
+/// `attempts_per_target` times, while all targets of a check run share a
+/// budget of `max_total_attempts`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RetryPolicy {
+    /// Attempts per target, including the first one.
+    #[serde(default = "RetryPolicy::default_attempts_per_target")]
+    pub attempts_per_target: u32,
+    /// Attempts across all targets of a single check run.
+    #[serde(default = "RetryPolicy::default_max_total_attempts")]
+    pub max_total_attempts: u32,
+    /// Delay before the first retry of a target.
+    #[serde(default = "RetryPolicy::default_backoff_initial_ms")]
+    pub backoff_initial_ms: u64,
+    /// Factor applied to the delay for each further retry.
+    #[serde(default = "RetryPolicy::default_backoff_multiplier")]
+    pub backoff_multiplier: u32,
+    #[serde(default = "RetryPolicy::default_backoff_max_ms")]
+    pub backoff_max_ms: u64,
+    /// Random deviation applied to each delay, in percent.
+    #[serde(default = "RetryPolicy::default_jitter_percent")]
+    pub jitter_percent: u8,
+    /// Don't retry URLs whose host name could not be resolved.
+    #[serde(default = "default_true")]
+    pub fail_fast_on_dns_error: bool,
+}
+
+impl RetryPolicy {
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.attempts_per_target == 0 {
+            anyhow::bail!("'online.retry.attempts_per_target' must be greater than 0");
+        }
+        if self.max_total_attempts == 0 {
+            anyhow::bail!("'online.retry.max_total_attempts' must be greater than 0");
+        }
+        if self.jitter_percent > 100 {
+            anyhow::bail!("'online.retry.jitter_percent' must be at most 100");
+        }
+        Ok(())
+    }
+
+    fn default_attempts_per_target() -> u32 {
+        3
+    }
+
+    fn default_max_total_attempts() -> u32 {
+        6
+    }
+
+    fn default_backoff_initial_ms() -> u64 {
+        2000
+    }
+
+    fn default_backoff_multiplier() -> u32 {
+        2
+    }
+
+    fn default_backoff_max_ms() -> u64 {
+        10_000
+    }
+
+    fn default_jitter_percent() -> u8 {
+        20
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts_per_target: Self::default_attempts_per_target(),
+            max_total_attempts: Self::default_max_total_attempts(),
+            backoff_initial_ms: Self::default_backoff_initial_ms(),
+            backoff_multiplier: Self::default_backoff_multiplier(),
+            backoff_max_ms: Self::default_backoff_max_ms(),
+            jitter_percent: Self::default_jitter_percent(),
+            fail_fast_on_dns_error: true,
+        }
+    }
+}
+
+/// Thresholds for considering the connection degraded.
+///
+/// The connection becomes degraded when the p95 latency or the loss rate
+/// exceeds the `*_enter` thresholds, and recovers once both are back within
+/// the `*_exit` thresholds.
+///
+/// Available variables: ${latency_ms} (p95), ${latency_p50_ms},
+/// ${loss_percent}
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DegradationConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Number of recent results kept per probe.
+    #[serde(default = "DegradationConfig::default_window_size")]
+    pub window_size: usize,
+    /// Minimum number of results across all probes before evaluating.
+    #[serde(default = "DegradationConfig::default_min_samples")]
+    pub min_samples: usize,
+    #[serde(default = "DegradationConfig::default_latency_ms_enter")]
+    pub latency_ms_enter: u64,
+    #[serde(default = "DegradationConfig::default_latency_ms_exit")]
+    pub latency_ms_exit: u64,
+    #[serde(default = "DegradationConfig::default_loss_percent_enter")]
+    pub loss_percent_enter: u8,
+    #[serde(default = "DegradationConfig::default_loss_percent_exit")]
+    pub loss_percent_exit: u8,
+    #[serde(default = "DegradationConfig::default_alert_degraded")]
+    pub alert_degraded: Option<Alert>,
+    #[serde(default = "DegradationConfig::default_alert_recovered")]
+    pub alert_recovered: Option<Alert>,
+}
+
+impl DegradationConfig {
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.window_size == 0 {
+            anyhow::bail!("'online.degradation.window_size' must be greater than 0");
+        }
+        if self.latency_ms_exit > self.latency_ms_enter {
+            anyhow::bail!(
+                "'online.degradation.latency_ms_exit' must not be greater than 'latency_ms_enter'"
+            );
+        }
+        if self.loss_percent_exit > self.loss_percent_enter {
+            anyhow::bail!(
+                "'online.degradation.loss_percent_exit' must not be greater than 'loss_percent_enter'"
+            );
+        }
+        Ok(())
+    }
+
+    fn default_window_size() -> usize {
+        20
+    }
+
+    fn default_min_samples() -> usize {
+        5
+    }
+
+    fn default_latency_ms_enter() -> u64 {
+        500
+    }
+
+    fn default_latency_ms_exit() -> u64 {
+        300
+    }
+
+    fn default_loss_percent_enter() -> u8 {
+        10
+    }
+
+    fn default_loss_percent_exit() -> u8 {
+        3
+    }
+
+    fn default_alert_degraded() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Warning,
+            on_startup: true,
+            repeat_after_seconds: None,
+            expire_after_seconds: None,
+            summary: "Internet connection is degraded".to_string(),
+            message: Some("Latency: ${latency_ms}ms, packet loss: ${loss_percent}%".to_string()),
+        })
+    }
+
+    fn default_alert_recovered() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Info,
+            on_startup: false,
+            repeat_after_seconds: None,
+            expire_after_seconds: Some(10),
+            summary: "Internet connection quality recovered".to_string(),
+            message: Some("Latency: ${latency_ms}ms, packet loss: ${loss_percent}%".to_string()),
+        })
+    }
+}
+
+impl Default for DegradationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            window_size: Self::default_window_size(),
+            min_samples: Self::default_min_samples(),
+            latency_ms_enter: Self::default_latency_ms_enter(),
+            latency_ms_exit: Self::default_latency_ms_exit(),
+            loss_percent_enter: Self::default_loss_percent_enter(),
+            loss_percent_exit: Self::default_loss_percent_exit(),
+            alert_degraded: Self::default_alert_degraded(),
+            alert_recovered: Self::default_alert_recovered(),
+        }
+    }
+}
+
+/// Request to a plain HTTP URL with a known response.
+/// A redirect or an unexpected response indicates a captive portal.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CaptivePortalCheck {
+    pub url: url::Url,
+    pub expected_status: u16,
     pub body_contains: Option<String>,
 }
 
+/// Alerts for specific reasons of being offline.
+/// `alert_disconnected` is used for states without an alert.
+///
+/// Available variables: ${reason}, plus ${gateway} for `gateway_unreachable`
+/// and ${portal_url} for `captive_portal`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StateAlerts {
+    #[serde(default)]
+    pub no_route: Option<Alert>,
+    #[serde(default)]
+    pub gateway_unreachable: Option<Alert>,
+    #[serde(default)]
+    pub dns_broken: Option<Alert>,
+    #[serde(default)]
+    pub http_blocked: Option<Alert>,
+    #[serde(default = "StateAlerts::default_captive_portal")]
+    pub captive_portal: Option<Alert>,
+}
+
+impl StateAlerts {
+    fn default_captive_portal() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Warning,
+            on_startup: true,
+            repeat_after_seconds: None,
+            expire_after_seconds: None,
+            summary: "Captive portal detected - log in to access the internet".to_string(),
+            message: Some("${portal_url}".to_string()),
+        })
+    }
+}
+
+impl Default for StateAlerts {
+    fn default() -> Self {
+        Self {
+            no_route: None,
+            gateway_unreachable: None,
+            dns_broken: None,
+            http_blocked: None,
+            captive_portal: Self::default_captive_portal(),
+        }
+    }
+}
+
 /// DNS servers to use for online checks.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum DnsServerSource {
+    /// Skip the DNS check.
+    Disabled,
+    /// Use the nameservers from `/etc/resolv.conf`.
     System,
-    Custom(Vec<IpAddr>),
+    Custom(Vec<DnsServer>),
+}
+
+/// Address of a DNS server, either "<ip>" or "<ip>:<port>".
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DnsServer(pub SocketAddr);
+
+impl DnsServer {
+    const DEFAULT_PORT: u16 = 53;
+}
+
+impl From<IpAddr> for DnsServer {
+    fn from(ip: IpAddr) -> Self {
+        Self(SocketAddr::new(ip, Self::DEFAULT_PORT))
+    }
+}
+
+impl std::str::FromStr for DnsServer {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(ip) = s.parse::<IpAddr>() {
+            return Ok(ip.into());
+        }
+        s.parse::<SocketAddr>()
+            .map(Self)
+            .map_err(|_| anyhow::anyhow!("invalid DNS server address '{s}'"))
+    }
+}
+
+impl std::fmt::Display for DnsServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.port() == Self::DEFAULT_PORT {
+            write!(f, "{}", self.0.ip())
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+impl serde::Serialize for DnsServer {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for DnsServer {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
 }
 
 fn default_true() -> bool {
@@ -0,0 +1,275 @@
+//! Diagnosis of the reason for missing connectivity.
+
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::Context;
+
+use super::cfg::CaptivePortalCheck;
+
+const RTF_UP: u32 = 0x0001;
+const RTF_REJECT: u32 = 0x0200;
+
+const GATEWAY_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Diagnosed connectivity state.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Connectivity {
+    Online,
+    NoRoute,
+    GatewayUnreachable(IpAddr),
+    DnsBroken,
+    HttpBlocked,
+    /// A captive portal intercepts traffic, usually requiring a login.
+    CaptivePortal {
+        /// Portal URL, if the check was redirected.
+        url: Option<String>,
+    },
+}
+
+impl Connectivity {
+    pub fn is_online(&self) -> bool {
+        matches!(self, Self::Online)
+    }
+
+    /// Human readable description, available to alerts as `${reason}`.
+    pub fn reason(&self) -> String {
+        match self {
+            Self::Online => "online".to_string(),
+            Self::NoRoute => "no default route - not connected to a network".to_string(),
+            Self::GatewayUnreachable(gw) => format!("gateway {gw} is unreachable"),
+            Self::DnsBroken => "DNS resolution failed".to_string(),
+            Self::HttpBlocked => "HTTP connectivity checks failed".to_string(),
+            Self::CaptivePortal { .. } => "captive portal detected - login required".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DefaultRoute {
+    pub iface: String,
+    /// Next hop, if the route has one.
+    /// Only set for IPv4, since IPv6 gateways are link-local.
+    pub gateway: Option<Ipv4Addr>,
+}
+
+/// Find the default route in `/proc/net/route` and `/proc/net/ipv6_route`.
+///
+/// IPv4 routes are preferred.
+pub fn load_default_route(proc_net: &Path) -> Result<Option<DefaultRoute>, anyhow::Error> {
+    let v4 = std::fs::read_to_string(proc_net.join("route"))
+        .context("could not read /proc/net/route")?;
+    if let Some(route) = parse_route_v4(&v4) {
+        return Ok(Some(route));
+    }
+
+    // Missing if IPv6 is disabled.
+    let v6 = std::fs::read_to_string(proc_net.join("ipv6_route")).unwrap_or_default();
+    Ok(parse_route_v6(&v6))
+}
+
+/// Parse the default route with the lowest metric from `/proc/net/route`.
+pub fn parse_route_v4(content: &str) -> Option<DefaultRoute> {
+    content
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let (iface, dest, gateway, flags, metric, mask) = (
+                *fields.first()?,
+                *fields.get(1)?,
+                *fields.get(2)?,
+                *fields.get(3)?,
+                *fields.get(6)?,
+                *fields.get(7)?,
+            );
+            let flags = u32::from_str_radix(flags, 16).ok()?;
+            if dest != "00000000" || mask != "00000000" || flags & RTF_UP == 0 {
+                return None;
+            }
+            let metric = metric.parse::<u32>().ok()?;
+            // Addresses are in host (little endian) byte order.
+            let gateway = u32::from_str_radix(gateway, 16).ok()?;
+            let gateway = Ipv4Addr::from(gateway.swap_bytes());
+            let route = DefaultRoute {
+                iface: iface.to_string(),
+                gateway: (!gateway.is_unspecified()).then_some(gateway),
+            };
+            Some((metric, route))
+        })
+        .min_by_key(|(metric, _)| *metric)
+        .map(|x| x.1)
+}
+
+/// Parse the default route from `/proc/net/ipv6_route`.
+pub fn parse_route_v6(content: &str) -> Option<DefaultRoute> {
+    content.lines().find_map(|line| {
+        let fields: Vec<_> = line.split_whitespace().collect();
+        let (dest, prefix_len, flags, iface) = (
+            *fields.first()?,
+            *fields.get(1)?,
+            *fields.get(8)?,
+            *fields.get(9)?,
+        );
+        let flags = u32::from_str_radix(flags, 16).ok()?;
+        let is_default = dest.chars().all(|c| c == '0') && prefix_len == "00";
+        if !is_default || flags & RTF_UP == 0 || flags & RTF_REJECT != 0 || iface == "lo" {
+            return None;
+        }
+        Some(DefaultRoute {
+            iface: iface.to_string(),
+            gateway: None,
+        })
+    })
+}
+
+/// Whether `/proc/net/arp` has a resolved entry for `ip`.
+pub fn parse_arp_complete(content: &str, ip: Ipv4Addr) -> bool {
+    const ATF_COM: u32 = 0x02;
+
+    content.lines().skip(1).any(|line| {
+        let fields: Vec<_> = line.split_whitespace().collect();
+        let (Some(addr), Some(flags)) = (fields.first(), fields.get(2)) else {
+            return false;
+        };
+        let flags = u32::from_str_radix(flags.trim_start_matches("0x"), 16).unwrap_or(0);
+        addr.parse::<Ipv4Addr>().ok() == Some(ip) && flags & ATF_COM != 0
+    })
+}
+
+/// Check whether the gateway responds.
+///
+/// A TCP connection attempt to the DNS port counts as success if the
+/// connection is accepted or actively refused. Since many routers silently
+/// drop such connections, a resolved ARP entry also counts as reachable.
+pub async fn probe_gateway(gateway: Ipv4Addr, proc_net: &Path) -> bool {
+    let connect = tokio::net::TcpStream::connect((gateway, 53));
+    match tokio::time::timeout(GATEWAY_PROBE_TIMEOUT, connect).await {
+        Ok(Ok(_)) => return true,
+        Ok(Err(err)) if err.kind() == std::io::ErrorKind::ConnectionRefused => return true,
+        _ => {}
+    }
+
+    let arp = std::fs::read_to_string(proc_net.join("arp")).unwrap_or_default();
+    parse_arp_complete(&arp, gateway)
+}
+
+/// Check for a captive portal by requesting a well-known URL without
+/// following redirects.
+///
+/// Returns `Ok(None)` if the expected response was received.
+pub fn check_captive_portal(
+    check: &CaptivePortalCheck,
+    timeout: Duration,
+) -> Result<Option<Connectivity>, anyhow::Error> {
+    let agent = ureq::AgentBuilder::new()
+        .redirects(0)
+        .timeout(timeout)
+        .build();
+
+    let res = match agent.get(check.url.as_str()).call() {
+        Ok(res) => res,
+        Err(ureq::Error::Status(_, res)) => res,
+        Err(err) => return Err(err).context("captive portal check failed"),
+    };
+
+    let status = res.status();
+    if (300..400).contains(&status) {
+        let url = res.header("location").map(|x| x.to_string());
+        return Ok(Some(Connectivity::CaptivePortal { url }));
+    }
+    if status != check.expected_status {
+        return Ok(Some(Connectivity::CaptivePortal { url: None }));
+    }
+    if let Some(expected) = &check.body_contains {
+        let body = res.into_string().context("could not read response body")?;
+        if !body.contains(expected) {
+            return Ok(Some(Connectivity::CaptivePortal { url: None }));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_route_v4() {
+        let content =
+            "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+eth0\t00000000\t0100000A\t0003\t0\t0\t100\t00000000\t0\t0\t0
+wlan0\t0001A8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\t0\t0\t0
+";
+        assert_eq!(
+            parse_route_v4(content),
+            Some(DefaultRoute {
+                iface: "eth0".to_string(),
+                gateway: Some(Ipv4Addr::new(10, 0, 0, 1)),
+            })
+        );
+
+        let no_default =
+            "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wlan0\t0001A8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\t0\t0\t0
+";
+        assert_eq!(parse_route_v4(no_default), None);
+
+        let point_to_point =
+            "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+ppp0\t00000000\t00000000\t0001\t0\t0\t0\t00000000\t0\t0\t0
+";
+        assert_eq!(
+            parse_route_v4(point_to_point),
+            Some(DefaultRoute {
+                iface: "ppp0".to_string(),
+                gateway: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_route_v6() {
+        let content = "00000000000000000000000000000000 00 00000000000000000000000000000000 00 00000000000000000000000000000000 ffffffff 00000001 00000000 00200200       lo
+fe800000000000000000000000000000 40 00000000000000000000000000000000 00 00000000000000000000000000000000 00000100 00000001 00000000 00000001    wlan0
+";
+        assert_eq!(parse_route_v6(content), None);
+
+        let content = "00000000000000000000000000000000 00 00000000000000000000000000000000 00 fe80000000000000021122fffe334455 00000400 00000001 00000000 00000003    wlan0
+";
+        assert_eq!(
+            parse_route_v6(content),
+            Some(DefaultRoute {
+                iface: "wlan0".to_string(),
+                gateway: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_arp() {
+        let content =
+            "IP address       HW type     Flags       HW address            Mask     Device
+192.168.1.1      0x1         0x2         aa:bb:cc:dd:ee:ff     *        wlan0
+192.168.1.7      0x1         0x0         00:00:00:00:00:00     *        wlan0
+";
+        assert!(parse_arp_complete(content, Ipv4Addr::new(192, 168, 1, 1)));
+        assert!(!parse_arp_complete(content, Ipv4Addr::new(192, 168, 1, 7)));
+        assert!(!parse_arp_complete(content, Ipv4Addr::new(10, 0, 0, 1)));
+    }
+
+    #[tokio::test]
+    async fn test_probe_gateway_refused() {
+        // Nothing listens on port 53 on localhost, but the refused
+        // connection proves the host is reachable.
+        let dir = crate::testutil::TempDir::new("gateway-probe");
+        assert!(probe_gateway(Ipv4Addr::LOCALHOST, dir.path()).await);
+    }
+}
@@ -0,0 +1,483 @@
+//! Minimal async DNS client used for connectivity checks.
+//!
+//! Queries are sent over UDP, falling back to TCP if the response was
+//! truncated. See RFC 1035 for the wire format.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use anyhow::Context;
+use futures::{stream::FuturesUnordered, StreamExt};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+};
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+const FLAG_RD: u16 = 0x0100;
+const FLAG_TC: u16 = 0x0200;
+const FLAG_QR: u16 = 0x8000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnsResponse {
+    pub id: u16,
+    pub truncated: bool,
+    pub rcode: u8,
+    pub addrs: Vec<IpAddr>,
+}
+
+/// Query all servers concurrently for the A record of `domain`.
+///
+/// Succeeds as soon as one server returns a successful response.
+pub async fn probe(
+    servers: &[SocketAddr],
+    domain: &str,
+    timeout: Duration,
+) -> Result<Vec<IpAddr>, anyhow::Error> {
+    if servers.is_empty() {
+        anyhow::bail!("no DNS servers configured");
+    }
+
+    let mut queries = servers
+        .iter()
+        .map(|server| async move {
+            let res = tokio::time::timeout(timeout, query(*server, domain))
+                .await
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("timed out after {timeout:?}")));
+            (server, res)
+        })
+        .collect::<FuturesUnordered<_>>();
+
+    let mut last_error = None;
+    while let Some((server, res)) = queries.next().await {
+        match res {
+            Ok(addrs) => return Ok(addrs),
+            Err(err) => {
+                tracing::debug!(%server, error = &*err, "DNS query failed");
+                last_error = Some(err.context(format!("DNS query to {server} failed")));
+            }
+        }
+    }
+
+    Err(last_error.unwrap())
+}
+
+/// Query a single server, falling back to TCP if the UDP response was
+/// truncated.
+pub async fn query(server: SocketAddr, domain: &str) -> Result<Vec<IpAddr>, anyhow::Error> {
+    let id = query_id();
+    let request = build_query(id, domain, TYPE_A)?;
+
+    let mut response = query_udp(server, &request, id).await?;
+    if response.truncated {
+        tracing::trace!(%server, "DNS response truncated - retrying over TCP");
+        response = query_tcp(server, &request, id).await?;
+    }
+
+    match response.rcode {
+        0 => Ok(response.addrs),
+        rcode => Err(anyhow::anyhow!("server returned error code {rcode}")),
+    }
+}
+
+async fn query_udp(
+    server: SocketAddr,
+    request: &[u8],
+    id: u16,
+) -> Result<DnsResponse, anyhow::Error> {
+    let bind: SocketAddr = if server.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(bind)
+        .await
+        .context("could not bind UDP socket")?;
+    socket
+        .connect(server)
+        .await
+        .context("could not connect UDP socket")?;
+    socket
+        .send(request)
+        .await
+        .context("could not send DNS query")?;
+
+    let mut buf = [0u8; 512];
+    loop {
+        let len = socket
+            .recv(&mut buf)
+            .await
+            .context("could not receive DNS response")?;
+        // Ignore stray datagrams and responses to other queries.
+        match parse_response(&buf[..len]) {
+            Ok(response) if response.id == id => return Ok(response),
+            Ok(_) => {}
+            Err(err) => tracing::trace!(%server, error = &*err, "ignoring invalid DNS response"),
+        }
+    }
+}
+
+async fn query_tcp(
+    server: SocketAddr,
+    request: &[u8],
+    id: u16,
+) -> Result<DnsResponse, anyhow::Error> {
+    let mut stream = TcpStream::connect(server)
+        .await
+        .context("could not connect to DNS server over TCP")?;
+
+    let mut msg = Vec::with_capacity(request.len() + 2);
+    msg.extend_from_slice(&(request.len() as u16).to_be_bytes());
+    msg.extend_from_slice(request);
+    stream
+        .write_all(&msg)
+        .await
+        .context("could not send DNS query")?;
+
+    let len = stream
+        .read_u16()
+        .await
+        .context("could not read DNS response length")?;
+    let mut buf = vec![0u8; len as usize];
+    stream
+        .read_exact(&mut buf)
+        .await
+        .context("could not read DNS response")?;
+
+    let response = parse_response(&buf)?;
+    if response.id != id {
+        anyhow::bail!("DNS response has mismatched id");
+    }
+    Ok(response)
+}
+
+fn query_id() -> u16 {
+    // No need for a proper RNG, the id only has to differ between queries.
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    (nanos ^ (nanos >> 16)) as u16
+}
+
+pub fn build_query(id: u16, domain: &str, qtype: u16) -> Result<Vec<u8>, anyhow::Error> {
+    let mut out = Vec::with_capacity(domain.len() + 18);
+    out.extend_from_slice(&id.to_be_bytes());
+    out.extend_from_slice(&FLAG_RD.to_be_bytes());
+    // QDCOUNT, ANCOUNT, NSCOUNT, ARCOUNT
+    out.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+
+    for label in domain.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            anyhow::bail!("invalid domain name '{domain}'");
+        }
+        out.push(label.len() as u8);
+        out.extend_from_slice(label.as_bytes());
+    }
+    out.push(0);
+
+    out.extend_from_slice(&qtype.to_be_bytes());
+    out.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(out)
+}
+
+pub fn parse_response(data: &[u8]) -> Result<DnsResponse, anyhow::Error> {
+    let mut reader = Reader { data, pos: 0 };
+
+    let id = reader.u16()?;
+    let flags = reader.u16()?;
+    if flags & FLAG_QR == 0 {
+        anyhow::bail!("DNS message is not a response");
+    }
+    let qdcount = reader.u16()?;
+    let ancount = reader.u16()?;
+    let _nscount = reader.u16()?;
+    let _arcount = reader.u16()?;
+
+    let truncated = flags & FLAG_TC != 0;
+    let rcode = (flags & 0x000f) as u8;
+
+    let mut addrs = Vec::new();
+    if truncated {
+        return Ok(DnsResponse {
+            id,
+            truncated,
+            rcode,
+            addrs,
+        });
+    }
+
+    for _ in 0..qdcount {
+        reader.skip_name()?;
+        // QTYPE, QCLASS
+        reader.take(4)?;
+    }
+
+    for _ in 0..ancount {
+        reader.skip_name()?;
+        let rtype = reader.u16()?;
+        let _class = reader.u16()?;
+        let _ttl = reader.take(4)?;
+        let len = reader.u16()? as usize;
+        let rdata = reader.take(len)?;
+
+        match (rtype, len) {
+            (TYPE_A, 4) => {
+                let octets: [u8; 4] = rdata.try_into().unwrap();
+                addrs.push(IpAddr::from(octets));
+            }
+            (TYPE_AAAA, 16) => {
+                let octets: [u8; 16] = rdata.try_into().unwrap();
+                addrs.push(IpAddr::from(octets));
+            }
+            // CNAMEs etc.
+            _ => {}
+        }
+    }
+
+    Ok(DnsResponse {
+        id,
+        truncated,
+        rcode,
+        addrs,
+    })
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], anyhow::Error> {
+        let end = self.pos + len;
+        let slice = self
+            .data
+            .get(self.pos..end)
+            .context("DNS message is truncated")?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, anyhow::Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, anyhow::Error> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    /// Skip over a (possibly compressed) domain name.
+    fn skip_name(&mut self) -> Result<(), anyhow::Error> {
+        loop {
+            let len = self.u8()?;
+            match len {
+                0 => return Ok(()),
+                // A compression pointer ends the name.
+                l if l & 0xc0 == 0xc0 => {
+                    self.u8()?;
+                    return Ok(());
+                }
+                l => {
+                    self.take(l as usize)?;
+                }
+            }
+        }
+    }
+}
+
+/// Parse the nameservers from the contents of `/etc/resolv.conf`.
+pub fn parse_resolv_conf(content: &str) -> Vec<IpAddr> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            if parts.next()? != "nameserver" {
+                return None;
+            }
+            // Strip IPv6 zone ids like "fe80::1%eth0".
+            let addr = parts.next()?.split('%').next()?;
+            addr.parse().ok()
+        })
+        .collect()
+}
+
+pub fn load_system_servers() -> Result<Vec<IpAddr>, anyhow::Error> {
+    let content =
+        std::fs::read_to_string("/etc/resolv.conf").context("could not read /etc/resolv.conf")?;
+    let servers = parse_resolv_conf(&content);
+    if servers.is_empty() {
+        anyhow::bail!("no nameservers found in /etc/resolv.conf");
+    }
+    Ok(servers)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use pretty_assertions::assert_eq;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Build a response to `query` with a single A record.
+    fn respond(query: &[u8], addr: Ipv4Addr, truncated: bool) -> Vec<u8> {
+        let mut out = query.to_vec();
+        let mut flags = FLAG_QR | FLAG_RD | 0x0080;
+        if truncated {
+            flags |= FLAG_TC;
+        }
+        out[2..4].copy_from_slice(&flags.to_be_bytes());
+        if truncated {
+            return out;
+        }
+        // ANCOUNT = 1
+        out[6..8].copy_from_slice(&1u16.to_be_bytes());
+        // Name as a compression pointer to the question.
+        out.extend_from_slice(&[0xc0, 12]);
+        out.extend_from_slice(&TYPE_A.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+        out.extend_from_slice(&60u32.to_be_bytes());
+        out.extend_from_slice(&4u16.to_be_bytes());
+        out.extend_from_slice(&addr.octets());
+        out
+    }
+
+    /// Start a stub resolver on localhost that answers every query with
+    /// `addr`. If `truncate_udp` is set, UDP responses are truncated so the
+    /// client has to fall back to TCP.
+    pub async fn stub_resolver(addr: Ipv4Addr, truncate_udp: bool) -> SocketAddr {
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let local = udp.local_addr().unwrap();
+        let tcp = TcpListener::bind(local).await.unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            while let Ok((len, peer)) = udp.recv_from(&mut buf).await {
+                let response = respond(&buf[..len], addr, truncate_udp);
+                udp.send_to(&response, peer).await.ok();
+            }
+        });
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = tcp.accept().await {
+                let len = stream.read_u16().await.unwrap();
+                let mut buf = vec![0u8; len as usize];
+                stream.read_exact(&mut buf).await.unwrap();
+                let response = respond(&buf, addr, false);
+                stream
+                    .write_all(&(response.len() as u16).to_be_bytes())
+                    .await
+                    .unwrap();
+                stream.write_all(&response).await.unwrap();
+            }
+        });
+
+        local
+    }
+
+    /// Address of a local UDP port that never answers.
+    pub async fn silent_server() -> (UdpSocket, SocketAddr) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        (socket, addr)
+    }
+
+    #[test]
+    fn test_build_query() {
+        let query = build_query(0x1234, "example.com", TYPE_A).unwrap();
+        assert_eq!(
+            query,
+            vec![
+                0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0, 7, b'e', b'x', b'a', b'm', b'p',
+                b'l', b'e', 3, b'c', b'o', b'm', 0, 0, 1, 0, 1
+            ]
+        );
+        assert!(build_query(1, "example..com", TYPE_A).is_err());
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let query = build_query(7, "example.com", TYPE_A).unwrap();
+        let response = parse_response(&respond(&query, Ipv4Addr::new(1, 2, 3, 4), false)).unwrap();
+        assert_eq!(
+            response,
+            DnsResponse {
+                id: 7,
+                truncated: false,
+                rcode: 0,
+                addrs: vec![IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4))],
+            }
+        );
+
+        assert!(parse_response(&query).is_err());
+        assert!(parse_response(&[0x12]).is_err());
+    }
+
+    #[test]
+    fn test_parse_resolv_conf() {
+        let content = "# generated\nsearch lan\nnameserver 192.168.1.1\nnameserver fe80::1%wlan0\nnameserver bogus\noptions edns0\n";
+        assert_eq!(
+            parse_resolv_conf(content),
+            vec![
+                "192.168.1.1".parse::<IpAddr>().unwrap(),
+                "fe80::1".parse::<IpAddr>().unwrap()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_probe_udp() {
+        let server = stub_resolver(Ipv4Addr::new(10, 0, 0, 1), false).await;
+        let addrs = probe(&[server], "example.com", Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(addrs, vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))]);
+    }
+
+    #[tokio::test]
+    async fn test_probe_udp_stray_datagram() {
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server = udp.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            let (len, peer) = udp.recv_from(&mut buf).await.unwrap();
+            udp.send_to(&[0x12], peer).await.unwrap();
+            let response = respond(&buf[..len], Ipv4Addr::new(10, 0, 0, 4), false);
+            udp.send_to(&response, peer).await.unwrap();
+        });
+        let addrs = probe(&[server], "example.com", Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(addrs, vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 4))]);
+    }
+
+    #[tokio::test]
+    async fn test_probe_tcp_fallback() {
+        let server = stub_resolver(Ipv4Addr::new(10, 0, 0, 2), true).await;
+        let addrs = probe(&[server], "example.com", Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(addrs, vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))]);
+    }
+
+    #[tokio::test]
+    async fn test_probe_timeout() {
+        let (_socket, silent) = silent_server().await;
+        let err = probe(&[silent], "example.com", Duration::from_millis(100))
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("timed out"));
+
+        // A working server wins over a silent one.
+        let server = stub_resolver(Ipv4Addr::new(10, 0, 0, 3), false).await;
+        let addrs = probe(&[silent, server], "example.com", Duration::from_secs(2))
+            .await
+            .unwrap();
+        assert_eq!(addrs, vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 3))]);
+    }
+}
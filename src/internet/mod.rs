@@ -1,28 +1,63 @@
-use std::time::Duration;
-
-// use dnsclient::UpstreamServer;
-// use rand::seq::SliceRandom;
+use std::{cell::RefCell, collections::HashMap, path::PathBuf, time::Duration};
 
 use anyhow::Context;
+use futures::{stream::FuturesUnordered, StreamExt};
+use tokio::{sync::mpsc, time::Instant};
 
 use crate::notify::Notifier;
 
-use self::cfg::OnlineConfig;
+use self::{
+    cfg::{AddressFamily, CheckKind, CheckUrl, DnsServerSource, OnlineConfig, Probe, ProxySetting},
+    diag::Connectivity,
+    quality::QualityTracker,
+    retry::{ProbeError, Retrier},
+};
 
 pub mod cfg;
+pub mod diag;
+pub mod dns;
+pub mod http;
+pub mod probe;
+pub mod quality;
+pub mod retry;
 
 const ALERT_GROUP_INTERNET: &str = "panorama.internet";
+const ALERT_GROUP_QUALITY: &str = "panorama.internet.quality";
+const ALERT_GROUP_SERVICE_PREFIX: &str = "panorama.service.";
+const ALERT_GROUP_FAMILY_PREFIX: &str = "panorama.internet.";
+const ALERT_GROUP_PROXY_PREFIX: &str = "panorama.proxy.";
 
 pub struct OnlineManager {
     config: OnlineConfig,
     notifier: Notifier,
+    proc_net: PathBuf,
+    /// Last diagnosed state, `None` before the first check.
+    state: Option<Connectivity>,
     offline_since: Option<std::time::SystemTime>,
     check_count: usize,
+    /// Whether each service check succeeded on its last run.
+    services_up: HashMap<String, bool>,
+    /// Results of connectivity probes, recorded while checks are running.
+    quality: RefCell<QualityTracker>,
+    degraded: bool,
+    /// Proxy failures detected by `also_direct` checks in the current check
+    /// run, keyed by the redacted proxy URL.
+    proxy_results: RefCell<HashMap<String, Option<anyhow::Error>>>,
+    /// Whether each proxy worked on its last check.
+    proxies_up: HashMap<String, bool>,
+    /// Connectivity per address family, empty while offline.
+    families_up: HashMap<AddressFamily, bool>,
+    /// Requests for an immediate check, eg after network changes.
+    check_requests: mpsc::Receiver<()>,
 }
 
 impl OnlineManager {
-    pub async fn start(config: OnlineConfig, notifier: Notifier) -> Result<(), anyhow::Error> {
-        let manager = Self::new(config, notifier)?;
+    pub async fn start(
+        config: OnlineConfig,
+        notifier: Notifier,
+        check_requests: mpsc::Receiver<()>,
+    ) -> Result<(), anyhow::Error> {
+        let manager = Self::new(config, notifier, check_requests)?;
         tokio::task::spawn_local(async move { manager.run().await })
             .await
             .context("OnlineManager taks failed")?
@@ -31,14 +66,27 @@ impl OnlineManager {
         Ok(())
     }
 
-    fn new(config: OnlineConfig, notifier: Notifier) -> Result<Self, anyhow::Error> {
+    fn new(
+        config: OnlineConfig,
+        notifier: Notifier,
+        check_requests: mpsc::Receiver<()>,
+    ) -> Result<Self, anyhow::Error> {
         let config = config.validate()?;
 
         Ok(Self {
-            config,
             notifier,
+            proc_net: PathBuf::from("/proc/net"),
+            state: None,
             offline_since: None,
             check_count: 0,
+            services_up: HashMap::new(),
+            quality: RefCell::new(QualityTracker::new(config.degradation.window_size)),
+            degraded: false,
+            proxy_results: RefCell::new(HashMap::new()),
+            proxies_up: HashMap::new(),
+            families_up: HashMap::new(),
+            check_requests,
+            config,
         })
     }
 
@@ -50,110 +98,1238 @@ impl OnlineManager {
             } else {
                 self.config.check_interval_seconds_online
             };
-            tokio::time::sleep(std::time::Duration::from_secs(time)).await;
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(time)) => {}
+                Some(()) = self.check_requests.recv() => {
+                    tracing::debug!("online check requested");
+                }
+            }
         }
     }
 
-    async fn tick(&mut self) -> Result<(), anyhow::Error> {
-        let mut count = 0;
-        let is_online = 'OUTER: loop {
-            // let servers = match &self.config.dns_servers {
-            //     DnsServerSource::System => dnsclient::system::default_resolvers()?,
-            //     DnsServerSource::Custom(servers) => {
-            //         let mut servers = servers.clone();
-            //         servers.shuffle(&mut rand::thread_rng());
-
-            //         servers
-            //             .iter()
-            //             .map(|s| UpstreamServer::new((s.clone(), 53)))
-            //             .collect()
-            //     }
-            // };
-
-            // let client = dnsclient::sync::DNSClient::new(servers);
-
-            // match client.query_a(&self.config.query_domain) {
-            //     Ok(_addrs) => {
-            //         break true;
-            //     }
-            //     Err(err) => {
-            //         tracing::warn!(error=%err, "DNS online query failed");
-            //         if count < self.config.retry_count {
-            //             count += 1;
-            //             std::thread::sleep(Duration::from_secs(self.config.retry_interval_seconds));
-            //             continue;
-            //         } else {
-            //             break false;
-            //         }
-            //     }
-            // }
-
-            for check in &self.config.urls {
-                let res = match ureq::get(check.url.as_str())
-                    .timeout(Duration::from_secs(self.config.http_timeout_secs))
-                    .call()
-                    .context("http query failed")
-                {
-                    Ok(res) => {
-                        if let Some(expected) = &check.body_contains {
-                            match res.into_string().context("could not read response body") {
-                                Ok(body) => {
-                                    if body.contains(expected) {
-                                        Ok(())
-                                    } else {
-                                        Err(anyhow::format_err!("response body did not contain expected string '{expected}'"))
-                                    }
-                                }
-                                Err(err) => Err(err),
-                            }
-                        } else {
-                            Ok(())
-                        }
+    /// Resolve `query_domain` with the configured DNS servers.
+    async fn check_dns(&self) -> Result<(), anyhow::Error> {
+        let servers = match &self.config.dns_servers {
+            DnsServerSource::Disabled => return Ok(()),
+            DnsServerSource::System => tokio::task::spawn_blocking(dns::load_system_servers)
+                .await??
+                .into_iter()
+                .map(|ip| cfg::DnsServer::from(ip).0)
+                .collect(),
+            DnsServerSource::Custom(servers) => servers.iter().map(|s| s.0).collect::<Vec<_>>(),
+        };
+
+        let timeout = Duration::from_secs(self.config.dns_timeout_secs);
+        let addrs = dns::probe(&servers, &self.config.query_domain, timeout).await?;
+        tracing::trace!(domain=%self.config.query_domain, ?addrs, "DNS online query succeeded");
+        Ok(())
+    }
+
+    /// Determine the connectivity state, including the reason for being
+    /// offline, by the `deadline`.
+    async fn diagnose(&self, deadline: Instant) -> Result<Connectivity, anyhow::Error> {
+        match tokio::time::timeout_at(deadline, self.diagnose_inner()).await {
+            Ok(res) => res,
+            Err(_) => {
+                tracing::warn!("online checks exceeded the deadline");
+                Ok(Connectivity::HttpBlocked)
+            }
+        }
+    }
+
+    async fn diagnose_inner(&self) -> Result<Connectivity, anyhow::Error> {
+        if self.config.route_check {
+            let proc_net = self.proc_net.clone();
+            let route =
+                tokio::task::spawn_blocking(move || diag::load_default_route(&proc_net)).await??;
+            let Some(route) = route else {
+                return Ok(Connectivity::NoRoute);
+            };
+            if let Some(gateway) = route.gateway {
+                if !diag::probe_gateway(gateway, &self.proc_net).await {
+                    return Ok(Connectivity::GatewayUnreachable(gateway.into()));
+                }
+            }
+        }
+
+        let state = self.check_dns_and_http().await;
+        if state.is_online() {
+            return Ok(state);
+        }
+
+        // Portals commonly intercept both DNS and HTTP(S) traffic.
+        if let Some(check) = self.config.captive_portal_check.clone() {
+            let timeout = Duration::from_secs(self.config.http_timeout_secs);
+            let res =
+                tokio::task::spawn_blocking(move || diag::check_captive_portal(&check, timeout))
+                    .await
+                    .context("captive portal check task failed")?;
+            match res {
+                Ok(Some(portal)) => return Ok(portal),
+                Ok(None) => {}
+                Err(err) => {
+                    tracing::debug!(error = &*err, "captive portal check failed");
+                }
+            }
+        }
+
+        Ok(state)
+    }
+
+    /// Run the DNS and HTTP checks, retrying on failure.
+    async fn check_dns_and_http(&self) -> Connectivity {
+        let retrier = Retrier::new(self.config.retry.clone());
+
+        let dns = retrier
+            .run(|| async { self.check_dns().await.map_err(ProbeError::Other) })
+            .await;
+        if let Err(err) = dns {
+            tracing::warn!(error = &*err, "DNS online query failed");
+            return Connectivity::DnsBroken;
+        }
+
+        match self.check_targets(&retrier, None).await {
+            Ok(()) => Connectivity::Online,
+            Err(err) => {
+                tracing::warn!(
+                    error = &*err,
+                    attempts = retrier.attempts_used(),
+                    "online checks failed"
+                );
+                Connectivity::HttpBlocked
+            }
+        }
+    }
+
+    /// URL checks and probes of the given kind.
+    fn checks(&self, kind: CheckKind) -> impl Iterator<Item = Check<'_>> {
+        let urls = self.config.urls.iter().map(Check::Url);
+        let probes = self.config.probes.iter().map(Check::Probe);
+        urls.chain(probes).filter(move |check| check.kind() == kind)
+    }
+
+    /// Run all connectivity URL checks and probes concurrently, each with its
+    /// own retries.
+    ///
+    /// Succeeds if any check succeeds. URL checks stop at the first success,
+    /// while probes always run to completion so their round trip times can be
+    /// recorded. The blocking HTTP requests run on the blocking thread pool,
+    /// so they don't stall other managers.
+    ///
+    /// If `family` is set, only checks that can use that address family are
+    /// run, with the family forced.
+    async fn check_targets(
+        &self,
+        retrier: &Retrier,
+        family: Option<AddressFamily>,
+    ) -> Result<(), anyhow::Error> {
+        let (probes, urls): (Vec<_>, Vec<_>) = self
+            .checks(CheckKind::Connectivity)
+            .filter(|check| family.is_none_or(|family| check.family().is_none_or(|f| f == family)))
+            .partition(|check| matches!(check, Check::Probe(_)));
+        if probes.is_empty() && urls.is_empty() {
+            return Ok(());
+        }
+
+        let probes = probes
+            .into_iter()
+            .map(|check| async move { (check, self.run_check(check, retrier, family).await) })
+            .collect::<FuturesUnordered<_>>()
+            .collect::<Vec<_>>();
+        let urls = async {
+            let mut checks = urls
+                .into_iter()
+                .map(|check| async move { (check, self.run_check(check, retrier, family).await) })
+                .collect::<FuturesUnordered<_>>();
+            let mut results = Vec::new();
+            while let Some((check, res)) = checks.next().await {
+                let ok = res.is_ok();
+                results.push((check, res));
+                if ok {
+                    break;
+                }
+            }
+            results
+        };
+        let (probes, urls) = futures::join!(probes, urls);
+
+        let mut last_error = None;
+        for (check, res) in probes.into_iter().chain(urls) {
+            match res {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    let target = check.target();
+                    tracing::debug!(%target, error = &*err, "online check failed");
+                    last_error = Some(err.context(format!("check of '{target}' failed")));
+                }
+            }
+        }
+
+        Err(last_error.unwrap())
+    }
+
+    /// Run a single check with retries, forcing `family` if set.
+    ///
+    /// Only the first attempt of unforced connectivity probes is recorded for
+    /// the quality tracking, so retries don't skew the loss.
+    async fn run_check(
+        &self,
+        check: Check<'_>,
+        retrier: &Retrier,
+        family: Option<AddressFamily>,
+    ) -> Result<(), anyhow::Error> {
+        let timeout = Duration::from_secs(self.config.http_timeout_secs);
+        match check {
+            Check::Url(check) => {
+                let proxy = check.proxy.clone().unwrap_or(self.config.proxy.clone());
+                let check = CheckUrl {
+                    family: family.or(check.family),
+                    proxy: Some(proxy),
+                    ..check.clone()
+                };
+                let proxy = check
+                    .proxy
+                    .as_ref()
+                    .and_then(|proxy| http::proxy_for(proxy, &check.url))
+                    .map(|proxy| http::redact_proxy(&proxy));
+                let res = Self::run_url_check(&check, retrier, timeout).await;
+                let (Some(proxy), true) = (proxy, check.also_direct) else {
+                    return res;
+                };
+                // Results of forced address families say nothing about the
+                // proxy in general.
+                let record = |err| {
+                    if family.is_none() {
+                        self.proxy_results.borrow_mut().insert(proxy.clone(), err);
                     }
-                    Err(err) => Err(err),
+                };
+                let Err(err) = res else {
+                    record(None);
+                    return Ok(());
                 };
 
-                match res {
+                let direct = CheckUrl {
+                    proxy: Some(ProxySetting::Direct),
+                    ..check
+                };
+                match Self::run_url_check(&direct, retrier, timeout).await {
                     Ok(()) => {
-                        break 'OUTER true;
+                        tracing::debug!(%proxy, error = &*err, "check only succeeded without proxy");
+                        record(Some(err));
+                        Ok(())
                     }
-                    Err(err) => {
-                        tracing::warn!(url=%check.url, error=%err, "HTTP online query failed");
-                        if count < self.config.retry_count {
-                            count += 1;
-                            tokio::time::sleep(Duration::from_secs(
-                                self.config.retry_interval_seconds,
-                            ))
-                            .await;
-                            continue;
-                        } else {
-                            break 'OUTER false;
-                        }
+                    // Without a direct connection the proxy state is unknown.
+                    Err(direct_err) => {
+                        Err(anyhow::anyhow!("{err:#}; without proxy: {direct_err:#}"))
                     }
                 }
             }
-        };
+            Check::Probe(probe) => {
+                let target = probe.target.to_string();
+                let forced = Probe {
+                    family: family.or(probe.family),
+                    ..probe.clone()
+                };
+                let mut record = probe.kind == CheckKind::Connectivity && family.is_none();
+                let rtt = retrier
+                    .run(|| {
+                        let first = std::mem::take(&mut record);
+                        let (forced, target) = (&forced, &target);
+                        async move {
+                            let res = probe::run(forced).await;
+                            if first {
+                                let rtt = res.as_ref().ok().copied();
+                                self.quality.borrow_mut().record(target, rtt);
+                            }
+                            res
+                        }
+                    })
+                    .await?;
+                tracing::trace!(%target, ?rtt, "probe succeeded");
+                Ok(())
+            }
+        }
+    }
 
-        if is_online {
-            if self.offline_since.is_some() {
-                self.offline_since = None;
+    async fn run_url_check(
+        check: &CheckUrl,
+        retrier: &Retrier,
+        timeout: Duration,
+    ) -> Result<(), anyhow::Error> {
+        retrier
+            .run(|| {
+                let check = check.clone();
+                async move {
+                    tokio::task::spawn_blocking(move || http::check_url(&check, timeout))
+                        .await
+                        .context("HTTP check task failed")
+                        .and_then(|x| x)
+                        .map_err(ProbeError::from_http)
+                }
+            })
+            .await
+    }
 
-                if let Some(alert) = &self.config.alert_reconnected {
-                    let full = alert.prepare(ALERT_GROUP_INTERNET.to_string(), []);
-                    self.notifier.notify(full).await?;
+    /// Alert when proxies of `also_direct` checks go down or come back up.
+    async fn tick_proxies(&mut self, initial: bool) -> Result<(), anyhow::Error> {
+        let results = std::mem::take(self.proxy_results.get_mut());
+        for (proxy, err) in results {
+            let up = err.is_none();
+            let previous = self.proxies_up.insert(proxy.clone(), up);
+            if previous == Some(up) {
+                continue;
+            }
+
+            let mut variables = HashMap::from([("proxy".to_string(), proxy.clone())]);
+            let alert = match err {
+                None if previous.is_none() => continue,
+                None => &self.config.proxy_alerts.up,
+                Some(err) => {
+                    tracing::warn!(%proxy, error = &*err, "proxy is down");
+                    variables.insert("error".to_string(), format!("{err:#}"));
+                    &self.config.proxy_alerts.down
                 }
+            };
+            if let Some(alert) = alert {
+                let group = format!("{ALERT_GROUP_PROXY_PREFIX}{proxy}");
+                let full = alert.prepare(group, variables).initial(initial);
+                self.notifier.notify(full).await?;
             }
-        } else if self.offline_since.is_none() {
-            self.offline_since = Some(std::time::SystemTime::now());
+        }
 
-            if let Some(alert) = &self.config.alert_disconnected {
-                let full = alert.prepare(ALERT_GROUP_INTERNET.to_string(), []);
+        Ok(())
+    }
+
+    /// Run all service checks concurrently and alert on state changes.
+    ///
+    /// Checks still running at the `deadline` are considered failed.
+    async fn tick_services(
+        &mut self,
+        initial: bool,
+        deadline: Instant,
+    ) -> Result<(), anyhow::Error> {
+        let this = &*self;
+        let results = this
+            .checks(CheckKind::Service)
+            .map(|check| async move {
+                // Each service gets its own attempt budget.
+                let retrier = Retrier::new(this.config.retry.clone());
+                let mut variables = HashMap::new();
+                variables.insert("service".to_string(), check.name());
+                variables.insert("target".to_string(), check.target());
+                if let Check::Url(url) = check {
+                    variables.insert("url".to_string(), url.url.to_string());
+                }
+                let res = tokio::time::timeout_at(deadline, this.run_check(check, &retrier, None))
+                    .await
+                    .unwrap_or_else(|_| Err(anyhow::anyhow!("check exceeded the deadline")));
+                (variables, res)
+            })
+            .collect::<FuturesUnordered<_>>()
+            .collect::<Vec<_>>()
+            .await;
+
+        for (mut variables, res) in results {
+            let name = variables["service"].clone();
+            let up = res.is_ok();
+            let previous = self.services_up.insert(name.clone(), up);
+            if previous == Some(up) {
+                continue;
+            }
+
+            let alert = match res {
+                Ok(()) if previous.is_none() => continue,
+                Ok(()) => &self.config.service_alerts.up,
+                Err(err) => {
+                    tracing::warn!(service=%name, error = &*err, "service check failed");
+                    variables.insert("error".to_string(), format!("{err:#}"));
+                    &self.config.service_alerts.down
+                }
+            };
+            if let Some(alert) = alert {
+                let group = format!("{ALERT_GROUP_SERVICE_PREFIX}{name}");
+                let full = alert.prepare(group, variables).initial(initial);
                 self.notifier.notify(full).await?;
             }
         }
 
+        Ok(())
+    }
+
+    /// Alert when the connection quality crosses the degradation thresholds.
+    async fn tick_quality(
+        &mut self,
+        state: &Connectivity,
+        initial: bool,
+    ) -> Result<(), anyhow::Error> {
+        let config = &self.config.degradation;
+        if !config.enabled {
+            return Ok(());
+        }
+        if !state.is_online() {
+            // Being offline is reported by the connectivity alerts, and the
+            // losses while offline say nothing about the next connection.
+            self.quality.get_mut().clear();
+            self.degraded = false;
+            return Ok(());
+        }
+
+        let stats = self.quality.get_mut().stats();
+        let degraded = stats.is_degraded(config, self.degraded);
+        if degraded == self.degraded {
+            return Ok(());
+        }
+        tracing::debug!(?stats, degraded, "connection quality changed");
+        self.degraded = degraded;
+
+        let alert = if degraded {
+            &config.alert_degraded
+        } else {
+            &config.alert_recovered
+        };
+        if let Some(alert) = alert {
+            let millis = |d: Option<Duration>| d.unwrap_or_default().as_millis().to_string();
+            let variables = HashMap::from([
+                ("latency_ms".to_string(), millis(stats.p95)),
+                ("latency_p50_ms".to_string(), millis(stats.p50)),
+                ("loss_percent".to_string(), stats.loss_percent.to_string()),
+            ]);
+            let full = alert
+                .prepare(ALERT_GROUP_QUALITY.to_string(), variables)
+                .initial(initial);
+            self.notifier.notify(full).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Check connectivity per address family and alert on changes.
+    async fn tick_families(
+        &mut self,
+        state: &Connectivity,
+        initial: bool,
+        deadline: Instant,
+    ) -> Result<(), anyhow::Error> {
+        let config = &self.config.address_families;
+        if !config.enabled {
+            return Ok(());
+        }
+        if !state.is_online() {
+            // Being offline is reported by the connectivity alerts.
+            self.families_up.clear();
+            return Ok(());
+        }
+
+        let this = &*self;
+        let checks = config
+            .families
+            .iter()
+            .map(|&family| async move {
+                // Each family gets its own attempt budget.
+                let retrier = Retrier::new(this.config.retry.clone());
+                (family, this.check_targets(&retrier, Some(family)).await)
+            })
+            .collect::<FuturesUnordered<_>>()
+            .collect::<Vec<_>>();
+        let results = match tokio::time::timeout_at(deadline, checks).await {
+            Ok(results) => results,
+            Err(_) => config
+                .families
+                .iter()
+                .map(|&family| {
+                    let err = anyhow::anyhow!("checks exceeded the deadline");
+                    (family, Err(err))
+                })
+                .collect(),
+        };
+
+        let mut changes = Vec::new();
+        for (family, res) in results {
+            let up = res.is_ok();
+            let previous = self.families_up.insert(family, up);
+            match res {
+                // Families that work after (re)connecting are not announced.
+                Ok(()) if previous.is_none() => {}
+                _ if previous == Some(up) => {}
+                Ok(()) => changes.push((family, None)),
+                Err(err) => {
+                    tracing::warn!(%family, error = &*err, "address family check failed");
+                    changes.push((family, Some(err)));
+                }
+            }
+        }
+
+        let families_up = self.families_up_summary();
+        for (family, err) in changes {
+            let config = &self.config.address_families;
+            let alert = match &err {
+                None => &config.alert_restored,
+                Some(_) => &config.alert_lost,
+            };
+            let Some(alert) = alert else {
+                continue;
+            };
+            let mut variables = HashMap::from([
+                ("family".to_string(), family.to_string()),
+                ("families_up".to_string(), families_up.clone()),
+            ]);
+            if let Some(err) = err {
+                variables.insert("error".to_string(), format!("{err:#}"));
+            }
+            let group = format!(
+                "{ALERT_GROUP_FAMILY_PREFIX}{}",
+                family.to_string().to_lowercase()
+            );
+            let full = alert.prepare(group, variables).initial(initial);
+            self.notifier.notify(full).await?;
+        }
+
+        Ok(())
+    }
+
+    /// The working address families, eg "IPv4, IPv6" or "none".
+    fn families_up_summary(&self) -> String {
+        let up = self
+            .config
+            .address_families
+            .families
+            .iter()
+            .filter(|family| self.families_up.get(family) == Some(&true))
+            .map(|family| family.to_string())
+            .collect::<Vec<_>>();
+        if up.is_empty() {
+            "none".to_string()
+        } else {
+            up.join(", ")
+        }
+    }
+
+    /// The deadline of a check phase starting now.
+    fn deadline(&self) -> Instant {
+        Instant::now() + Duration::from_secs(self.config.check_deadline_secs)
+    }
+
+    async fn tick(&mut self) -> Result<(), anyhow::Error> {
+        // Each phase gets its own budget, so a slow diagnosis does not make
+        // the services time out.
+        let state = self.diagnose(self.deadline()).await?;
+
+        // The first check observes the initial state.
+        let initial = self.check_count == 0;
         self.check_count += 1;
 
+        self.tick_services(initial, self.deadline()).await?;
+        self.tick_proxies(initial).await?;
+        self.tick_quality(&state, initial).await?;
+        self.tick_families(&state, initial, self.deadline()).await?;
+
+        if self.state.as_ref() == Some(&state) {
+            return Ok(());
+        }
+        tracing::debug!(?state, "connectivity changed");
+
+        let mut variables = HashMap::new();
+        variables.insert("reason".to_string(), state.reason());
+        if self.config.address_families.enabled {
+            variables.insert("families_up".to_string(), self.families_up_summary());
+        }
+
+        let alert = match &state {
+            Connectivity::Online => {
+                self.offline_since = None;
+                &self.config.alert_reconnected
+            }
+            offline => {
+                if self.offline_since.is_none() {
+                    self.offline_since = Some(std::time::SystemTime::now());
+                }
+
+                let alerts = &self.config.state_alerts;
+                let specific = match offline {
+                    Connectivity::Online => unreachable!(),
+                    Connectivity::NoRoute => &alerts.no_route,
+                    Connectivity::GatewayUnreachable(gateway) => {
+                        variables.insert("gateway".to_string(), gateway.to_string());
+                        &alerts.gateway_unreachable
+                    }
+                    Connectivity::DnsBroken => &alerts.dns_broken,
+                    Connectivity::HttpBlocked => &alerts.http_blocked,
+                    Connectivity::CaptivePortal { url } => {
+                        let url = url.clone().or_else(|| {
+                            self.config
+                                .captive_portal_check
+                                .as_ref()
+                                .map(|c| c.url.to_string())
+                        });
+                        variables.insert("portal_url".to_string(), url.unwrap_or_default());
+                        &alerts.captive_portal
+                    }
+                };
+                if specific.is_some() {
+                    specific
+                } else {
+                    &self.config.alert_disconnected
+                }
+            }
+        };
+
+        if let Some(alert) = alert {
+            let full = alert
+                .prepare(ALERT_GROUP_INTERNET.to_string(), variables)
+                .initial(initial);
+            self.notifier.notify(full).await?;
+        }
+
+        self.state = Some(state);
+
         Ok(())
     }
 }
+
+/// A configured URL check or probe.
+#[derive(Clone, Copy)]
+enum Check<'a> {
+    Url(&'a CheckUrl),
+    Probe(&'a Probe),
+}
+
+impl Check<'_> {
+    fn kind(&self) -> CheckKind {
+        match self {
+            Self::Url(check) => check.kind,
+            Self::Probe(probe) => probe.kind,
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Self::Url(check) => check.name().to_string(),
+            Self::Probe(probe) => probe.name(),
+        }
+    }
+
+    fn family(&self) -> Option<AddressFamily> {
+        match self {
+            Self::Url(check) => check.family,
+            Self::Probe(probe) => probe.family,
+        }
+    }
+
+    fn target(&self) -> String {
+        match self {
+            Self::Url(check) => check.url.to_string(),
+            Self::Probe(probe) => probe.target.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::io::{Read, Write};
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    /// Serve a fixed HTTP response on a background thread.
+    fn http_server(body: &'static str) -> url::Url {
+        http_server_raw(format!(
+            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+            body.len()
+        ))
+    }
+
+    pub fn http_server_raw(response: String) -> url::Url {
+        http_server_delayed(response, Duration::ZERO)
+    }
+
+    /// Serve a fixed HTTP response after a delay.
+    pub fn http_server_delayed(response: String, delay: Duration) -> url::Url {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let response = response.clone();
+                std::thread::spawn(move || {
+                    let mut buf = [0u8; 4096];
+                    let _ = stream.read(&mut buf);
+                    std::thread::sleep(delay);
+                    let _ = stream.write_all(response.as_bytes());
+                });
+            }
+        });
+        format!("http://127.0.0.1:{port}/").parse().unwrap()
+    }
+
+    fn slow_server(delay: Duration) -> url::Url {
+        http_server_delayed(
+            "HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n".to_string(),
+            delay,
+        )
+    }
+
+    /// URL of a local port that refuses connections.
+    fn closed_url() -> url::Url {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        format!("http://127.0.0.1:{port}/").parse().unwrap()
+    }
+
+    fn online_manager(
+        url: url::Url,
+        on_startup: bool,
+    ) -> (
+        OnlineManager,
+        tokio::sync::mpsc::Receiver<crate::notify::PreparedAlert>,
+    ) {
+        let mut config = OnlineConfig {
+            urls: vec![CheckUrl::new(url)],
+            dns_servers: DnsServerSource::Disabled,
+            route_check: false,
+            captive_portal_check: None,
+            retry: cfg::RetryPolicy {
+                attempts_per_target: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        for alert in [
+            &mut config.alert_reconnected,
+            &mut config.alert_disconnected,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            alert.on_startup = on_startup;
+        }
+        let (notifier, alerts) = Notifier::test_channel();
+        let (_, check_requests) = mpsc::channel(1);
+        (
+            OnlineManager::new(config, notifier, check_requests).unwrap(),
+            alerts,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_on_startup_online() {
+        let url = http_server("hello");
+
+        let (mut manager, mut alerts) = online_manager(url.clone(), false);
+        manager.tick().await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        let (mut manager, mut alerts) = online_manager(url, true);
+        manager.tick().await.unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.alert.summary, "Internet is reachable!");
+
+        // Staying online does not alert again.
+        manager.tick().await.unwrap();
+        assert!(alerts.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_on_startup_offline() {
+        let (mut manager, mut alerts) = online_manager(closed_url(), false);
+        manager.tick().await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        let (mut manager, mut alerts) = online_manager(closed_url(), true);
+        manager.tick().await.unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert!(alert.initial);
+        assert_eq!(
+            alert.alert.summary,
+            "Internet is unreachable - system appears to be offline!"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_changes_after_startup_always_alert() {
+        let (mut manager, mut alerts) = online_manager(closed_url(), false);
+        manager.tick().await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        manager.config.urls[0].url = http_server("hello");
+        manager.tick().await.unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert!(!alert.initial);
+        assert_eq!(alert.alert.summary, "Internet is reachable!");
+    }
+
+    #[tokio::test]
+    async fn test_dns_combined_with_http() {
+        let (mut manager, mut alerts) = online_manager(http_server("hello"), true);
+        let (_socket, silent) = dns::tests::silent_server().await;
+        manager.config.dns_servers = DnsServerSource::Custom(vec![cfg::DnsServer(silent)]);
+        manager.config.dns_timeout_secs = 1;
+
+        // HTTP works, but DNS does not.
+        manager.tick().await.unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(
+            alert.alert.summary,
+            "Internet is unreachable - system appears to be offline!"
+        );
+
+        let server = dns::tests::stub_resolver(std::net::Ipv4Addr::new(10, 0, 0, 1), false).await;
+        manager.config.dns_servers = DnsServerSource::Custom(vec![cfg::DnsServer(server)]);
+        manager.tick().await.unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.alert.summary, "Internet is reachable!");
+    }
+
+    #[tokio::test]
+    async fn test_diagnose_no_route() {
+        let dir = crate::testutil::TempDir::new("online-no-route");
+        dir.write(
+            "route",
+            "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n",
+        );
+        let (mut manager, mut alerts) = online_manager(http_server("hello"), true);
+        manager.config.route_check = true;
+        manager.proc_net = dir.path().to_path_buf();
+
+        manager.tick().await.unwrap();
+        assert_eq!(manager.state, Some(Connectivity::NoRoute));
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(
+            alert.variables["reason"],
+            "no default route - not connected to a network"
+        );
+
+        // A route via a reachable gateway restores connectivity.
+        dir.write(
+            "route",
+            "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+eth0\t00000000\t0100007F\t0003\t0\t0\t100\t00000000\t0\t0\t0
+",
+        );
+        manager.tick().await.unwrap();
+        assert_eq!(manager.state, Some(Connectivity::Online));
+        assert_eq!(
+            alerts.try_recv().unwrap().alert.summary,
+            "Internet is reachable!"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_diagnose_captive_portal() {
+        let (mut manager, mut alerts) = online_manager(closed_url(), true);
+        let portal = http_server_raw(
+            "HTTP/1.1 302 Found\r\nlocation: http://login.hotel.example/\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                .to_string(),
+        );
+        manager.config.captive_portal_check = Some(cfg::CaptivePortalCheck {
+            url: portal,
+            expected_status: 204,
+            body_contains: None,
+        });
+
+        manager.tick().await.unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(
+            alert.alert.summary,
+            "Captive portal detected - log in to access the internet"
+        );
+        assert_eq!(alert.variables["portal_url"], "http://login.hotel.example/");
+        assert_eq!(
+            alert.variables["reason"],
+            "captive portal detected - login required"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_diagnose_http_blocked() {
+        let (mut manager, mut alerts) = online_manager(closed_url(), true);
+        let no_content =
+            http_server_raw("HTTP/1.1 204 No Content\r\nconnection: close\r\n\r\n".to_string());
+        manager.config.captive_portal_check = Some(cfg::CaptivePortalCheck {
+            url: no_content,
+            expected_status: 204,
+            body_contains: None,
+        });
+
+        manager.tick().await.unwrap();
+        assert_eq!(manager.state, Some(Connectivity::HttpBlocked));
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(
+            alert.alert.summary,
+            "Internet is unreachable - system appears to be offline!"
+        );
+        assert_eq!(alert.variables["reason"], "HTTP connectivity checks failed");
+    }
+
+    #[tokio::test]
+    async fn test_slow_check_does_not_block_other_tasks() {
+        let (mut manager, mut alerts) = online_manager(slow_server(Duration::from_secs(1)), true);
+
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async move {
+                // Stand-in for the other managers sharing the executor.
+                let ticks = std::rc::Rc::new(std::cell::Cell::new(0));
+                let counter = ticks.clone();
+                let other = tokio::task::spawn_local(async move {
+                    loop {
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                        counter.set(counter.get() + 1);
+                    }
+                });
+
+                let start = std::time::Instant::now();
+                manager.tick().await.unwrap();
+                assert!(start.elapsed() >= Duration::from_secs(1));
+                assert!(
+                    ticks.get() >= 50,
+                    "other task only ticked {} times",
+                    ticks.get()
+                );
+                other.abort();
+
+                assert_eq!(manager.state, Some(Connectivity::Online));
+                assert!(alerts.try_recv().is_ok());
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_http_checks_run_concurrently() {
+        let (mut manager, _alerts) = online_manager(slow_server(Duration::from_secs(2)), true);
+        manager.config.urls.push(CheckUrl {
+            body_contains: Some("hello".to_string()),
+            ..CheckUrl::new(http_server("hello"))
+        });
+
+        // The fast check wins without waiting for the slow one.
+        let start = std::time::Instant::now();
+        manager.tick().await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(manager.state, Some(Connectivity::Online));
+    }
+
+    #[tokio::test]
+    async fn test_check_deadline() {
+        let (mut manager, mut alerts) = online_manager(slow_server(Duration::from_secs(2)), true);
+        manager.config.check_deadline_secs = 1;
+        manager.config.urls.push(CheckUrl {
+            name: Some("dev-server".to_string()),
+            kind: CheckKind::Service,
+            ..CheckUrl::new(http_server("ok"))
+        });
+        manager
+            .config
+            .service_alerts
+            .down
+            .as_mut()
+            .unwrap()
+            .on_startup = true;
+
+        let start = std::time::Instant::now();
+        manager.tick().await.unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_secs(1) && elapsed < Duration::from_secs(2));
+        assert_eq!(manager.state, Some(Connectivity::HttpBlocked));
+
+        // Slow connectivity checks don't take the time of the services.
+        assert!(manager.services_up["dev-server"]);
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(
+            alert.alert.summary,
+            "Internet is unreachable - system appears to be offline!"
+        );
+        assert!(alerts.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_service_alerts() {
+        let (mut manager, mut alerts) = online_manager(http_server("hello"), false);
+        let service = CheckUrl {
+            name: Some("dashboard".to_string()),
+            kind: CheckKind::Service,
+            ..CheckUrl::new(closed_url())
+        };
+        manager.config.urls.push(service);
+        manager.config.urls.push(CheckUrl {
+            name: Some("dev-server".to_string()),
+            kind: CheckKind::Service,
+            ..CheckUrl::new(http_server("ok"))
+        });
+
+        // A failing service does not affect connectivity.
+        manager.tick().await.unwrap();
+        assert_eq!(manager.state, Some(Connectivity::Online));
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.alert.summary, "Service ${service} is down");
+        assert_eq!(alert.variables["service"], "dashboard");
+        assert!(alert.variables["error"].contains("http query failed"));
+        assert_eq!(alert.group.as_deref(), Some("panorama.service.dashboard"));
+        assert!(alert.initial);
+        assert!(alerts.try_recv().is_err());
+
+        // No repeated alert while the service stays down.
+        manager.tick().await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        manager.config.urls[1].url = http_server("hello");
+        manager.tick().await.unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.alert.summary, "Service ${service} is back up");
+        assert_eq!(alert.variables["service"], "dashboard");
+        assert!(!alert.initial);
+        assert!(alerts.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_duplicate_service_names() {
+        let service = CheckUrl {
+            name: Some("api".to_string()),
+            kind: CheckKind::Service,
+            ..CheckUrl::new("http://localhost/".parse().unwrap())
+        };
+        let config = OnlineConfig {
+            urls: vec![service.clone(), service],
+            ..Default::default()
+        };
+        let err = config.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "service check 'api' is defined multiple times"
+        );
+    }
+
+    #[test]
+    fn test_partial_alert_config() {
+        let config: OnlineConfig = serde_yaml::from_str(
+            "state_alerts:\n  no_route:\n    severity: warning\n    on_startup: false\n    summary: No route\n",
+        )
+        .unwrap();
+        assert!(config.state_alerts.no_route.is_some());
+        assert!(config.state_alerts.captive_portal.is_some());
+
+        let config: OnlineConfig = serde_yaml::from_str("service_alerts:\n  up: null\n").unwrap();
+        assert!(config.service_alerts.up.is_none());
+        assert!(config.service_alerts.down.is_some());
+
+        let config: OnlineConfig = serde_yaml::from_str("proxy_alerts:\n  up: null\n").unwrap();
+        assert!(config.proxy_alerts.up.is_none());
+        assert!(config.proxy_alerts.down.is_some());
+    }
+
+    #[test]
+    fn test_retry_config() {
+        let config: OnlineConfig =
+            serde_yaml::from_str("retry:\n  attempts_per_target: 5\n").unwrap();
+        assert_eq!(config.retry.attempts_per_target, 5);
+        assert_eq!(config.retry.max_total_attempts, 6);
+        assert!(config.retry.fail_fast_on_dns_error);
+
+        // Options of older versions.
+        let config: OnlineConfig =
+            serde_yaml::from_str("retry_count: 1\nretry_interval_seconds: 4\n").unwrap();
+        let retry = config.validate().unwrap().retry;
+        assert_eq!(retry.attempts_per_target, 2);
+        assert_eq!(retry.max_total_attempts, 2);
+        assert_eq!(retry.backoff_initial_ms, 4000);
+        assert_eq!(retry.backoff_multiplier, 1);
+        assert_eq!(retry.backoff_max_ms, 4000);
+    }
+
+    #[tokio::test]
+    async fn test_tcp_probes() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let tcp = |port| {
+            Probe::new(cfg::ProbeTarget::Tcp {
+                host: "127.0.0.1".to_string(),
+                port,
+            })
+        };
+
+        // A probe alone is enough to determine connectivity.
+        let (mut manager, mut alerts) = online_manager(closed_url(), false);
+        manager.config.urls.clear();
+        manager.config.probes.push(tcp(port));
+        manager.config.probes.push(Probe {
+            kind: CheckKind::Service,
+            ..tcp(closed_url().port().unwrap())
+        });
+
+        manager.tick().await.unwrap();
+        assert_eq!(manager.state, Some(Connectivity::Online));
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.alert.summary, "Service ${service} is down");
+        assert_eq!(alert.variables["service"], alert.variables["target"]);
+        assert!(alert.variables["target"].starts_with("tcp://127.0.0.1:"));
+        assert!(!alert.variables.contains_key("url"));
+
+        drop(listener);
+        manager.tick().await.unwrap();
+        assert_eq!(manager.state, Some(Connectivity::HttpBlocked));
+    }
+
+    #[tokio::test]
+    async fn test_degraded_by_packet_loss() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed_port = closed_url().port().unwrap();
+        let tcp = |port| {
+            Probe::new(cfg::ProbeTarget::Tcp {
+                host: "127.0.0.1".to_string(),
+                port,
+            })
+        };
+
+        let (mut manager, mut alerts) = online_manager(closed_url(), false);
+        manager.config.urls.clear();
+        manager.config.degradation.min_samples = 4;
+        manager.quality = RefCell::new(QualityTracker::new(4));
+        // Retries are not recorded as samples.
+        manager.config.retry.attempts_per_target = 3;
+        manager.config.retry.backoff_initial_ms = 1;
+        manager.config.retry.backoff_max_ms = 1;
+        manager
+            .config
+            .probes
+            .push(tcp(listener.local_addr().unwrap().port()));
+        manager.config.probes.push(tcp(closed_port));
+
+        // Not enough samples yet.
+        manager.tick().await.unwrap();
+        assert_eq!(manager.state, Some(Connectivity::Online));
+        assert!(alerts.try_recv().is_err());
+
+        manager.tick().await.unwrap();
+        assert!(manager.degraded);
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.alert.summary, "Internet connection is degraded");
+        assert_eq!(alert.variables["loss_percent"], "50");
+        assert!(!alert.initial);
+
+        // Degraded until the losses have left the window.
+        let _second = std::net::TcpListener::bind(("127.0.0.1", closed_port)).unwrap();
+        for _ in 0..3 {
+            manager.tick().await.unwrap();
+            assert!(manager.degraded);
+        }
+        assert!(alerts.try_recv().is_err());
+
+        manager.tick().await.unwrap();
+        assert!(!manager.degraded);
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.alert.summary, "Internet connection quality recovered");
+        assert_eq!(alert.variables["loss_percent"], "0");
+    }
+
+    #[tokio::test]
+    async fn test_address_families() {
+        let v4 = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let v6 = std::net::TcpListener::bind("[::1]:0").unwrap();
+        let v6_port = v6.local_addr().unwrap().port();
+        let tcp = |host: &str, port| {
+            Probe::new(cfg::ProbeTarget::Tcp {
+                host: host.to_string(),
+                port,
+            })
+        };
+
+        let (mut manager, mut alerts) = online_manager(closed_url(), false);
+        manager.config.urls.clear();
+        manager.config.address_families.enabled = true;
+        manager.config.degradation.enabled = false;
+        manager
+            .config
+            .probes
+            .push(tcp("127.0.0.1", v4.local_addr().unwrap().port()));
+        manager.config.probes.push(tcp("::1", v6_port));
+
+        manager.tick().await.unwrap();
+        assert!(alerts.try_recv().is_err());
+        assert_eq!(manager.families_up_summary(), "IPv4, IPv6");
+
+        drop(v6);
+        manager.tick().await.unwrap();
+        assert_eq!(manager.state, Some(Connectivity::Online));
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.alert.summary, "${family} connectivity lost");
+        assert_eq!(alert.group.as_deref(), Some("panorama.internet.ipv6"));
+        assert_eq!(alert.variables["family"], "IPv6");
+        assert_eq!(alert.variables["families_up"], "IPv4");
+        assert!(alert.variables["error"].contains(&format!("tcp://::1:{v6_port}")));
+        assert!(alerts.try_recv().is_err());
+
+        let _v6 = std::net::TcpListener::bind(("::1", v6_port)).unwrap();
+        manager.tick().await.unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.alert.summary, "${family} connectivity restored");
+        assert_eq!(alert.variables["families_up"], "IPv4, IPv6");
+
+        // Offline clears the state, so the families are not announced again
+        // when reconnecting.
+        drop(v4);
+        drop(_v6);
+        manager.tick().await.unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.variables["families_up"], "none");
+        assert!(alerts.try_recv().is_err());
+        assert!(manager.families_up.is_empty());
+    }
+
+    #[test]
+    fn test_address_family_without_checks() {
+        let config = OnlineConfig {
+            urls: vec![CheckUrl {
+                family: Some(AddressFamily::Ipv4),
+                ..CheckUrl::new("http://localhost/".parse().unwrap())
+            }],
+            address_families: cfg::AddressFamilyChecks {
+                enabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let err = config.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "'online.address_families': no connectivity check can use IPv6"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_proxy_also_direct() {
+        let url = http_server("hello");
+        let (mut manager, mut alerts) = online_manager(url.clone(), false);
+        let closed = closed_url();
+        let check = CheckUrl {
+            proxy: Some(ProxySetting::Url(closed.to_string())),
+            also_direct: true,
+            ..CheckUrl::new(url)
+        };
+        manager.config.urls = vec![check];
+
+        // Only the direct request succeeds.
+        manager.tick().await.unwrap();
+        assert_eq!(manager.state, Some(Connectivity::Online));
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.alert.summary, "Proxy ${proxy} is down");
+        let proxy = closed.to_string().trim_end_matches('/').to_string();
+        assert_eq!(alert.variables["proxy"], proxy);
+        assert_eq!(
+            alert.group.as_deref(),
+            Some(format!("panorama.proxy.{proxy}").as_str())
+        );
+
+        manager.tick().await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        // The test server answers proxied requests as well.
+        let proxy = http_server("proxied");
+        manager.config.urls[0].proxy = Some(ProxySetting::Url(proxy.to_string()));
+        manager
+            .proxies_up
+            .insert(proxy.to_string().trim_end_matches('/').to_string(), false);
+        manager.tick().await.unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.alert.summary, "Proxy ${proxy} is back up");
+    }
+}
@@ -0,0 +1,258 @@
+//! TCP connect and ICMP echo probes.
+
+use std::{
+    net::{IpAddr, SocketAddr, UdpSocket},
+    sync::atomic::{AtomicU16, Ordering},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use socket2::{Domain, Protocol, Socket, Type};
+
+use super::{
+    cfg::{AddressFamily, Probe, ProbeTarget},
+    retry::ProbeError,
+};
+
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+/// Run a probe, returning the round trip time.
+pub async fn run(probe: &Probe) -> Result<Duration, ProbeError> {
+    let timeout = Duration::from_millis(probe.timeout_ms);
+    match &probe.target {
+        ProbeTarget::Tcp { host, port } => {
+            let addr = resolve(host, *port, probe.family, timeout)
+                .await
+                .map_err(ProbeError::Dns)?;
+            tcp_connect(addr, timeout).await.map_err(ProbeError::Other)
+        }
+        ProbeTarget::Icmp { host } => {
+            let addr = resolve(host, 0, probe.family, timeout)
+                .await
+                .map_err(ProbeError::Dns)?;
+            tokio::task::spawn_blocking(move || ping(addr.ip(), timeout))
+                .await
+                .context("ICMP probe task failed")
+                .and_then(|x| x)
+                .map_err(ProbeError::Other)
+        }
+    }
+}
+
+/// Resolve `host`, only considering addresses of `family` if set.
+async fn resolve(
+    host: &str,
+    port: u16,
+    family: Option<AddressFamily>,
+    timeout: Duration,
+) -> Result<SocketAddr, anyhow::Error> {
+    let matches = |addr: &SocketAddr| family.is_none_or(|f| f.matches(&addr.ip()));
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        let addr = SocketAddr::new(ip, port);
+        if !matches(&addr) {
+            anyhow::bail!("'{host}' is not an {} address", family.unwrap());
+        }
+        return Ok(addr);
+    }
+    let mut addrs = tokio::time::timeout(timeout, tokio::net::lookup_host((host, port)))
+        .await
+        .with_context(|| format!("resolving '{host}' timed out"))?
+        .with_context(|| format!("could not resolve '{host}'"))?;
+    match family {
+        Some(family) => addrs
+            .find(matches)
+            .with_context(|| format!("'{host}' has no {family} addresses")),
+        None => addrs
+            .next()
+            .with_context(|| format!("'{host}' has no addresses")),
+    }
+}
+
+async fn tcp_connect(addr: SocketAddr, timeout: Duration) -> Result<Duration, anyhow::Error> {
+    let start = Instant::now();
+    tokio::time::timeout(timeout, tokio::net::TcpStream::connect(addr))
+        .await
+        .with_context(|| format!("connecting to {addr} timed out after {timeout:?}"))?
+        .with_context(|| format!("could not connect to {addr}"))?;
+    Ok(start.elapsed())
+}
+
+/// Send an ICMP echo request through an unprivileged datagram socket and
+/// wait for the reply.
+fn ping(ip: IpAddr, timeout: Duration) -> Result<Duration, anyhow::Error> {
+    static SEQUENCE: AtomicU16 = AtomicU16::new(0);
+
+    let (domain, protocol, request_type, reply_type) = match ip {
+        IpAddr::V4(_) => (
+            Domain::IPV4,
+            Protocol::ICMPV4,
+            ICMP_ECHO_REQUEST,
+            ICMP_ECHO_REPLY,
+        ),
+        IpAddr::V6(_) => (
+            Domain::IPV6,
+            Protocol::ICMPV6,
+            ICMPV6_ECHO_REQUEST,
+            ICMPV6_ECHO_REPLY,
+        ),
+    };
+
+    let socket = Socket::new(domain, Type::DGRAM, Some(protocol)).map_err(|err| {
+        if err.kind() == std::io::ErrorKind::PermissionDenied {
+            anyhow::anyhow!(
+                "unprivileged ICMP sockets are not permitted - check the net.ipv4.ping_group_range sysctl"
+            )
+        } else {
+            anyhow::Error::new(err).context("could not create ICMP socket")
+        }
+    })?;
+    let socket = UdpSocket::from(socket);
+    socket.connect(SocketAddr::new(ip, 0))?;
+    socket.set_write_timeout(Some(timeout))?;
+
+    let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+    let start = Instant::now();
+    socket
+        .send(&echo_request(request_type, sequence))
+        .with_context(|| format!("could not send ICMP echo request to {ip}"))?;
+
+    // The kernel filters replies by identifier, but replies to earlier timed
+    // out requests may still arrive.
+    let mut buf = [0u8; 1500];
+    loop {
+        let remaining = timeout.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            anyhow::bail!("no ICMP echo reply from {ip} within {timeout:?}");
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let len = match socket.recv(&mut buf) {
+            Ok(len) => len,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                anyhow::bail!("no ICMP echo reply from {ip} within {timeout:?}");
+            }
+            Err(err) => return Err(err).context("could not receive ICMP echo reply"),
+        };
+        if is_echo_reply(&buf[..len], reply_type, sequence) {
+            return Ok(start.elapsed());
+        }
+    }
+}
+
+/// Build an ICMP echo request.
+/// The identifier is set by the kernel for datagram sockets.
+fn echo_request(kind: u8, sequence: u16) -> Vec<u8> {
+    let mut packet = vec![kind, 0, 0, 0, 0, 0];
+    packet.extend_from_slice(&sequence.to_be_bytes());
+    packet.extend_from_slice(b"panorama");
+    let checksum = checksum(&packet);
+    packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    packet
+}
+
+fn is_echo_reply(packet: &[u8], kind: u8, sequence: u16) -> bool {
+    packet.len() >= 8 && packet[0] == kind && packet[6..8] == sequence.to_be_bytes()
+}
+
+/// Internet checksum (RFC 1071).
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|c| u32::from(u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)])))
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn tcp_probe(port: u16) -> Probe {
+        Probe {
+            timeout_ms: 1000,
+            ..Probe::new(ProbeTarget::Tcp {
+                host: "127.0.0.1".to_string(),
+                port,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tcp_probe() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        run(&tcp_probe(port)).await.unwrap();
+
+        drop(listener);
+        let err = run(&tcp_probe(port)).await.unwrap_err();
+        assert!(matches!(err, ProbeError::Other(_)));
+    }
+
+    #[tokio::test]
+    async fn test_tcp_probe_resolves_host() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let probe = Probe::new(ProbeTarget::Tcp {
+            host: "localhost".to_string(),
+            port,
+        });
+        run(&probe).await.unwrap();
+
+        let probe = Probe::new(ProbeTarget::Tcp {
+            host: "does-not-exist.invalid".to_string(),
+            port,
+        });
+        let err = run(&probe).await.unwrap_err();
+        assert!(matches!(err, ProbeError::Dns(_)));
+    }
+
+    #[tokio::test]
+    async fn test_icmp_probe() {
+        let probe = Probe::new(ProbeTarget::Icmp {
+            host: "127.0.0.1".to_string(),
+        });
+        // Ping sockets are not permitted on all systems.
+        if let Err(err) = run(&probe).await {
+            let ProbeError::Other(err) = err else {
+                panic!("unexpected error: {err:?}");
+            };
+            assert!(err.to_string().contains("ping_group_range"), "{err:#}");
+        }
+    }
+
+    #[test]
+    fn test_echo_request() {
+        let packet = echo_request(ICMP_ECHO_REQUEST, 0x1234);
+        assert_eq!(&packet[..8], &[8, 0, 0x27, 0x38, 0, 0, 0x12, 0x34]);
+        assert_eq!(&packet[8..], b"panorama");
+        // A packet including its checksum sums up to zero.
+        assert_eq!(checksum(&packet), 0);
+
+        assert!(is_echo_reply(&[0, 0, 0, 0, 0, 1, 0x12, 0x34], 0, 0x1234));
+        assert!(!is_echo_reply(&[0, 0, 0, 0, 0, 1, 0x12, 0x35], 0, 0x1234));
+        assert!(!is_echo_reply(&[3, 0, 0, 0, 0, 1, 0x12, 0x34], 0, 0x1234));
+        assert!(!is_echo_reply(&[0, 0, 0], 0, 0x1234));
+    }
+
+    #[test]
+    fn test_config() {
+        let yaml = "- type: tcp\n  host: 10.0.0.1\n  port: 22\n  kind: service\n- type: icmp\n  host: 1.1.1.1\n  timeout_ms: 500\n";
+        let probes: Vec<Probe> = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(probes[0].name(), "tcp://10.0.0.1:22");
+        assert_eq!(probes[0].timeout_ms, 3000);
+        assert_eq!(probes[1].name(), "icmp://1.1.1.1");
+        assert_eq!(probes[1].timeout_ms, 500);
+    }
+}
@@ -0,0 +1,195 @@
+//! Connection quality tracking based on probe round trip times.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use super::cfg::DegradationConfig;
+
+/// Rolling window of probe results per target.
+/// A sample of `None` is a lost probe.
+#[derive(Debug)]
+pub struct QualityTracker {
+    window_size: usize,
+    samples: HashMap<String, VecDeque<Option<Duration>>>,
+}
+
+impl QualityTracker {
+    pub fn new(window_size: usize) -> Self {
+        Self {
+            window_size,
+            samples: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, target: &str, rtt: Option<Duration>) {
+        let samples = self.samples.entry(target.to_string()).or_default();
+        if samples.len() >= self.window_size {
+            samples.pop_front();
+        }
+        samples.push_back(rtt);
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Statistics across all targets.
+    pub fn stats(&self) -> QualityStats {
+        let mut rtts = Vec::new();
+        let mut total = 0;
+        for sample in self.samples.values().flatten() {
+            total += 1;
+            rtts.extend(*sample);
+        }
+        rtts.sort();
+
+        let lost = total - rtts.len();
+        QualityStats {
+            samples: total,
+            p50: percentile(&rtts, 50),
+            p95: percentile(&rtts, 95),
+            loss_percent: (lost * 100).checked_div(total).unwrap_or(0) as u8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct QualityStats {
+    pub samples: usize,
+    pub p50: Option<Duration>,
+    pub p95: Option<Duration>,
+    pub loss_percent: u8,
+}
+
+impl QualityStats {
+    /// Whether the connection should be considered degraded.
+    ///
+    /// Uses the stricter `*_exit` thresholds while already degraded, so the
+    /// state doesn't flap around a single threshold.
+    pub fn is_degraded(&self, config: &DegradationConfig, degraded: bool) -> bool {
+        if self.samples < config.min_samples {
+            return degraded;
+        }
+        let (latency_ms, loss_percent) = if degraded {
+            (config.latency_ms_exit, config.loss_percent_exit)
+        } else {
+            (config.latency_ms_enter, config.loss_percent_enter)
+        };
+        let latency = self
+            .p95
+            .is_some_and(|p95| p95 > Duration::from_millis(latency_ms));
+        latency || self.loss_percent > loss_percent
+    }
+}
+
+/// Nearest-rank percentile of sorted values.
+fn percentile(sorted: &[Duration], percent: usize) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (percent * sorted.len()).div_ceil(100).max(1);
+    Some(sorted[rank - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn ms(ms: u64) -> Option<Duration> {
+        Some(Duration::from_millis(ms))
+    }
+
+    fn config() -> DegradationConfig {
+        DegradationConfig {
+            min_samples: 4,
+            latency_ms_enter: 300,
+            latency_ms_exit: 200,
+            loss_percent_enter: 20,
+            loss_percent_exit: 10,
+            ..Default::default()
+        }
+    }
+
+    fn stats(p95: u64, loss_percent: u8) -> QualityStats {
+        QualityStats {
+            samples: 10,
+            p50: ms(p95),
+            p95: ms(p95),
+            loss_percent,
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut tracker = QualityTracker::new(10);
+        assert_eq!(
+            tracker.stats(),
+            QualityStats {
+                samples: 0,
+                p50: None,
+                p95: None,
+                loss_percent: 0,
+            }
+        );
+
+        for rtt in 1..=9 {
+            tracker.record("a", ms(rtt * 10));
+        }
+        tracker.record("b", None);
+        tracker.record("b", ms(1000));
+        tracker.record("b", None);
+
+        assert_eq!(
+            tracker.stats(),
+            QualityStats {
+                samples: 12,
+                p50: ms(50),
+                p95: ms(1000),
+                loss_percent: 16,
+            }
+        );
+    }
+
+    #[test]
+    fn test_rolling_window() {
+        let mut tracker = QualityTracker::new(3);
+        tracker.record("a", None);
+        tracker.record("a", None);
+        for _ in 0..3 {
+            tracker.record("a", ms(10));
+        }
+        let stats = tracker.stats();
+        assert_eq!(stats.samples, 3);
+        assert_eq!(stats.loss_percent, 0);
+
+        tracker.clear();
+        assert_eq!(tracker.stats().samples, 0);
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let config = config();
+
+        assert!(!stats(250, 0).is_degraded(&config, false));
+        assert!(stats(350, 0).is_degraded(&config, false));
+        assert!(stats(0, 25).is_degraded(&config, false));
+
+        // Between the thresholds the current state is kept.
+        assert!(stats(250, 0).is_degraded(&config, true));
+        assert!(stats(0, 15).is_degraded(&config, true));
+        assert!(!stats(0, 15).is_degraded(&config, false));
+        assert!(!stats(150, 5).is_degraded(&config, true));
+
+        // Too few samples don't change the state.
+        let few = QualityStats {
+            samples: 3,
+            ..stats(1000, 100)
+        };
+        assert!(!few.is_degraded(&config, false));
+        assert!(few.is_degraded(&config, true));
+    }
+}
@@ -0,0 +1,258 @@
+//! Retry execution for connectivity probes.
+
+use std::{cell::Cell, future::Future, rc::Rc, time::Duration};
+
+use super::cfg::RetryPolicy;
+
+/// Error returned by a single probe attempt.
+#[derive(Debug)]
+pub enum ProbeError {
+    /// The target host name could not be resolved.
+    Dns(anyhow::Error),
+    Other(anyhow::Error),
+}
+
+impl ProbeError {
+    /// Classify an error returned by an HTTP request.
+    pub fn from_http(err: anyhow::Error) -> Self {
+        match err.downcast_ref::<ureq::Error>() {
+            Some(ureq::Error::Transport(t)) if t.kind() == ureq::ErrorKind::Dns => Self::Dns(err),
+            _ => Self::Other(err),
+        }
+    }
+
+    pub fn into_inner(self) -> anyhow::Error {
+        match self {
+            Self::Dns(err) | Self::Other(err) => err,
+        }
+    }
+}
+
+/// Runs probes according to a [`RetryPolicy`].
+///
+/// Clones share the total attempt budget, so a single `Retrier` should be
+/// created per check run and cloned for each concurrently probed target.
+#[derive(Clone, Debug)]
+pub struct Retrier {
+    policy: RetryPolicy,
+    attempts_used: Rc<Cell<u32>>,
+}
+
+impl Retrier {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self {
+            policy,
+            attempts_used: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// Number of attempts made so far, across all targets.
+    pub fn attempts_used(&self) -> u32 {
+        self.attempts_used.get()
+    }
+
+    /// Run `probe` against a single target until it succeeds, the per-target
+    /// attempts or the shared total budget are used up, or a DNS error occurs
+    /// with `fail_fast_on_dns_error` enabled.
+    pub async fn run<T, F, Fut>(&self, mut probe: F) -> Result<T, anyhow::Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, ProbeError>>,
+    {
+        let mut attempt = 0;
+        loop {
+            if self.attempts_used.get() >= self.policy.max_total_attempts {
+                anyhow::bail!(
+                    "retry budget of {} attempts exhausted",
+                    self.policy.max_total_attempts
+                );
+            }
+            self.attempts_used.set(self.attempts_used.get() + 1);
+            attempt += 1;
+
+            let err = match probe().await {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+
+            let fail_fast = matches!(err, ProbeError::Dns(_)) && self.policy.fail_fast_on_dns_error;
+            let exhausted = attempt >= self.policy.attempts_per_target
+                || self.attempts_used.get() >= self.policy.max_total_attempts;
+            if fail_fast || exhausted {
+                return Err(err.into_inner());
+            }
+
+            let delay = self.policy.backoff(attempt, random_unit());
+            tracing::trace!(attempt, ?delay, error = %err.into_inner(), "probe failed - retrying");
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the retry following the given (1-based) failed attempt.
+    ///
+    /// `random` is a value in `0.0..1.0` used to apply the jitter.
+    pub fn backoff(&self, attempt: u32, random: f64) -> Duration {
+        let exp = self
+            .backoff_multiplier
+            .saturating_pow(attempt.saturating_sub(1));
+        let base = self
+            .backoff_initial_ms
+            .saturating_mul(exp as u64)
+            .min(self.backoff_max_ms);
+
+        let jitter = f64::from(self.jitter_percent.min(100)) / 100.0;
+        let factor = 1.0 + jitter * (random * 2.0 - 1.0);
+        Duration::from_millis((base as f64 * factor).round() as u64)
+    }
+}
+
+/// Random value in `0.0..1.0`.
+///
+/// Jitter does not need a proper RNG, the randomly keyed std hasher is
+/// good enough.
+fn random_unit() -> f64 {
+    use std::hash::{BuildHasher, Hasher};
+
+    let value = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    (value >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            attempts_per_target: 3,
+            max_total_attempts: 5,
+            backoff_initial_ms: 1000,
+            backoff_multiplier: 2,
+            backoff_max_ms: 3000,
+            jitter_percent: 0,
+            fail_fast_on_dns_error: true,
+        }
+    }
+
+    /// Probe that fails the first `failures` attempts.
+    fn fake_probe(
+        calls: &Cell<u32>,
+        failures: u32,
+        dns: bool,
+    ) -> impl FnMut() -> std::future::Ready<Result<u32, ProbeError>> + '_ {
+        move || {
+            calls.set(calls.get() + 1);
+            let res = if calls.get() <= failures {
+                let err = anyhow::anyhow!("attempt {} failed", calls.get());
+                Err(if dns {
+                    ProbeError::Dns(err)
+                } else {
+                    ProbeError::Other(err)
+                })
+            } else {
+                Ok(calls.get())
+            };
+            std::future::ready(res)
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = policy();
+        assert_eq!(policy.backoff(1, 0.5), Duration::from_secs(1));
+        assert_eq!(policy.backoff(2, 0.5), Duration::from_secs(2));
+        // Capped at the maximum.
+        assert_eq!(policy.backoff(3, 0.5), Duration::from_secs(3));
+        assert_eq!(policy.backoff(30, 0.5), Duration::from_secs(3));
+
+        let jittered = RetryPolicy {
+            jitter_percent: 20,
+            ..policy
+        };
+        assert_eq!(jittered.backoff(1, 0.0), Duration::from_millis(800));
+        assert_eq!(jittered.backoff(1, 0.5), Duration::from_millis(1000));
+        assert_eq!(jittered.backoff(1, 0.999), Duration::from_millis(1200));
+    }
+
+    #[test]
+    fn test_random_unit() {
+        for _ in 0..100 {
+            let value = random_unit();
+            assert!((0.0..1.0).contains(&value));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries_until_success() {
+        let retrier = Retrier::new(policy());
+        let calls = Cell::new(0);
+        let start = tokio::time::Instant::now();
+
+        let value = retrier.run(fake_probe(&calls, 2, false)).await.unwrap();
+        assert_eq!(value, 3);
+        assert_eq!(retrier.attempts_used(), 3);
+        // 1s + 2s of backoff.
+        assert_eq!(start.elapsed(), Duration::from_secs(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_attempts_per_target() {
+        let retrier = Retrier::new(policy());
+        let calls = Cell::new(0);
+
+        let err = retrier
+            .run(fake_probe(&calls, 10, false))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "attempt 3 failed");
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_total_attempts_shared() {
+        let retrier = Retrier::new(policy());
+
+        let first = Cell::new(0);
+        retrier
+            .run(fake_probe(&first, 10, false))
+            .await
+            .unwrap_err();
+        assert_eq!(first.get(), 3);
+
+        // Only two attempts of the budget are left for the second target.
+        let second = Cell::new(0);
+        let err = retrier
+            .clone()
+            .run(fake_probe(&second, 10, false))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "attempt 2 failed");
+        assert_eq!(second.get(), 2);
+
+        let third = Cell::new(0);
+        let err = retrier.run(fake_probe(&third, 0, false)).await.unwrap_err();
+        assert_eq!(err.to_string(), "retry budget of 5 attempts exhausted");
+        assert_eq!(third.get(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fail_fast_on_dns_error() {
+        let retrier = Retrier::new(policy());
+        let calls = Cell::new(0);
+        retrier.run(fake_probe(&calls, 10, true)).await.unwrap_err();
+        assert_eq!(calls.get(), 1);
+
+        let retrier = Retrier::new(RetryPolicy {
+            fail_fast_on_dns_error: false,
+            ..policy()
+        });
+        let calls = Cell::new(0);
+        retrier.run(fake_probe(&calls, 10, true)).await.unwrap_err();
+        assert_eq!(calls.get(), 3);
+    }
+}
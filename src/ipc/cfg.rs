@@ -0,0 +1,42 @@
+use std::path::PathBuf;
+
+use serde_derive::{Deserialize, Serialize};
+
+/// Control socket used by CLI commands like `--travel-mode` to talk to a
+/// running daemon.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IpcConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Path of the unix socket.
+    /// Defaults to `$XDG_RUNTIME_DIR/panorama.sock`.
+    pub socket_path: Option<PathBuf>,
+}
+
+impl IpcConfig {
+    /// There is no fallback without `$XDG_RUNTIME_DIR`, since a predictable
+    /// path in a shared directory like /tmp could be taken by other users.
+    pub fn socket_path(&self) -> Result<PathBuf, anyhow::Error> {
+        if let Some(path) = &self.socket_path {
+            return Ok(path.clone());
+        }
+
+        match std::env::var_os("XDG_RUNTIME_DIR") {
+            Some(dir) => Ok(PathBuf::from(dir).join("panorama.sock")),
+            None => anyhow::bail!("$XDG_RUNTIME_DIR is not set - configure 'ipc.socket_path'"),
+        }
+    }
+}
+
+impl Default for IpcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            socket_path: None,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
@@ -0,0 +1,257 @@
+//! Control socket for a running daemon.
+//!
+//! The protocol is line based: the client sends a single request line and
+//! receives a single response line, either `ok` or `error: <message>`.
+
+pub mod cfg;
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::{mpsc, oneshot},
+};
+
+use crate::power::PowerCommand;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Request {
+    /// Temporarily allow the battery to charge to 100%.
+    TravelMode(bool),
+}
+
+impl Request {
+    pub fn parse(line: &str) -> Result<Self, anyhow::Error> {
+        let mut parts = line.split_whitespace();
+        let cmd = parts.next().context("empty request")?;
+        let req = match cmd {
+            "travel-mode" => {
+                let value = parts.next().context("travel-mode requires 'on' or 'off'")?;
+                Self::TravelMode(parse_on_off(value)?)
+            }
+            other => anyhow::bail!("unknown command '{other}'"),
+        };
+        if let Some(extra) = parts.next() {
+            anyhow::bail!("unexpected argument '{extra}'");
+        }
+        Ok(req)
+    }
+
+    fn to_line(&self) -> String {
+        match self {
+            Self::TravelMode(true) => "travel-mode on\n".to_string(),
+            Self::TravelMode(false) => "travel-mode off\n".to_string(),
+        }
+    }
+}
+
+pub fn parse_on_off(value: &str) -> Result<bool, anyhow::Error> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        other => anyhow::bail!("expected 'on' or 'off', got '{other}'"),
+    }
+}
+
+/// Channels to the managers that can be controlled over the socket.
+#[derive(Clone, Default)]
+pub(crate) struct Handlers {
+    pub power: Option<mpsc::Sender<PowerCommand>>,
+}
+
+impl Handlers {
+    async fn dispatch(&self, request: Request) -> Result<(), anyhow::Error> {
+        match request {
+            Request::TravelMode(enabled) => {
+                let power = self
+                    .power
+                    .as_ref()
+                    .context("power monitoring is not enabled")?;
+                let (reply, rx) = oneshot::channel();
+                power
+                    .send(PowerCommand::SetTravelMode { enabled, reply })
+                    .await
+                    .map_err(|_| anyhow::anyhow!("power manager is not running"))?;
+                rx.await.context("power manager did not respond")?
+            }
+        }
+    }
+}
+
+/// Listen on the control socket and dispatch requests until an error occurs.
+pub(crate) async fn serve(path: PathBuf, handlers: Handlers) -> Result<(), anyhow::Error> {
+    if path.exists() {
+        // Only sockets of crashed daemons are replaced.
+        if UnixStream::connect(&path).await.is_ok() {
+            anyhow::bail!(
+                "another panorama daemon is listening on '{}'",
+                path.display()
+            );
+        }
+        std::fs::remove_file(&path)
+            .with_context(|| format!("could not remove stale socket '{}'", path.display()))?;
+    }
+    let listener = UnixListener::bind(&path)
+        .with_context(|| format!("could not listen on socket '{}'", path.display()))?;
+    tracing::debug!(path=%path.display(), "listening on control socket");
+
+    loop {
+        let (stream, _addr) = listener
+            .accept()
+            .await
+            .context("could not accept control connection")?;
+        let handlers = handlers.clone();
+        tokio::task::spawn_local(async move {
+            if let Err(err) = handle_connection(stream, &handlers).await {
+                tracing::warn!(error = &*err, "control connection failed");
+            }
+        });
+    }
+}
+
+async fn handle_connection(stream: UnixStream, handlers: &Handlers) -> Result<(), anyhow::Error> {
+    let (read, mut write) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(read)
+        .read_line(&mut line)
+        .await
+        .context("could not read request")?;
+
+    let res = match Request::parse(&line) {
+        Ok(request) => {
+            tracing::info!(?request, "received control request");
+            handlers.dispatch(request).await
+        }
+        Err(err) => Err(err),
+    };
+    let response = match res {
+        Ok(()) => "ok\n".to_string(),
+        Err(err) => format!("error: {err:#}\n"),
+    };
+    write
+        .write_all(response.as_bytes())
+        .await
+        .context("could not write response")?;
+
+    Ok(())
+}
+
+/// Send a request to a running daemon.
+pub async fn send(path: &Path, request: &Request) -> Result<(), anyhow::Error> {
+    let stream = UnixStream::connect(path).await.with_context(|| {
+        format!(
+            "could not connect to '{}' - is panorama running?",
+            path.display()
+        )
+    })?;
+    let (read, mut write) = stream.into_split();
+    write
+        .write_all(request.to_line().as_bytes())
+        .await
+        .context("could not send request")?;
+
+    let mut line = String::new();
+    BufReader::new(read)
+        .read_line(&mut line)
+        .await
+        .context("could not read response")?;
+
+    match line.trim() {
+        "ok" => Ok(()),
+        other => {
+            let msg = other.strip_prefix("error: ").unwrap_or(other);
+            Err(anyhow::anyhow!("{msg}"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        assert_eq!(
+            Request::parse("travel-mode on\n").unwrap(),
+            Request::TravelMode(true)
+        );
+        assert_eq!(
+            Request::parse(&Request::TravelMode(false).to_line()).unwrap(),
+            Request::TravelMode(false)
+        );
+        assert!(Request::parse("travel-mode maybe").is_err());
+        assert!(Request::parse("travel-mode on now").is_err());
+        assert!(Request::parse("reboot").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_travel_mode_roundtrip() {
+        let path = std::env::temp_dir().join(format!("panorama-ipc-{}.sock", std::process::id()));
+        let (tx, mut rx) = mpsc::channel(1);
+        let handlers = Handlers { power: Some(tx) };
+
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async move {
+                tokio::task::spawn_local(serve(path.clone(), handlers));
+                tokio::task::spawn_local(async move {
+                    while let Some(PowerCommand::SetTravelMode { enabled, reply }) = rx.recv().await
+                    {
+                        let res = if enabled {
+                            Ok(())
+                        } else {
+                            Err(anyhow::anyhow!("not in travel mode"))
+                        };
+                        reply.send(res).ok();
+                    }
+                });
+                while !path.exists() {
+                    tokio::task::yield_now().await;
+                }
+
+                send(&path, &Request::TravelMode(true)).await.unwrap();
+                let err = send(&path, &Request::TravelMode(false)).await.unwrap_err();
+                assert_eq!(err.to_string(), "not in travel mode");
+
+                std::fs::remove_file(&path).ok();
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_socket_in_use() {
+        let path =
+            std::env::temp_dir().join(format!("panorama-ipc-{}-used.sock", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let err = serve(path.clone(), Handlers::default()).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "another panorama daemon is listening on '{}'",
+                path.display()
+            )
+        );
+        assert!(path.exists());
+
+        // A stale socket is replaced.
+        drop(listener);
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                tokio::task::spawn_local(serve(path.clone(), Handlers::default()));
+                while UnixStream::connect(&path).await.is_err() {
+                    tokio::task::yield_now().await;
+                }
+                let err = send(&path, &Request::TravelMode(true)).await.unwrap_err();
+                assert_eq!(err.to_string(), "power monitoring is not enabled");
+            })
+            .await;
+        std::fs::remove_file(&path).ok();
+    }
+}
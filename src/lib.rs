@@ -1,16 +1,29 @@
+mod block;
 pub mod cfg;
+mod data_usage;
+mod display;
 mod fs;
 mod internet;
+pub mod ipc;
+mod network;
 mod notify;
 mod power;
+mod public_ip;
+#[cfg(test)]
+mod testutil;
 mod udev;
+mod usb;
+mod vpn;
+mod wifi;
 
 use anyhow::Context;
 use cfg::Config;
 use futures::{future::LocalBoxFuture, stream::FuturesUnordered, StreamExt};
 use power::PowerManager;
 
-use crate::internet::OnlineManager;
+use crate::{internet::OnlineManager, network::NetworkManager};
+
+pub use crate::usb::policy::learn_devices as learn_usb_devices;
 
 pub type ResultCallback = Box<dyn Fn(Result<(), anyhow::Error>) + Send + Sync>;
 
@@ -35,12 +48,76 @@ impl App {
         let mut tasks =
             FuturesUnordered::<LocalBoxFuture<'static, Result<(), anyhow::Error>>>::new();
 
+        let mut handlers = ipc::Handlers::default();
+        // All udev events are read from a single monitor socket.
+        let udev_hub = udev::UdevHub::new();
+
         if config.power.enabled {
-            let fut = PowerManager::start(config.power.clone(), notifier.clone());
+            let (tx, rx) = tokio::sync::mpsc::channel(8);
+            handlers.power = Some(tx);
+            let events = PowerManager::subscribe(&udev_hub);
+            let fut = PowerManager::start(config.power.clone(), notifier.clone(), rx, events);
             tasks.push(Box::pin(fut));
         }
+        // Network changes trigger an immediate online check.
+        let (online_check_tx, online_check_rx) = tokio::sync::mpsc::channel(1);
+        let online_check_tx = (config.online.enabled && config.network.trigger_online_check)
+            .then_some(online_check_tx);
         if config.online.enabled {
-            let fut = OnlineManager::start(config.online.clone(), notifier.clone());
+            let fut =
+                OnlineManager::start(config.online.clone(), notifier.clone(), online_check_rx);
+            tasks.push(Box::pin(fut));
+        }
+        if config.public_ip.enabled {
+            let fut = public_ip::PublicIpManager::start(config.public_ip.clone(), notifier.clone());
+            tasks.push(Box::pin(fut));
+        }
+        if config.network.enabled {
+            let fut =
+                NetworkManager::start(config.network.clone(), notifier.clone(), online_check_tx);
+            tasks.push(Box::pin(fut));
+        }
+        if config.wifi.enabled {
+            let fut = wifi::WifiManager::start(config.wifi.clone(), notifier.clone());
+            tasks.push(Box::pin(fut));
+        }
+        if !config.data_usage.budgets.is_empty() {
+            let fut =
+                data_usage::DataUsageManager::start(config.data_usage.clone(), notifier.clone());
+            tasks.push(Box::pin(fut));
+        }
+        if !config.vpn.tunnels.is_empty() {
+            let fut = vpn::VpnManager::start(config.vpn.clone(), notifier.clone());
+            tasks.push(Box::pin(fut));
+        }
+        if config.usb.enabled {
+            let events = usb::UsbManager::subscribe(&udev_hub);
+            let fut = usb::UsbManager::start(config.usb.clone(), notifier.clone(), events);
+            tasks.push(Box::pin(fut));
+        }
+        if config.usb.policy.enabled {
+            let events = usb::policy::UsbPolicyManager::subscribe(&udev_hub);
+            let fut = usb::policy::UsbPolicyManager::start(
+                config.usb.policy.clone(),
+                notifier.clone(),
+                events,
+            );
+            tasks.push(Box::pin(fut));
+        }
+        if config.block.enabled {
+            let events = block::BlockManager::subscribe(&udev_hub);
+            let fut = block::BlockManager::start(config.block.clone(), notifier.clone(), events);
+            tasks.push(Box::pin(fut));
+        }
+        if config.display.enabled {
+            let events = display::DisplayManager::subscribe(&udev_hub);
+            let fut =
+                display::DisplayManager::start(config.display.clone(), notifier.clone(), events);
+            tasks.push(Box::pin(fut));
+        }
+        if !config.udev.watchers.is_empty() {
+            let events = udev::UdevManager::subscribe(&config.udev, &udev_hub);
+            let fut = udev::UdevManager::start(config.udev.clone(), notifier.clone(), events);
             tasks.push(Box::pin(fut));
         }
         if config.fs.enabled {
@@ -48,9 +125,26 @@ impl App {
             tasks.push(Box::pin(fut));
         }
 
-        let fut = tokio::task::spawn_local(async move { udev::run().await });
-        let fut = async move { fut.await.context("udev task failed")? };
-        tasks.push(Box::pin(fut));
+        if udev_hub.has_subscribers() {
+            let fut = async move { udev_hub.run().await.context("udev monitor failed") };
+            tasks.push(Box::pin(fut));
+        }
+
+        if config.ipc.enabled {
+            // The control socket is not essential, so failures only disable
+            // it instead of stopping the monitoring.
+            let fut = async move {
+                let res = match config.ipc.socket_path() {
+                    Ok(path) => ipc::serve(path, handlers).await,
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = res {
+                    tracing::warn!(error = &*err, "control socket disabled");
+                }
+                futures::future::pending().await
+            };
+            tasks.push(Box::pin(fut));
+        }
 
         if tasks.is_empty() {
             anyhow::bail!("No checks enabled - exiting");
@@ -1,13 +1,15 @@
 use std::path::PathBuf;
 
 use anyhow::Context;
-use panoramas::{cfg::Config, App};
+use panoramas::{cfg::Config, ipc, App};
 
 struct Cli {
     config_path: Option<String>,
     verbose: bool,
     help: bool,
     dump_default_config: bool,
+    travel_mode: Option<bool>,
+    learn_usb_devices: bool,
 }
 
 impl Cli {
@@ -19,6 +21,8 @@ USAGE:
 * -v/--verbose - enable verbose logging
 * -h/--help - show this help message
 * --dump-default-config - show the default config file and exit
+* --travel-mode <on|off> - let a running daemon charge the battery to 100%
+* --learn-usb-devices - add the attached USB devices to the USB allowlist
 "#;
 
     fn parse_env() -> Result<Self, anyhow::Error> {
@@ -34,6 +38,8 @@ USAGE:
             verbose: false,
             help: false,
             dump_default_config: false,
+            travel_mode: None,
+            learn_usb_devices: false,
         };
 
         while let Some(val) = args.next() {
@@ -51,6 +57,15 @@ USAGE:
                 "--dump-default-config" => {
                     s.dump_default_config = true;
                 }
+                "--travel-mode" => {
+                    let value = args
+                        .next()
+                        .context("--travel-mode requires 'on' or 'off'")?;
+                    s.travel_mode = Some(ipc::parse_on_off(&value)?);
+                }
+                "--learn-usb-devices" => {
+                    s.learn_usb_devices = true;
+                }
                 other => {
                     anyhow::bail!("unknown argument '{other}'");
                 }
@@ -114,6 +129,20 @@ USAGE:
             Config::default()
         };
 
+        if let Some(enabled) = self.travel_mode {
+            let path = config.ipc.socket_path()?;
+            ipc::send(&path, &ipc::Request::TravelMode(enabled)).await?;
+            let state = if enabled { "enabled" } else { "disabled" };
+            println!("travel mode {state}");
+            return Ok(());
+        }
+
+        if self.learn_usb_devices {
+            let (path, added) = panoramas::learn_usb_devices(&config.usb.policy)?;
+            println!("added {added} USB devices to '{}'", path.display());
+            return Ok(());
+        }
+
         App::run(config).await
     }
 }
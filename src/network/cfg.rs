@@ -0,0 +1,121 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::cfg::{Alert, AlertSeverity};
+
+/// Notifications for network interface, address and route changes.
+///
+/// Available variables: ${interface}, plus ${address} and ${prefix_len} for
+/// address alerts, and ${gateway} and ${family} ("ipv4" or "ipv6") for route
+/// alerts.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NetworkConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Re-run the online checks immediately when the network changes.
+    #[serde(default = "default_true")]
+    pub trigger_online_check: bool,
+    /// Interfaces to ignore. A trailing `*` matches any suffix.
+    #[serde(default = "NetworkConfig::default_ignore_interfaces")]
+    pub ignore_interfaces: Vec<String>,
+
+    #[serde(default = "NetworkConfig::default_alert_interface_up")]
+    pub alert_interface_up: Option<Alert>,
+    #[serde(default = "NetworkConfig::default_alert_interface_down")]
+    pub alert_interface_down: Option<Alert>,
+    #[serde(default = "NetworkConfig::default_alert_cable_plugged")]
+    pub alert_cable_plugged: Option<Alert>,
+    #[serde(default = "NetworkConfig::default_alert_cable_unplugged")]
+    pub alert_cable_unplugged: Option<Alert>,
+    #[serde(default = "NetworkConfig::default_alert_address_added")]
+    pub alert_address_added: Option<Alert>,
+    #[serde(default = "NetworkConfig::default_alert_default_route_changed")]
+    pub alert_default_route_changed: Option<Alert>,
+    #[serde(default = "NetworkConfig::default_alert_default_route_removed")]
+    pub alert_default_route_removed: Option<Alert>,
+}
+
+impl NetworkConfig {
+    pub fn is_ignored(&self, interface: &str) -> bool {
+        self.ignore_interfaces
+            .iter()
+            .any(|pattern| interface_matches(pattern, interface))
+    }
+
+    fn default_ignore_interfaces() -> Vec<String> {
+        ["lo", "veth*", "docker*", "br-*", "virbr*"]
+            .map(String::from)
+            .to_vec()
+    }
+
+    fn info(summary: &str) -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Info,
+            on_startup: false,
+            repeat_after_seconds: None,
+            expire_after_seconds: Some(10),
+            summary: summary.to_string(),
+            message: None,
+        })
+    }
+
+    fn default_alert_interface_up() -> Option<Alert> {
+        Self::info("Network interface ${interface} is up")
+    }
+
+    fn default_alert_interface_down() -> Option<Alert> {
+        Self::info("Network interface ${interface} is down")
+    }
+
+    fn default_alert_cable_plugged() -> Option<Alert> {
+        Self::info("Network connected on ${interface}")
+    }
+
+    fn default_alert_cable_unplugged() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Warning,
+            ..Self::info("Network disconnected on ${interface}").unwrap()
+        })
+    }
+
+    fn default_alert_address_added() -> Option<Alert> {
+        Self::info("${interface} acquired address ${address}/${prefix_len}")
+    }
+
+    fn default_alert_default_route_changed() -> Option<Alert> {
+        Self::info("Default ${family} route via ${gateway} on ${interface}")
+    }
+
+    fn default_alert_default_route_removed() -> Option<Alert> {
+        Self::info("Default ${family} route on ${interface} removed")
+    }
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            trigger_online_check: true,
+            ignore_interfaces: Self::default_ignore_interfaces(),
+            alert_interface_up: Self::default_alert_interface_up(),
+            alert_interface_down: Self::default_alert_interface_down(),
+            alert_cable_plugged: Self::default_alert_cable_plugged(),
+            alert_cable_unplugged: Self::default_alert_cable_unplugged(),
+            alert_address_added: Self::default_alert_address_added(),
+            alert_default_route_changed: Self::default_alert_default_route_changed(),
+            alert_default_route_removed: Self::default_alert_default_route_removed(),
+        }
+    }
+}
+
+/// Match an interface name against a pattern, which may end with a `*`
+/// wildcard (eg "wwan*").
+pub fn interface_matches(pattern: &str, interface: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => interface.starts_with(prefix),
+        None => interface == pattern,
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
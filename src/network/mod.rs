@@ -0,0 +1,581 @@
+pub mod cfg;
+pub mod netlink;
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+};
+
+use anyhow::Context;
+use tokio::sync::mpsc;
+
+use crate::notify::Notifier;
+
+use self::{
+    cfg::NetworkConfig,
+    netlink::{AddressEvent, LinkEvent, NetEvent, NetlinkSocket, Overrun, RouteEvent},
+};
+
+const ALERT_GROUP_NETWORK_PREFIX: &str = "panorama.network.";
+
+#[derive(Clone, Default, Debug)]
+struct Link {
+    name: String,
+    up: bool,
+    carrier: bool,
+}
+
+pub struct NetworkManager {
+    config: NetworkConfig,
+    notifier: Notifier,
+    /// Notified to trigger an immediate online check.
+    online_check: Option<mpsc::Sender<()>>,
+    links: HashMap<u32, Link>,
+    addresses: HashSet<(u32, IpAddr)>,
+    /// Gateway of each default route, by address family and interface.
+    default_routes: HashMap<(bool, Option<u32>), Option<IpAddr>>,
+}
+
+impl NetworkManager {
+    pub async fn start(
+        config: NetworkConfig,
+        notifier: Notifier,
+        online_check: Option<mpsc::Sender<()>>,
+    ) -> Result<(), anyhow::Error> {
+        let manager = Self::new(config, notifier, online_check);
+        tokio::task::spawn_local(async move { manager.run().await })
+            .await
+            .context("NetworkManager task failed")?
+            .context("NetworkManager failed")?;
+
+        Ok(())
+    }
+
+    fn new(
+        config: NetworkConfig,
+        notifier: Notifier,
+        online_check: Option<mpsc::Sender<()>>,
+    ) -> Self {
+        Self {
+            config,
+            notifier,
+            online_check,
+            links: HashMap::new(),
+            addresses: HashSet::new(),
+            default_routes: HashMap::new(),
+        }
+    }
+
+    async fn run(mut self) -> Result<(), anyhow::Error> {
+        let mut socket = NetlinkSocket::open()?;
+
+        // Load the current state, which also provides the interface names
+        // for later address and route events.
+        self.load_state(&mut socket, true).await?;
+
+        loop {
+            match socket.recv().await {
+                Ok(events) => self.handle_events(events, false).await?,
+                // The lost events are recovered from the difference.
+                Err(err) if err.is::<Overrun>() => {
+                    tracing::warn!(error = &*err, "reloading the network state");
+                    self.load_state(&mut socket, false).await?;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Dump the current state, until no notifications were lost meanwhile.
+    async fn load_state(
+        &mut self,
+        socket: &mut NetlinkSocket,
+        initial: bool,
+    ) -> Result<(), anyhow::Error> {
+        'dump: loop {
+            let mut events = Vec::new();
+            for kind in [
+                netlink::RTM_GETLINK,
+                netlink::RTM_GETADDR,
+                netlink::RTM_GETROUTE,
+            ] {
+                match socket.dump(kind).await {
+                    Ok(dump) => events.extend(dump),
+                    Err(err) if err.is::<Overrun>() => {
+                        tracing::debug!(error = &*err, "restarting the network state dump");
+                        continue 'dump;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            let events = self.with_removals(events);
+            return self.handle_events(events, initial).await;
+        }
+    }
+
+    /// Add removal events for the known objects that are missing in a full
+    /// `dump`, after the dump itself so removed addresses and routes still
+    /// have their interface names.
+    fn with_removals(&self, mut dump: Vec<NetEvent>) -> Vec<NetEvent> {
+        let mut links = HashSet::new();
+        let mut addresses = HashSet::new();
+        let mut routes = HashSet::new();
+        for event in &dump {
+            match event {
+                NetEvent::Link(link) => {
+                    links.insert(link.index);
+                }
+                NetEvent::Address(addr) => {
+                    addresses.insert((addr.index, addr.address));
+                }
+                NetEvent::Route(route) if route.is_default() => {
+                    routes.insert((route.ipv6, route.oif));
+                }
+                NetEvent::Route(_) => {}
+            }
+        }
+
+        let removed_addresses = self
+            .addresses
+            .iter()
+            .filter(|key| !addresses.contains(*key))
+            .map(|&(index, address)| {
+                NetEvent::Address(AddressEvent {
+                    index,
+                    address,
+                    prefix_len: 0,
+                    removed: true,
+                })
+            });
+        let removed_routes = self
+            .default_routes
+            .iter()
+            .filter(|(key, _)| !routes.contains(*key))
+            .map(|(&(ipv6, oif), &gateway)| {
+                NetEvent::Route(RouteEvent {
+                    ipv6,
+                    table: netlink::RT_TABLE_MAIN,
+                    route_type: netlink::RTN_UNICAST,
+                    dst_len: 0,
+                    gateway,
+                    oif,
+                    removed: true,
+                })
+            });
+        let removed_links = self
+            .links
+            .keys()
+            .filter(|index| !links.contains(*index))
+            .map(|&index| {
+                NetEvent::Link(LinkEvent {
+                    index,
+                    name: None,
+                    up: false,
+                    carrier: false,
+                    removed: true,
+                })
+            });
+        let removed = removed_addresses
+            .chain(removed_routes)
+            .chain(removed_links)
+            .collect::<Vec<_>>();
+        dump.extend(removed);
+        dump
+    }
+
+    async fn handle_events(
+        &mut self,
+        events: Vec<NetEvent>,
+        initial: bool,
+    ) -> Result<(), anyhow::Error> {
+        let mut changed = false;
+        for event in events {
+            tracing::trace!(?event, "network event");
+            changed |= match event {
+                NetEvent::Link(link) => self.handle_link(link, initial).await?,
+                NetEvent::Address(addr) => self.handle_address(addr, initial).await?,
+                NetEvent::Route(route) => self.handle_route(route, initial).await?,
+            };
+        }
+
+        if changed && !initial {
+            if let Some(online_check) = &self.online_check {
+                // A full channel already has a pending check.
+                online_check.try_send(()).ok();
+            }
+        }
+        Ok(())
+    }
+
+    fn interface_name(&self, index: u32) -> String {
+        self.links
+            .get(&index)
+            .map(|link| link.name.clone())
+            .unwrap_or_else(|| index.to_string())
+    }
+
+    async fn notify(
+        &self,
+        alert: &Option<crate::cfg::Alert>,
+        interface: &str,
+        mut variables: HashMap<String, String>,
+        initial: bool,
+    ) -> Result<(), anyhow::Error> {
+        let Some(alert) = alert else {
+            return Ok(());
+        };
+        variables.insert("interface".to_string(), interface.to_string());
+        let group = format!("{ALERT_GROUP_NETWORK_PREFIX}{interface}");
+        let full = alert.prepare(group, variables).initial(initial);
+        self.notifier.notify(full).await
+    }
+
+    /// Returns whether a relevant change occurred.
+    async fn handle_link(
+        &mut self,
+        event: LinkEvent,
+        initial: bool,
+    ) -> Result<bool, anyhow::Error> {
+        let previous = if event.removed {
+            self.links.remove(&event.index)
+        } else {
+            let name = event
+                .name
+                .clone()
+                .unwrap_or_else(|| self.interface_name(event.index));
+            let link = Link {
+                name,
+                up: event.up,
+                carrier: event.carrier,
+            };
+            self.links.insert(event.index, link)
+        };
+        let previous = previous.unwrap_or_default();
+        let (up, carrier) = if event.removed {
+            (false, false)
+        } else {
+            (event.up, event.carrier)
+        };
+
+        let name = event.name.unwrap_or(previous.name.clone());
+        if self.config.is_ignored(&name) {
+            return Ok(false);
+        }
+
+        if up != previous.up {
+            let alert = if up {
+                &self.config.alert_interface_up
+            } else {
+                &self.config.alert_interface_down
+            };
+            self.notify(alert, &name, HashMap::new(), initial).await?;
+        }
+        if carrier != previous.carrier {
+            let alert = if carrier {
+                &self.config.alert_cable_plugged
+            } else {
+                &self.config.alert_cable_unplugged
+            };
+            self.notify(alert, &name, HashMap::new(), initial).await?;
+        }
+
+        Ok(up != previous.up || carrier != previous.carrier)
+    }
+
+    async fn handle_address(
+        &mut self,
+        event: AddressEvent,
+        initial: bool,
+    ) -> Result<bool, anyhow::Error> {
+        let key = (event.index, event.address);
+        let name = self.interface_name(event.index);
+        if event.removed {
+            let removed = self.addresses.remove(&key);
+            return Ok(removed && !self.config.is_ignored(&name));
+        }
+        // IPv6 addresses are re-announced whenever their lifetime is renewed.
+        if !self.addresses.insert(key) || self.config.is_ignored(&name) {
+            return Ok(false);
+        }
+
+        // Link-local IPv6 addresses are configured on every interface that
+        // comes up.
+        let link_local =
+            matches!(event.address, IpAddr::V6(ip) if ip.segments()[0] & 0xffc0 == 0xfe80);
+        if !link_local {
+            let variables = HashMap::from([
+                ("address".to_string(), event.address.to_string()),
+                ("prefix_len".to_string(), event.prefix_len.to_string()),
+            ]);
+            self.notify(&self.config.alert_address_added, &name, variables, initial)
+                .await?;
+        }
+        Ok(true)
+    }
+
+    async fn handle_route(
+        &mut self,
+        event: RouteEvent,
+        initial: bool,
+    ) -> Result<bool, anyhow::Error> {
+        if !event.is_default() {
+            return Ok(false);
+        }
+
+        let key = (event.ipv6, event.oif);
+        let name = event
+            .oif
+            .map(|index| self.interface_name(index))
+            .unwrap_or_default();
+        let alert = if event.removed {
+            if self.default_routes.remove(&key).is_none() {
+                return Ok(false);
+            }
+            &self.config.alert_default_route_removed
+        } else {
+            if self.default_routes.insert(key, event.gateway) == Some(event.gateway) {
+                return Ok(false);
+            }
+            &self.config.alert_default_route_changed
+        };
+
+        let family = if event.ipv6 { "ipv6" } else { "ipv4" };
+        let gateway = event.gateway.map(|ip| ip.to_string()).unwrap_or_default();
+        let variables = HashMap::from([
+            ("family".to_string(), family.to_string()),
+            ("gateway".to_string(), gateway),
+        ]);
+        self.notify(alert, &name, variables, initial).await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::notify::PreparedAlert;
+
+    fn manager() -> (
+        NetworkManager,
+        mpsc::Receiver<PreparedAlert>,
+        mpsc::Receiver<()>,
+    ) {
+        let mut config = NetworkConfig::default();
+        for alert in [
+            &mut config.alert_interface_up,
+            &mut config.alert_interface_down,
+            &mut config.alert_cable_plugged,
+            &mut config.alert_cable_unplugged,
+            &mut config.alert_address_added,
+            &mut config.alert_default_route_changed,
+            &mut config.alert_default_route_removed,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            alert.on_startup = true;
+        }
+        let (notifier, alerts) = Notifier::test_channel();
+        let (tx, rx) = mpsc::channel(1);
+        (NetworkManager::new(config, notifier, Some(tx)), alerts, rx)
+    }
+
+    fn link(index: u32, name: &str, up: bool, carrier: bool) -> NetEvent {
+        NetEvent::Link(LinkEvent {
+            index,
+            name: Some(name.to_string()),
+            up,
+            carrier,
+            removed: false,
+        })
+    }
+
+    fn address(index: u32, address: &str) -> NetEvent {
+        NetEvent::Address(AddressEvent {
+            index,
+            address: address.parse().unwrap(),
+            prefix_len: 24,
+            removed: false,
+        })
+    }
+
+    fn default_route(oif: u32, gateway: &str, removed: bool) -> NetEvent {
+        NetEvent::Route(RouteEvent {
+            ipv6: false,
+            table: netlink::RT_TABLE_MAIN,
+            route_type: 1,
+            dst_len: 0,
+            gateway: Some(gateway.parse().unwrap()),
+            oif: Some(oif),
+            removed,
+        })
+    }
+
+    fn summaries(alerts: &mut mpsc::Receiver<PreparedAlert>) -> Vec<String> {
+        let mut summaries = Vec::new();
+        while let Ok(alert) = alerts.try_recv() {
+            summaries.push(crate::notify::render(
+                &alert.alert.summary,
+                &alert.variables,
+            ));
+        }
+        summaries
+    }
+
+    #[tokio::test]
+    async fn test_link_changes() {
+        let (mut manager, mut alerts, mut checks) = manager();
+        manager
+            .handle_events(vec![link(2, "eth0", true, false)], true)
+            .await
+            .unwrap();
+        assert_eq!(summaries(&mut alerts), vec!["Network interface eth0 is up"]);
+        // The initial state does not trigger an online check.
+        assert!(checks.try_recv().is_err());
+
+        // Unrelated link updates are ignored.
+        manager
+            .handle_events(vec![link(2, "eth0", true, false)], false)
+            .await
+            .unwrap();
+        assert!(alerts.try_recv().is_err());
+        assert!(checks.try_recv().is_err());
+
+        manager
+            .handle_events(vec![link(2, "eth0", true, true)], false)
+            .await
+            .unwrap();
+        assert_eq!(summaries(&mut alerts), vec!["Network connected on eth0"]);
+        checks.try_recv().unwrap();
+
+        let removed = NetEvent::Link(LinkEvent {
+            index: 2,
+            name: None,
+            up: false,
+            carrier: false,
+            removed: true,
+        });
+        manager.handle_events(vec![removed], false).await.unwrap();
+        assert_eq!(
+            summaries(&mut alerts),
+            vec![
+                "Network interface eth0 is down",
+                "Network disconnected on eth0"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ignored_interfaces() {
+        let (mut manager, mut alerts, mut checks) = manager();
+        let events = vec![
+            link(1, "lo", true, true),
+            link(7, "veth12ab", true, true),
+            address(1, "127.0.0.1"),
+        ];
+        manager.handle_events(events, false).await.unwrap();
+        assert!(alerts.try_recv().is_err());
+        assert!(checks.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_addresses() {
+        let (mut manager, mut alerts, mut checks) = manager();
+        let events = vec![
+            link(3, "wlan0", true, true),
+            address(3, "192.168.1.20"),
+            address(3, "fe80::1"),
+            // Lifetime renewal.
+            address(3, "192.168.1.20"),
+        ];
+        manager.handle_events(events, false).await.unwrap();
+        assert_eq!(
+            summaries(&mut alerts),
+            vec![
+                "Network interface wlan0 is up",
+                "Network connected on wlan0",
+                "wlan0 acquired address 192.168.1.20/24",
+            ]
+        );
+        checks.try_recv().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_default_route() {
+        let (mut manager, mut alerts, mut checks) = manager();
+        manager
+            .handle_events(vec![link(3, "wlan0", true, true)], true)
+            .await
+            .unwrap();
+        summaries(&mut alerts);
+
+        manager
+            .handle_events(vec![default_route(3, "192.168.1.1", false)], false)
+            .await
+            .unwrap();
+        assert_eq!(
+            summaries(&mut alerts),
+            vec!["Default ipv4 route via 192.168.1.1 on wlan0"]
+        );
+        checks.try_recv().unwrap();
+
+        // Same route again.
+        manager
+            .handle_events(vec![default_route(3, "192.168.1.1", false)], false)
+            .await
+            .unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        manager
+            .handle_events(vec![default_route(3, "192.168.1.1", true)], false)
+            .await
+            .unwrap();
+        assert_eq!(
+            summaries(&mut alerts),
+            vec!["Default ipv4 route on wlan0 removed"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reload_state() {
+        let (mut manager, mut alerts, mut checks) = manager();
+        manager
+            .handle_events(
+                vec![
+                    link(2, "eth0", true, true),
+                    link(3, "wlan0", true, true),
+                    address(3, "192.168.1.10"),
+                    default_route(3, "192.168.1.1", false),
+                ],
+                true,
+            )
+            .await
+            .unwrap();
+        summaries(&mut alerts);
+
+        // wlan0 was removed while events were lost.
+        let events = manager.with_removals(vec![link(2, "eth0", true, true)]);
+        manager.handle_events(events, false).await.unwrap();
+        let alerts = std::iter::from_fn(|| alerts.try_recv().ok()).collect::<Vec<_>>();
+        assert!(alerts.iter().all(|alert| !alert.initial));
+        assert_eq!(
+            alerts
+                .iter()
+                .map(|alert| alert.alert.summary.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "Default ${family} route on ${interface} removed",
+                "Network interface ${interface} is down",
+                "Network disconnected on ${interface}",
+            ]
+        );
+        assert_eq!(alerts[0].variables["interface"], "wlan0");
+        assert_eq!(manager.links.keys().collect::<Vec<_>>(), vec![&2]);
+        assert!(manager.addresses.is_empty());
+        assert!(manager.default_routes.is_empty());
+        checks.try_recv().unwrap();
+        assert!(checks.try_recv().is_err());
+    }
+}
@@ -0,0 +1,602 @@
+//! Minimal rtnetlink client for link, address and route events.
+//!
+//! Only the few message types and attributes needed for notifications are
+//! decoded, see `rtnetlink(7)`.
+
+use std::{
+    io::{Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    os::fd::{FromRawFd, OwnedFd},
+};
+
+use anyhow::Context;
+use tokio::io::unix::AsyncFd;
+
+const NLMSG_HDR_LEN: usize = 16;
+const NLMSG_NOOP: u16 = 1;
+pub const NLMSG_ERROR: u16 = 2;
+pub const NLMSG_DONE: u16 = 3;
+pub const NLM_F_REQUEST: u16 = 0x1;
+pub const NLM_F_DUMP: u16 = 0x300;
+
+pub const RTM_NEWLINK: u16 = 16;
+pub const RTM_DELLINK: u16 = 17;
+pub const RTM_GETLINK: u16 = 18;
+pub const RTM_NEWADDR: u16 = 20;
+pub const RTM_DELADDR: u16 = 21;
+pub const RTM_GETADDR: u16 = 22;
+pub const RTM_NEWROUTE: u16 = 24;
+pub const RTM_DELROUTE: u16 = 25;
+pub const RTM_GETROUTE: u16 = 26;
+
+const RTMGRP_LINK: u32 = 0x1;
+const RTMGRP_IPV4_IFADDR: u32 = 0x10;
+const RTMGRP_IPV4_ROUTE: u32 = 0x40;
+const RTMGRP_IPV6_IFADDR: u32 = 0x100;
+const RTMGRP_IPV6_ROUTE: u32 = 0x400;
+
+const IFF_UP: u32 = 0x1;
+const IFF_LOWER_UP: u32 = 0x10000;
+const IFLA_IFNAME: u16 = 3;
+
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+
+const RTA_OIF: u16 = 4;
+const RTA_GATEWAY: u16 = 5;
+const RTA_TABLE: u16 = 15;
+pub const RT_TABLE_MAIN: u32 = 254;
+pub const RTN_UNICAST: u8 = 1;
+
+const AF_INET: u8 = 2;
+const AF_INET6: u8 = 10;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NetEvent {
+    Link(LinkEvent),
+    Address(AddressEvent),
+    Route(RouteEvent),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LinkEvent {
+    pub index: u32,
+    pub name: Option<String>,
+    /// Administratively up.
+    pub up: bool,
+    /// Physical link (cable or Wi-Fi association) is present.
+    pub carrier: bool,
+    pub removed: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AddressEvent {
+    pub index: u32,
+    pub address: IpAddr,
+    pub prefix_len: u8,
+    pub removed: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RouteEvent {
+    pub ipv6: bool,
+    pub table: u32,
+    pub route_type: u8,
+    pub dst_len: u8,
+    pub gateway: Option<IpAddr>,
+    pub oif: Option<u32>,
+    pub removed: bool,
+}
+
+impl RouteEvent {
+    /// Whether this is a default route of the main routing table.
+    pub fn is_default(&self) -> bool {
+        self.dst_len == 0 && self.table == RT_TABLE_MAIN && self.route_type == RTN_UNICAST
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Message {
+    Event(NetEvent),
+    /// End of a dump.
+    Done,
+    /// Error response, with the (negative) errno.
+    Error(i32),
+    Other,
+}
+
+/// A netlink message of a datagram, before decoding its payload.
+pub struct RawMessage<'a> {
+    pub kind: u16,
+    pub seq: u32,
+    pub payload: &'a [u8],
+}
+
+/// Split a datagram into its messages.
+pub fn split_messages(mut buf: &[u8]) -> Result<Vec<RawMessage<'_>>, anyhow::Error> {
+    let mut messages = Vec::new();
+    while buf.len() >= NLMSG_HDR_LEN {
+        let len = u32::from_ne_bytes(buf[0..4].try_into().unwrap()) as usize;
+        if len < NLMSG_HDR_LEN || len > buf.len() {
+            anyhow::bail!("invalid netlink message length {len}");
+        }
+        messages.push(RawMessage {
+            kind: u16::from_ne_bytes([buf[4], buf[5]]),
+            seq: u32::from_ne_bytes(buf[8..12].try_into().unwrap()),
+            payload: &buf[NLMSG_HDR_LEN..len],
+        });
+        buf = &buf[align(len).min(buf.len())..];
+    }
+    Ok(messages)
+}
+
+/// Decode the errno of an `NLMSG_ERROR` message, 0 for acknowledgements.
+pub fn parse_error(payload: &[u8]) -> Result<i32, anyhow::Error> {
+    let errno = payload
+        .get(0..4)
+        .context("truncated netlink error message")?;
+    Ok(i32::from_ne_bytes(errno.try_into().unwrap()))
+}
+
+/// Decode all rtnetlink messages in a datagram.
+pub fn parse_messages(buf: &[u8]) -> Result<Vec<Message>, anyhow::Error> {
+    let mut messages = Vec::new();
+    for RawMessage { kind, payload, .. } in split_messages(buf)? {
+        let message = match kind {
+            NLMSG_DONE => Message::Done,
+            NLMSG_ERROR => Message::Error(parse_error(payload)?),
+            NLMSG_NOOP => Message::Other,
+            RTM_NEWLINK | RTM_DELLINK => {
+                Message::Event(NetEvent::Link(parse_link(payload, kind == RTM_DELLINK)?))
+            }
+            RTM_NEWADDR | RTM_DELADDR => match parse_address(payload, kind == RTM_DELADDR)? {
+                Some(addr) => Message::Event(NetEvent::Address(addr)),
+                None => Message::Other,
+            },
+            RTM_NEWROUTE | RTM_DELROUTE => match parse_route(payload, kind == RTM_DELROUTE)? {
+                Some(route) => Message::Event(NetEvent::Route(route)),
+                None => Message::Other,
+            },
+            _ => Message::Other,
+        };
+        messages.push(message);
+    }
+    Ok(messages)
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Iterate over the `rtattr`/`nlattr` attributes of a message.
+pub fn attributes(mut buf: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        if buf.len() < 4 {
+            return None;
+        }
+        let len = u16::from_ne_bytes([buf[0], buf[1]]) as usize;
+        let kind = u16::from_ne_bytes([buf[2], buf[3]]);
+        if len < 4 || len > buf.len() {
+            return None;
+        }
+        let value = &buf[4..len];
+        buf = &buf[align(len).min(buf.len())..];
+        Some((kind, value))
+    })
+}
+
+fn parse_link(payload: &[u8], removed: bool) -> Result<LinkEvent, anyhow::Error> {
+    // struct ifinfomsg
+    if payload.len() < 16 {
+        anyhow::bail!("truncated link message");
+    }
+    let index = u32::from_ne_bytes(payload[4..8].try_into().unwrap());
+    let flags = u32::from_ne_bytes(payload[8..12].try_into().unwrap());
+
+    let name = attributes(&payload[16..])
+        .find(|(kind, _)| *kind == IFLA_IFNAME)
+        .map(|(_, value)| {
+            let value = value.split(|b| *b == 0).next().unwrap_or_default();
+            String::from_utf8_lossy(value).into_owned()
+        });
+
+    Ok(LinkEvent {
+        index,
+        name,
+        up: flags & IFF_UP != 0,
+        carrier: flags & IFF_LOWER_UP != 0,
+        removed,
+    })
+}
+
+fn parse_address(payload: &[u8], removed: bool) -> Result<Option<AddressEvent>, anyhow::Error> {
+    // struct ifaddrmsg
+    if payload.len() < 8 {
+        anyhow::bail!("truncated address message");
+    }
+    let family = payload[0];
+    let prefix_len = payload[1];
+    let index = u32::from_ne_bytes(payload[4..8].try_into().unwrap());
+
+    // IFA_LOCAL is the interface address, IFA_ADDRESS the peer address for
+    // point-to-point links.
+    let mut address = None;
+    for (kind, value) in attributes(&payload[8..]) {
+        match kind {
+            IFA_LOCAL => address = parse_ip(family, value),
+            IFA_ADDRESS if address.is_none() => address = parse_ip(family, value),
+            _ => {}
+        }
+    }
+
+    Ok(address.map(|address| AddressEvent {
+        index,
+        address,
+        prefix_len,
+        removed,
+    }))
+}
+
+fn parse_route(payload: &[u8], removed: bool) -> Result<Option<RouteEvent>, anyhow::Error> {
+    // struct rtmsg
+    if payload.len() < 12 {
+        anyhow::bail!("truncated route message");
+    }
+    let family = payload[0];
+    if family != AF_INET && family != AF_INET6 {
+        return Ok(None);
+    }
+
+    let mut route = RouteEvent {
+        ipv6: family == AF_INET6,
+        dst_len: payload[1],
+        table: u32::from(payload[4]),
+        route_type: payload[7],
+        gateway: None,
+        oif: None,
+        removed,
+    };
+    for (kind, value) in attributes(&payload[12..]) {
+        match kind {
+            RTA_GATEWAY => route.gateway = parse_ip(family, value),
+            RTA_OIF => route.oif = parse_u32(value),
+            RTA_TABLE => {
+                if let Some(table) = parse_u32(value) {
+                    route.table = table;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(Some(route))
+}
+
+pub fn parse_u32(value: &[u8]) -> Option<u32> {
+    Some(u32::from_ne_bytes(value.try_into().ok()?))
+}
+
+fn parse_ip(family: u8, value: &[u8]) -> Option<IpAddr> {
+    match family {
+        AF_INET => Some(Ipv4Addr::from(<[u8; 4]>::try_from(value).ok()?).into()),
+        AF_INET6 => Some(Ipv6Addr::from(<[u8; 16]>::try_from(value).ok()?).into()),
+        _ => None,
+    }
+}
+
+/// The kernel dropped messages because the receive buffer was full, so the
+/// state needs to be dumped again.
+#[derive(Debug)]
+pub struct Overrun;
+
+impl std::fmt::Display for Overrun {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "netlink receive buffer overrun - events were lost")
+    }
+}
+
+impl std::error::Error for Overrun {}
+
+/// Netlink route socket subscribed to link, address and route changes.
+pub struct NetlinkSocket {
+    fd: AsyncFd<socket2::Socket>,
+    seq: u32,
+    buf: Vec<u8>,
+}
+
+impl NetlinkSocket {
+    pub fn open() -> Result<Self, anyhow::Error> {
+        let groups = RTMGRP_LINK
+            | RTMGRP_IPV4_IFADDR
+            | RTMGRP_IPV6_IFADDR
+            | RTMGRP_IPV4_ROUTE
+            | RTMGRP_IPV6_ROUTE;
+
+        let socket = open_socket(libc::NETLINK_ROUTE, groups)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            fd: AsyncFd::new(socket)?,
+            seq: 0,
+            buf: vec![0; 64 * 1024],
+        })
+    }
+
+    /// Request a dump of all objects of the given kind (eg [`RTM_GETLINK`])
+    /// and return the resulting events.
+    ///
+    /// Change notifications received in between are included. Fails with
+    /// [`Overrun`] once the dump is complete if notifications were lost.
+    pub async fn dump(&mut self, kind: u16) -> Result<Vec<NetEvent>, anyhow::Error> {
+        self.seq += 1;
+        let request = dump_request(kind, self.seq);
+        self.fd
+            .get_ref()
+            .write_all(&request)
+            .context("could not send netlink dump request")?;
+
+        let mut events = Vec::new();
+        let mut overrun = false;
+        loop {
+            // The dump itself is not affected, it is continued on each read.
+            let messages = match self.recv_messages().await {
+                Ok(messages) => messages,
+                Err(err) if err.is::<Overrun>() => {
+                    overrun = true;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            for message in messages {
+                match message {
+                    Message::Event(ev) => events.push(ev),
+                    Message::Done if overrun => return Err(Overrun.into()),
+                    Message::Done => return Ok(events),
+                    Message::Error(errno) if errno != 0 => {
+                        return Err(std::io::Error::from_raw_os_error(-errno))
+                            .context("netlink dump failed");
+                    }
+                    Message::Error(_) | Message::Other => {}
+                }
+            }
+        }
+    }
+
+    /// Wait for the next batch of change notifications.
+    ///
+    /// Fails with [`Overrun`] if notifications were lost.
+    pub async fn recv(&mut self) -> Result<Vec<NetEvent>, anyhow::Error> {
+        let messages = self.recv_messages().await?;
+        Ok(messages
+            .into_iter()
+            .filter_map(|message| match message {
+                Message::Event(ev) => Some(ev),
+                _ => None,
+            })
+            .collect())
+    }
+
+    async fn recv_messages(&mut self) -> Result<Vec<Message>, anyhow::Error> {
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let res = guard.try_io(|fd| fd.get_ref().read(&mut self.buf));
+            match res {
+                Ok(Ok(len)) => return parse_messages(&self.buf[..len]),
+                Ok(Err(err)) if err.raw_os_error() == Some(libc::ENOBUFS) => {
+                    return Err(Overrun.into());
+                }
+                Ok(Err(err)) => return Err(err).context("could not read from netlink socket"),
+                Err(_would_block) => {}
+            }
+        }
+    }
+}
+
+/// Open a netlink socket of the given protocol, subscribed to `groups`.
+pub fn open_socket(protocol: i32, groups: u32) -> Result<socket2::Socket, anyhow::Error> {
+    // SAFETY: plain syscalls, the returned fd is checked and owned below.
+    let fd = unsafe {
+        let fd = libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            protocol,
+        );
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error()).context("could not create netlink socket");
+        }
+        let fd = OwnedFd::from_raw_fd(fd);
+
+        let mut addr: libc::sockaddr_nl = std::mem::zeroed();
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = groups;
+        let res = libc::bind(
+            std::os::fd::AsRawFd::as_raw_fd(&fd),
+            &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        );
+        if res < 0 {
+            return Err(std::io::Error::last_os_error()).context("could not bind netlink socket");
+        }
+        fd
+    };
+    Ok(socket2::Socket::from(fd))
+}
+
+/// Build a netlink message.
+pub fn message(kind: u16, flags: u16, seq: u32, body: &[u8]) -> Vec<u8> {
+    let len = NLMSG_HDR_LEN + body.len();
+    let mut buf = Vec::with_capacity(align(len));
+    buf.extend_from_slice(&(len as u32).to_ne_bytes());
+    buf.extend_from_slice(&kind.to_ne_bytes());
+    buf.extend_from_slice(&flags.to_ne_bytes());
+    buf.extend_from_slice(&seq.to_ne_bytes());
+    buf.extend_from_slice(&0u32.to_ne_bytes());
+    buf.extend_from_slice(body);
+    buf
+}
+
+/// Encode a single attribute, including padding.
+pub fn attribute(kind: u16, value: &[u8]) -> Vec<u8> {
+    let len = 4 + value.len();
+    let mut buf = Vec::with_capacity(align(len));
+    buf.extend_from_slice(&(len as u16).to_ne_bytes());
+    buf.extend_from_slice(&kind.to_ne_bytes());
+    buf.extend_from_slice(value);
+    buf.resize(align(len), 0);
+    buf
+}
+
+fn dump_request(kind: u16, seq: u32) -> Vec<u8> {
+    // The family specific header is left zeroed, which matches all objects.
+    let body_len = match kind {
+        RTM_GETLINK => 16,
+        RTM_GETADDR => 8,
+        _ => 12,
+    };
+    message(kind, NLM_F_REQUEST | NLM_F_DUMP, seq, &vec![0; body_len])
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    // Messages captured from a dump on x86_64, link messages trimmed to the
+    // IFLA_IFNAME and IFLA_OPERSTATE attributes.
+    const LINK_ETH0: &str = "340000001000020001000000895d0000\
+        000001000400000043100100000000000900030065746830000000000500100006000000";
+    const ADDR_V4: &str = "580000001400020001000000895d0000021880000400000008000100c0000202\
+        08000200c000020208000400c00002ff0900030065746830000000000800080080000000\
+        14000600ffffffffffffffff0f0000000f000000";
+    const ADDR_V6: &str = "480000001400020001000000895d00000a408200040000001400010\
+        0fd00000000000000000000000000000214000600ffffffffffffffff0f0000000f000000\
+        0800080082000000";
+    const ROUTE_DEFAULT_V4: &str = "340000001800020001000000895d000002000000fe030001\
+        0000000008000f00fe00000008000500c00002010800040004000000";
+    const ROUTE_DEFAULT_V6: &str =
+        "740000001800020001000000895d00000a000000fe0300010000000008000f00\
+        fe000000080006000004000014000500fd000000000000000000000000000001\
+        080004000400000024000c000000000000000000000000000000000000000000\
+        0000000000000000000000000500140000000000";
+    const ROUTE_LOCAL: &str = "3c0000001800020001000000895d000002200000ff02fe020000\
+        000008000f00ff000000080001007f000001080007007f0000010800040001000000";
+    const DONE: &str = "140000000300020001000000895d000000000000";
+
+    fn bytes(hex: &str) -> Vec<u8> {
+        let hex = hex.replace(char::is_whitespace, "");
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn parse_one(hex: &str) -> Message {
+        let mut messages = parse_messages(&bytes(hex)).unwrap();
+        assert_eq!(messages.len(), 1);
+        messages.remove(0)
+    }
+
+    #[test]
+    fn test_parse_link() {
+        assert_eq!(
+            parse_one(LINK_ETH0),
+            Message::Event(NetEvent::Link(LinkEvent {
+                index: 4,
+                name: Some("eth0".to_string()),
+                up: true,
+                carrier: true,
+                removed: false,
+            }))
+        );
+
+        // RTM_DELLINK
+        let mut del = bytes(LINK_ETH0);
+        del[4] = RTM_DELLINK as u8;
+        let Message::Event(NetEvent::Link(link)) = &parse_messages(&del).unwrap()[0] else {
+            panic!("expected link event");
+        };
+        assert!(link.removed);
+    }
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(
+            parse_one(ADDR_V4),
+            Message::Event(NetEvent::Address(AddressEvent {
+                index: 4,
+                address: "192.0.2.2".parse().unwrap(),
+                prefix_len: 24,
+                removed: false,
+            }))
+        );
+        assert_eq!(
+            parse_one(ADDR_V6),
+            Message::Event(NetEvent::Address(AddressEvent {
+                index: 4,
+                address: "fd00::2".parse().unwrap(),
+                prefix_len: 64,
+                removed: false,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_parse_route() {
+        let Message::Event(NetEvent::Route(route)) = parse_one(ROUTE_DEFAULT_V4) else {
+            panic!("expected route event");
+        };
+        assert_eq!(
+            route,
+            RouteEvent {
+                ipv6: false,
+                table: RT_TABLE_MAIN,
+                route_type: RTN_UNICAST,
+                dst_len: 0,
+                gateway: Some("192.0.2.1".parse().unwrap()),
+                oif: Some(4),
+                removed: false,
+            }
+        );
+        assert!(route.is_default());
+
+        let Message::Event(NetEvent::Route(route)) = parse_one(ROUTE_DEFAULT_V6) else {
+            panic!("expected route event");
+        };
+        assert!(route.ipv6);
+        assert!(route.is_default());
+        assert_eq!(route.gateway, Some("fd00::1".parse().unwrap()));
+
+        // Local route of the loopback interface.
+        let Message::Event(NetEvent::Route(route)) = parse_one(ROUTE_LOCAL) else {
+            panic!("expected route event");
+        };
+        assert!(!route.is_default());
+    }
+
+    #[test]
+    fn test_parse_multiple_messages() {
+        let datagram = [ADDR_V4, ROUTE_DEFAULT_V4, DONE].map(bytes).concat();
+        let messages = parse_messages(&datagram).unwrap();
+        assert_eq!(messages.len(), 3);
+        assert!(matches!(messages[0], Message::Event(NetEvent::Address(_))));
+        assert!(matches!(messages[1], Message::Event(NetEvent::Route(_))));
+        assert_eq!(messages[2], Message::Done);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        let mut truncated = bytes(ADDR_V4);
+        truncated.truncate(40);
+        let err = parse_messages(&truncated).unwrap_err();
+        assert_eq!(err.to_string(), "invalid netlink message length 88");
+    }
+
+    #[test]
+    fn test_dump_request() {
+        let request = dump_request(RTM_GETADDR, 7);
+        assert_eq!(request.len(), 24);
+        let messages = parse_messages(&request).unwrap();
+        assert_eq!(messages, vec![Message::Other]);
+    }
+}
@@ -1,6 +1,7 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, process::Stdio};
 
 use anyhow::Context;
+use tokio::io::{AsyncBufReadExt, BufReader};
 
 use crate::cfg::{Alert, AlertSeverity};
 
@@ -9,6 +10,50 @@ pub struct PreparedAlert {
     pub alert: Alert,
     pub group: Option<String>,
     pub variables: HashMap<String, String>,
+    /// Whether the alert was raised by the first observation of a state
+    /// after startup, rather than by a change of state.
+    /// Such alerts are only sent if [`Alert::on_startup`] is set.
+    pub initial: bool,
+    pub action: Option<NotifyAction>,
+}
+
+impl PreparedAlert {
+    /// Mark the alert as raised by the initial state observation.
+    pub fn initial(mut self, initial: bool) -> Self {
+        self.initial = initial;
+        self
+    }
+
+    /// Add a button to the notification.
+    pub fn action(mut self, action: impl Into<Option<NotifyAction>>) -> Self {
+        self.action = action.into();
+        self
+    }
+
+    fn is_suppressed(&self) -> bool {
+        self.initial && !self.alert.on_startup
+    }
+}
+
+/// A notification button that runs a command when clicked.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NotifyAction {
+    pub label: String,
+    /// The program and its arguments. Variables are substituted as in the
+    /// alert text.
+    pub command: Vec<String>,
+}
+
+/// The notify-send action key, printed when the button is clicked.
+const ACTION_KEY: &str = "panorama";
+
+/// Substitute the ${variables} of a template.
+pub fn render(template: &str, variables: &HashMap<String, String>) -> String {
+    let mut out = template.to_string();
+    for (key, value) in variables {
+        out = out.replace(&format!("${{{}}}", key), value);
+    }
+    out
 }
 
 #[derive(Clone)]
@@ -45,20 +90,12 @@ impl State {
 
         let mut cmd = tokio::process::Command::new("notify-send");
 
-        let mut summary = alert.alert.summary.clone();
-
-        for (key, value) in &alert.variables {
-            summary = summary.replace(&format!("${{{}}}", key), value);
-        }
-        let message = if let Some(msg) = &alert.alert.message {
-            let mut msg = msg.clone();
-            for (key, value) in &alert.variables {
-                msg = msg.replace(&format!("${{{}}}", key), value);
-            }
-            Some(msg)
-        } else {
-            None
-        };
+        let summary = render(&alert.alert.summary, &alert.variables);
+        let message = alert
+            .alert
+            .message
+            .as_ref()
+            .map(|msg| render(msg, &alert.variables));
 
         cmd
             // Print the notification ID so it can be replaced.
@@ -77,11 +114,28 @@ impl State {
             }
         }
 
+        if let Some(action) = &alert.action {
+            cmd.arg(format!("--action={ACTION_KEY}={}", action.label));
+        }
+
         cmd.arg(summary);
         if let Some(message) = message {
             cmd.arg(message);
         }
 
+        if let Some(action) = &alert.action {
+            let command = action
+                .command
+                .iter()
+                .map(|arg| render(arg, &alert.variables))
+                .collect();
+            let id = spawn_with_action(cmd, command).await?;
+            if let Some(group) = &alert.group {
+                self.category_ids.insert(group.to_string(), id);
+            }
+            return Ok(());
+        }
+
         let out = cmd
             .output()
             .await
@@ -109,6 +163,63 @@ impl State {
     }
 }
 
+/// Show a notification with an action button.
+///
+/// notify-send keeps running until the notification is closed, and prints
+/// the key of the clicked action, so it is awaited in the background.
+async fn spawn_with_action(
+    mut cmd: tokio::process::Command,
+    command: Vec<String>,
+) -> Result<u64, anyhow::Error> {
+    let mut child = cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("could not execute 'notify-send'")?;
+    let stdout = child.stdout.take().context("missing notify-send stdout")?;
+    let mut lines = BufReader::new(stdout).lines();
+
+    let id = lines
+        .next_line()
+        .await
+        .context("could not read notify-send output")?
+        .context("'notify-send' printed no notification ID - actions may be unsupported")?
+        .trim()
+        .parse::<u64>()
+        .context("could not parse notification ID")?;
+
+    tokio::task::spawn_local(async move {
+        while let Ok(Some(line)) = lines.next_line().await {
+            if line.trim() == ACTION_KEY {
+                run_action(&command).await;
+            }
+        }
+        let _ = child.wait().await;
+    });
+
+    Ok(id)
+}
+
+async fn run_action(command: &[String]) {
+    let Some((program, args)) = command.split_first() else {
+        return;
+    };
+    tracing::debug!(?command, "running notification action");
+    match tokio::process::Command::new(program)
+        .args(args)
+        .status()
+        .await
+    {
+        Ok(status) if status.success() => {}
+        Ok(status) => tracing::warn!(?command, %status, "notification action failed"),
+        Err(err) => tracing::warn!(
+            ?command,
+            error = &err as &dyn std::error::Error,
+            "could not run notification action"
+        ),
+    }
+}
+
 impl Notifier {
     pub fn start() -> (Self, tokio::task::JoinHandle<Result<(), anyhow::Error>>) {
         let (tx, rx) = tokio::sync::mpsc::channel(100);
@@ -128,7 +239,20 @@ impl Notifier {
         (s, join)
     }
 
+    /// Create a notifier that forwards alerts to the returned receiver
+    /// instead of displaying them.
+    #[cfg(test)]
+    pub fn test_channel() -> (Self, tokio::sync::mpsc::Receiver<PreparedAlert>) {
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+        (Self { sender: tx }, rx)
+    }
+
     pub async fn notify(&self, alert: PreparedAlert) -> Result<(), anyhow::Error> {
+        if alert.is_suppressed() {
+            tracing::trace!(summary=%alert.alert.summary, "skipping alert for initial state - on_startup is disabled");
+            return Ok(());
+        }
+
         self.sender
             .send(alert)
             .await
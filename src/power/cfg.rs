@@ -8,6 +8,13 @@ pub struct PowerConfig {
     pub enabled: bool,
     #[serde(default = "PowerConfig::default_refresh_interval_seconds")]
     pub refresh_interval_seconds: u64,
+    /// Window in which bursts of udev power supply events are coalesced into
+    /// a single refresh.
+    #[serde(default = "PowerConfig::default_udev_debounce_milliseconds")]
+    pub udev_debounce_milliseconds: u64,
+    /// How long a plugged in/unplugged state must hold before it is announced.
+    #[serde(default = "PowerConfig::default_mode_change_dwell_seconds")]
+    pub mode_change_dwell_seconds: u64,
     #[serde(default = "PowerConfig::default_phases")]
     pub phases: Vec<BatteryPhase>,
 
@@ -15,6 +22,23 @@ pub struct PowerConfig {
     pub alert_battery_activated: Option<Alert>,
     #[serde(default = "PowerConfig::default_alert_battery_deactivated")]
     pub alert_battery_deactivated: Option<Alert>,
+
+    /// Battery charge thresholds to apply on startup and whenever a battery
+    /// is added.
+    /// Requires write access to the `charge_control_*_threshold` sysfs files.
+    #[serde(default)]
+    pub charge_thresholds: Option<ChargeThresholds>,
+
+    /// Alerts for batteries of peripherals like mice, keyboards and headsets.
+    #[serde(default)]
+    pub device_batteries: DeviceBatteryConfig,
+
+    /// Monitor a UPS through a NUT `upsd` server instead of the system
+    /// battery.
+    /// Battery alerts get the additional variables ${ups_status} and
+    /// ${runtime_minutes}.
+    #[serde(default)]
+    pub ups: Option<UpsConfig>,
 }
 
 impl PowerConfig {
@@ -29,32 +53,20 @@ impl PowerConfig {
             self.phases = Self::default_phases();
         }
 
-        let mut names = std::collections::HashSet::new();
-        for (index, phase) in self.phases.iter().enumerate() {
-            if !names.insert(&phase.name) {
-                anyhow::bail!("Phase '{}' is defined multiple times", phase.name);
-            }
+        validate_phases(&self.phases)?;
 
-            if phase.from > phase.to {
-                return Err(anyhow::anyhow!(
-                    "Phase '{}' has invalid range: {}..{}",
-                    phase.name,
-                    phase.from,
-                    phase.to
-                ));
-            }
+        if self.device_batteries.phases.is_empty() {
+            self.device_batteries.phases = DeviceBatteryConfig::default_phases();
+        }
+        validate_phases(&self.device_batteries.phases)?;
 
-            if index > 0 {
-                let prev = &self.phases[index - 1];
-                if phase.from <= prev.to {
-                    return Err(anyhow::anyhow!(
-                        "Phase '{}' has overlapping range with phase '{}': {}..{}",
-                        phase.name,
-                        prev.name,
-                        phase.from,
-                        phase.to
-                    ));
-                }
+        if let Some(thresholds) = &self.charge_thresholds {
+            thresholds.validate()?;
+        }
+
+        if let Some(ups) = &self.ups {
+            if ups.timeout_seconds == 0 {
+                anyhow::bail!("'power.ups.timeout_seconds' must be greater than 0");
             }
         }
 
@@ -65,6 +77,14 @@ impl PowerConfig {
         5
     }
 
+    pub fn default_udev_debounce_milliseconds() -> u64 {
+        500
+    }
+
+    pub fn default_mode_change_dwell_seconds() -> u64 {
+        2
+    }
+
     pub fn default_phases() -> Vec<BatteryPhase> {
         vec![
             BatteryPhase {
@@ -129,7 +149,7 @@ impl PowerConfig {
     pub fn default_alert_battery_deactivated() -> Option<Alert> {
         Some(Alert {
             severity: AlertSeverity::Info,
-            on_startup: true,
+            on_startup: false,
             repeat_after_seconds: None,
             summary: "Plugged in! Battery is charging (${capacity}%)".to_string(),
             message: None,
@@ -143,9 +163,14 @@ impl Default for PowerConfig {
         Self {
             enabled: default_true(),
             refresh_interval_seconds: Self::default_refresh_interval_seconds(),
+            udev_debounce_milliseconds: Self::default_udev_debounce_milliseconds(),
+            mode_change_dwell_seconds: Self::default_mode_change_dwell_seconds(),
             phases: Self::default_phases(),
             alert_battery_activated: Self::default_alert_battery_activated(),
             alert_battery_deactivated: Self::default_alert_battery_deactivated(),
+            charge_thresholds: None,
+            device_batteries: DeviceBatteryConfig::default(),
+            ups: None,
         }
     }
 }
@@ -154,6 +179,134 @@ fn default_true() -> bool {
     true
 }
 
+fn validate_phases(phases: &[BatteryPhase]) -> Result<(), anyhow::Error> {
+    let mut names = std::collections::HashSet::new();
+    for (index, phase) in phases.iter().enumerate() {
+        if !names.insert(&phase.name) {
+            anyhow::bail!("Phase '{}' is defined multiple times", phase.name);
+        }
+
+        if phase.from > phase.to {
+            return Err(anyhow::anyhow!(
+                "Phase '{}' has invalid range: {}..{}",
+                phase.name,
+                phase.from,
+                phase.to
+            ));
+        }
+
+        if index > 0 {
+            let prev = &phases[index - 1];
+            if phase.from <= prev.to {
+                return Err(anyhow::anyhow!(
+                    "Phase '{}' has overlapping range with phase '{}': {}..{}",
+                    phase.name,
+                    prev.name,
+                    phase.from,
+                    phase.to
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Connection to a NUT `upsd` server.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UpsConfig {
+    #[serde(default = "UpsConfig::default_host")]
+    pub host: String,
+    #[serde(default = "UpsConfig::default_port")]
+    pub port: u16,
+    /// Name of the UPS as configured in `ups.conf`.
+    #[serde(default = "UpsConfig::default_name")]
+    pub name: String,
+    #[serde(default = "UpsConfig::default_timeout_seconds")]
+    pub timeout_seconds: u64,
+}
+
+impl UpsConfig {
+    fn default_host() -> String {
+        "127.0.0.1".to_string()
+    }
+
+    fn default_port() -> u16 {
+        3493
+    }
+
+    fn default_name() -> String {
+        "ups".to_string()
+    }
+
+    fn default_timeout_seconds() -> u64 {
+        5
+    }
+}
+
+impl Default for UpsConfig {
+    fn default() -> Self {
+        Self {
+            host: Self::default_host(),
+            port: Self::default_port(),
+            name: Self::default_name(),
+            timeout_seconds: Self::default_timeout_seconds(),
+        }
+    }
+}
+
+/// Battery alerts for peripherals (power supplies with `scope=Device`).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeviceBatteryConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Available variables: ${device}, ${name}, ${capacity}
+    #[serde(default = "DeviceBatteryConfig::default_phases")]
+    pub phases: Vec<BatteryPhase>,
+}
+
+impl DeviceBatteryConfig {
+    pub fn default_phases() -> Vec<BatteryPhase> {
+        vec![
+            BatteryPhase {
+                name: "almost_empty".to_string(),
+                from: 0,
+                to: 5,
+                alert: Some(Alert {
+                    severity: AlertSeverity::Critical,
+                    on_startup: true,
+                    repeat_after_seconds: Some(60 * 10),
+                    summary: "${device} battery is almost empty! (${capacity}%)".to_string(),
+                    message: None,
+                    expire_after_seconds: None,
+                }),
+            },
+            BatteryPhase {
+                name: "low".to_string(),
+                from: 6,
+                to: 15,
+                alert: Some(Alert {
+                    severity: AlertSeverity::Warning,
+                    on_startup: true,
+                    repeat_after_seconds: None,
+                    summary: "${device} battery is low (${capacity}%)".to_string(),
+                    message: None,
+                    expire_after_seconds: Some(60),
+                }),
+            },
+        ]
+    }
+}
+
+impl Default for DeviceBatteryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            phases: Self::default_phases(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct BatteryPhase {
     pub name: String,
@@ -162,6 +315,61 @@ pub struct BatteryPhase {
     pub alert: Option<Alert>,
 }
 
+/// Charge control thresholds for laptop batteries.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChargeThresholds {
+    /// Name of the battery to configure (eg "BAT0").
+    /// All batteries that support thresholds are configured if not set.
+    pub battery: Option<String>,
+    /// Start charging when the capacity drops below this percentage.
+    pub start: Option<u8>,
+    /// Stop charging when the capacity reaches this percentage.
+    pub end: Option<u8>,
+    /// Sent when the thresholds could not be written.
+    /// Available variables: ${battery}, ${error}
+    #[serde(default = "ChargeThresholds::default_alert_write_failed")]
+    pub alert_write_failed: Option<Alert>,
+}
+
+impl ChargeThresholds {
+    /// Thresholds applied while travel mode is active, allowing the battery
+    /// to charge to 100%.
+    pub const TRAVEL_MODE: (u8, u8) = (0, 100);
+
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if let Some(start) = self.start {
+            if start > 100 {
+                anyhow::bail!("'power.charge_thresholds.start' must be at most 100");
+            }
+        }
+        if let Some(end) = self.end {
+            if end > 100 {
+                anyhow::bail!("'power.charge_thresholds.end' must be at most 100");
+            }
+        }
+        if let (Some(start), Some(end)) = (self.start, self.end) {
+            if start >= end {
+                anyhow::bail!(
+                    "'power.charge_thresholds.start' ({start}) must be lower than 'end' ({end})"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub fn default_alert_write_failed() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Warning,
+            on_startup: true,
+            repeat_after_seconds: None,
+            expire_after_seconds: Some(30),
+            summary: "Could not set charge thresholds for battery ${battery}".to_string(),
+            message: Some("${error}".to_string()),
+        })
+    }
+}
+
+#[allow(dead_code)]
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum Range {
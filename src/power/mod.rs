@@ -1,187 +1,514 @@
 //! Read power supply information from the system.
 //! See https://www.kernel.org/doc/Documentation/ABI/testing/sysfs-class-power
 
-use std::time::SystemTime;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
 
 use anyhow::Context;
-use futures::StreamExt;
+use futures::{Stream, StreamExt};
+use tokio::sync::{mpsc, oneshot};
 
-use crate::notify::Notifier;
+use crate::{
+    cfg::Alert,
+    notify::Notifier,
+    udev::{Action, Lagged, Subscriber, UdevHub},
+};
 
-use self::{cfg::PowerConfig, system::PowerSupplyType};
+use self::{
+    cfg::{BatteryPhase, ChargeThresholds, PowerConfig},
+    system::{BatteryStatus, PowerSupplyType},
+};
 
 pub mod cfg;
+pub mod nut;
 pub mod system;
 
 const ALERT_GROUP_BATTERY: &str = "panorama.battery_status";
+const ALERT_GROUP_DEVICE_BATTERY: &str = "panorama.device_battery";
+const ALERT_GROUP_CHARGE_THRESHOLDS: &str = "panorama.charge_thresholds";
+
+/// Commands that can be sent to a running [`PowerManager`].
+pub enum PowerCommand {
+    SetTravelMode {
+        enabled: bool,
+        reply: oneshot::Sender<Result<(), anyhow::Error>>,
+    },
+}
 
 pub struct PowerManager {
     config: PowerConfig,
     notifier: Notifier,
+    commands: mpsc::Receiver<PowerCommand>,
+    sysfs_root: PathBuf,
     battery_phase: Option<PhaseTransition>,
-    mode: PowerMode,
+    /// Phase state of peripheral batteries, keyed by power supply name.
+    device_phases: HashMap<String, Option<PhaseTransition>>,
+    device_batteries_observed: bool,
+    /// Current power mode, `None` until the first observation.
+    mode: Option<PowerMode>,
+    /// Observed mode that has not been announced yet, see
+    /// `mode_change_dwell_seconds`.
+    pending_mode: Option<PendingMode>,
+    travel_mode: bool,
 }
 
 impl PowerManager {
-    pub async fn start(config: PowerConfig, notifier: Notifier) -> Result<(), anyhow::Error> {
-        let manager = Self::new(config, notifier)?;
-        tokio::task::spawn_local(async move { manager.run().await })
+    pub fn subscribe(hub: &UdevHub) -> Subscriber {
+        hub.subscribe(vec![("power_supply".to_string(), None)], &[])
+    }
+
+    pub async fn start(
+        config: PowerConfig,
+        notifier: Notifier,
+        commands: mpsc::Receiver<PowerCommand>,
+        events: Subscriber,
+    ) -> Result<(), anyhow::Error> {
+        let manager = Self::new(config, notifier, commands)?;
+        tokio::task::spawn_local(async move { manager.run(events).await })
             .await
             .context("PowerManager failed")??;
 
         Ok(())
     }
 
-    fn new(config: PowerConfig, notifier: Notifier) -> Result<Self, anyhow::Error> {
+    fn new(
+        config: PowerConfig,
+        notifier: Notifier,
+        commands: mpsc::Receiver<PowerCommand>,
+    ) -> Result<Self, anyhow::Error> {
         let config = config.validate()?;
         Ok(Self {
             config,
             notifier,
+            commands,
+            sysfs_root: PathBuf::from(system::SYSFS_POWER_SUPPLY),
             battery_phase: None,
-            mode: PowerMode::PluggedIn,
+            device_phases: HashMap::new(),
+            device_batteries_observed: false,
+            mode: None,
+            pending_mode: None,
+            travel_mode: false,
         })
     }
 
-    async fn run(mut self) -> Result<(), anyhow::Error> {
-        // Listen to udev power_supply events to learn of state changes as
+    async fn run(self, events: Subscriber) -> Result<(), anyhow::Error> {
+        // udev power_supply events are used to learn of state changes as
         // quickly as possible.
-        let builder = tokio_udev::MonitorBuilder::new()
-            .expect("Couldn't create builder")
-            .match_subsystem("power_supply")
-            .context("could not create power_supply filter")?;
-
-        let mut stream: tokio_udev::AsyncMonitorSocket = builder
-            .listen()
-            .context("Couldn't listen on udev socket")?
-            .try_into()
-            .context("could not create udev monitor socket")?;
+        // No need to actually interpret the udev event data, we re-parse the
+        // /sys/ data anyway. udev is just used to get fast notifications.
+        // Newly added batteries need the charge thresholds applied though,
+        // which is done as well when events were missed.
+        let events = events.into_stream().map(|res| SupplyEvent {
+            added: match res {
+                Ok(ev) => ev.action == Action::Add,
+                Err(err) => err.is::<Lagged>(),
+            },
+        });
+
+        self.run_events(events).await
+    }
 
-        eprintln!("reading udev event stream...");
+    async fn run_events(
+        mut self,
+        mut events: impl Stream<Item = SupplyEvent> + Unpin,
+    ) -> Result<(), anyhow::Error> {
+        self.apply_charge_thresholds().await?;
 
         loop {
             self.tick().await?;
 
-            let timeout = tokio::time::sleep(std::time::Duration::from_secs(
-                self.config.refresh_interval_seconds,
-            ));
-
-            // Wait for a tick timeout, or a udev power supply event.
-            tokio::select! {
-                _ = timeout => {},
-                _ev = stream.next() => {
-                    // No need to actually interpret the udev event data,
-                    // we re-parse the /sys/ data anyway.
-                    // udev is just used to get fast notifications.
-                    // TODO: maybe batch up events to prevent constant re-computations?
-                    // (unplugging will trigger two notifications, one for the
-                    // AC and one for the battery)
+            match self.wait(&mut events).await {
+                Wakeup::Timeout => {}
+                Wakeup::Supplies { added } => {
+                    if added {
+                        self.apply_charge_thresholds().await?;
+                    }
+                }
+                Wakeup::Command(cmd) => {
+                    self.handle_command(cmd).await?;
                 }
             }
         }
     }
 
-    async fn tick(&mut self) -> Result<(), anyhow::Error> {
-        let supplies = tokio::task::spawn_blocking(system::read_all_supplies).await??;
+    /// Wait for the next tick timeout, a power supply event or a command.
+    ///
+    /// Bursts of supply events are coalesced into a single wakeup, since
+    /// unplugging triggers multiple events (one for the AC and one for the
+    /// battery).
+    async fn wait(&mut self, events: &mut (impl Stream<Item = SupplyEvent> + Unpin)) -> Wakeup {
+        let mut timeout = Duration::from_secs(self.config.refresh_interval_seconds);
+        if let Some(pending) = &self.pending_mode {
+            let dwell = Duration::from_secs(self.config.mode_change_dwell_seconds);
+            timeout = timeout.min(dwell.saturating_sub(pending.since.elapsed()));
+        }
 
-        let ac = supplies.iter().find_map(|s| s.kind.as_main());
-        let ac_online = ac.map(|s| s.online).unwrap_or(false);
+        tokio::select! {
+            _ = tokio::time::sleep(timeout) => Wakeup::Timeout,
+            Some(ev) = events.next() => {
+                let mut added = ev.added;
+
+                let window = tokio::time::sleep(Duration::from_millis(
+                    self.config.udev_debounce_milliseconds,
+                ));
+                tokio::pin!(window);
+                loop {
+                    tokio::select! {
+                        _ = &mut window => break,
+                        Some(ev) = events.next() => {
+                            added |= ev.added;
+                        }
+                    }
+                }
 
-        // TODO: support multiple batteries.
-        let battery_opt = supplies
-            .iter()
-            .find_map(|s| match &s.kind {
-                PowerSupplyType::Battery(b) => Some((s, b)),
-                PowerSupplyType::Main(_) => None,
-            })
-            .map(|x| x.1);
+                Wakeup::Supplies { added }
+            }
+            Some(cmd) = self.commands.recv() => Wakeup::Command(cmd),
+        }
+    }
 
-        let mut variables = std::collections::HashMap::new();
-        if let Some(bat) = &battery_opt {
-            variables.insert("capacity".to_string(), bat.capacity.to_string());
+    async fn handle_command(&mut self, cmd: PowerCommand) -> Result<(), anyhow::Error> {
+        match cmd {
+            PowerCommand::SetTravelMode { enabled, reply } => {
+                if self.config.charge_thresholds.is_none() {
+                    reply
+                        .send(Err(anyhow::anyhow!(
+                            "'power.charge_thresholds' is not configured"
+                        )))
+                        .ok();
+                    return Ok(());
+                }
+
+                tracing::info!(enabled, "setting travel mode");
+                self.travel_mode = enabled;
+                let failures = self.apply_charge_thresholds().await?;
+                let res = if failures.is_empty() {
+                    Ok(())
+                } else {
+                    let failures = failures
+                        .iter()
+                        .map(|(name, err)| format!("battery {name}: {err:#}"))
+                        .collect::<Vec<_>>();
+                    Err(anyhow::anyhow!(failures.join("; ")))
+                };
+                reply.send(res).ok();
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the configured charge thresholds to all matching batteries.
+    ///
+    /// Write failures are reported as alerts rather than treated as fatal,
+    /// and are returned so callers can relay them.
+    async fn apply_charge_thresholds(
+        &mut self,
+    ) -> Result<Vec<(String, anyhow::Error)>, anyhow::Error> {
+        let Some(cfg) = &self.config.charge_thresholds else {
+            return Ok(Vec::new());
+        };
+
+        let (start, end) = if self.travel_mode {
+            let (start, end) = ChargeThresholds::TRAVEL_MODE;
+            (Some(start), Some(end))
+        } else {
+            (cfg.start, cfg.end)
+        };
+
+        let root = self.sysfs_root.clone();
+        let battery = cfg.battery.clone();
+        let results = tokio::task::spawn_blocking(move || {
+            let names = match system::list_charge_control_batteries(&root) {
+                Ok(names) => names,
+                Err(err) => return vec![("*".to_string(), Err(err))],
+            };
+            names
+                .into_iter()
+                .filter(|name| battery.as_ref().is_none_or(|b| b == name))
+                .map(|name| {
+                    let res = system::write_charge_thresholds(&root.join(&name), start, end);
+                    (name, res)
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .context("charge threshold task failed")?;
+
+        if results.is_empty() {
+            tracing::warn!("no battery with charge threshold support found");
         }
 
-        if ac_online && self.mode != PowerMode::PluggedIn {
-            tracing::trace!("power mode changed to plugged in");
-            self.mode = PowerMode::PluggedIn;
-            if let Some(alert) = &self.config.alert_battery_deactivated {
-                let full = alert.prepare(ALERT_GROUP_BATTERY.to_string(), variables.clone());
+        let mut failures = Vec::new();
+        for (name, res) in results {
+            match res {
+                Ok(()) => {
+                    tracing::debug!(battery=%name, ?start, ?end, "applied charge thresholds");
+                }
+                Err(err) => {
+                    tracing::warn!(battery=%name, error = &*err, "could not apply charge thresholds");
+                    if let Some(alert) = &cfg.alert_write_failed {
+                        let variables = HashMap::from([
+                            ("battery".to_string(), name.clone()),
+                            ("error".to_string(), format!("{err:#}")),
+                        ]);
+                        let full =
+                            alert.prepare(ALERT_GROUP_CHARGE_THRESHOLDS.to_string(), variables);
+                        self.notifier.notify(full).await?;
+                    }
+                    failures.push((name, err));
+                }
+            }
+        }
+
+        Ok(failures)
+    }
+
+    async fn tick(&mut self) -> Result<(), anyhow::Error> {
+        let root = self.sysfs_root.clone();
+        let supplies =
+            tokio::task::spawn_blocking(move || system::read_all_supplies(&root)).await??;
+
+        let source = match &self.config.ups {
+            Some(ups) => match nut::query(ups).await {
+                Ok(status) => Some(PowerSourceStatus::from_ups(&status)),
+                Err(err) => {
+                    tracing::warn!(error = &*err, "could not query UPS status");
+                    None
+                }
+            },
+            None => Some(PowerSourceStatus::from_supplies(&supplies)),
+        };
+        if let Some(source) = source {
+            self.tick_source(source).await?;
+        }
+
+        if self.config.device_batteries.enabled {
+            self.tick_device_batteries(&supplies).await?;
+        }
+
+        tracing::trace!("power tick");
+
+        Ok(())
+    }
+
+    /// Update the power mode and battery phase of the main power source.
+    async fn tick_source(&mut self, source: PowerSourceStatus) -> Result<(), anyhow::Error> {
+        let PowerSourceStatus {
+            ac_online,
+            capacity,
+            low_battery,
+            variables,
+        } = source;
+
+        let observed = if ac_online {
+            PowerMode::PluggedIn
+        } else {
+            PowerMode::Battery
+        };
+
+        // The first observation is the initial state, which is not subject
+        // to the dwell time.
+        let initial = self.mode.is_none();
+
+        // Only announce a mode change once the new mode has held for the
+        // configured dwell time, to avoid flapping on transient states.
+        let changed = if initial {
+            true
+        } else if self.mode.as_ref() == Some(&observed) {
+            self.pending_mode = None;
+            false
+        } else {
+            let dwell = Duration::from_secs(self.config.mode_change_dwell_seconds);
+            let since = match &self.pending_mode {
+                Some(pending) if pending.mode == observed => pending.since,
+                _ => {
+                    let now = tokio::time::Instant::now();
+                    self.pending_mode = Some(PendingMode {
+                        mode: observed.clone(),
+                        since: now,
+                    });
+                    now
+                }
+            };
+            since.elapsed() >= dwell
+        };
+
+        if changed {
+            self.pending_mode = None;
+            self.mode = Some(observed.clone());
+
+            let alert = match observed {
+                PowerMode::PluggedIn => {
+                    tracing::trace!("power mode changed to plugged in");
+                    &self.config.alert_battery_deactivated
+                }
+                PowerMode::Battery => {
+                    tracing::trace!("power mode changed to battery");
+                    &self.config.alert_battery_activated
+                }
+            };
+            if let Some(alert) = alert {
+                let full = alert
+                    .prepare(ALERT_GROUP_BATTERY.to_string(), variables.clone())
+                    .initial(initial);
                 self.notifier.notify(full).await?;
             }
-        } else if !ac_online && self.mode != PowerMode::Battery {
-            tracing::trace!("power mode changed to battery");
-            self.mode = PowerMode::Battery;
+        }
 
-            if let Some(alert) = &self.config.alert_battery_activated {
-                let full = alert.prepare(ALERT_GROUP_BATTERY.to_string(), variables.clone());
+        if self.mode == Some(PowerMode::Battery) {
+            let phase = if low_battery {
+                // The source signalled a low battery, which always maps to the
+                // lowest phase.
+                self.config.phases.first()
+            } else if let Some(capacity) = capacity {
+                self.config
+                    .phases
+                    .iter()
+                    .find(|p| capacity >= p.from && capacity <= p.to)
+            } else {
+                None
+            };
+
+            if let Some(alert) = PhaseTransition::update(&mut self.battery_phase, phase) {
+                let full = alert
+                    .prepare(ALERT_GROUP_BATTERY.to_string(), variables)
+                    .initial(initial);
                 self.notifier.notify(full).await?;
             }
         }
 
-        if self.mode == PowerMode::Battery {
-            let bat = battery_opt.as_ref().unwrap();
+        Ok(())
+    }
+
+    /// Check the batteries of peripherals like mice, keyboards and headsets.
+    async fn tick_device_batteries(
+        &mut self,
+        supplies: &[system::PowerSupply],
+    ) -> Result<(), anyhow::Error> {
+        // Forget devices that were disconnected.
+        self.device_phases
+            .retain(|name, _| supplies.iter().any(|s| &s.name == name));
+
+        for supply in supplies {
+            let PowerSupplyType::DeviceBattery(bat) = &supply.kind else {
+                continue;
+            };
+
+            let state = self.device_phases.entry(supply.name.clone()).or_default();
+
+            // Only alert while the device is actually draining.
+            if matches!(bat.status, BatteryStatus::Charging | BatteryStatus::Full) {
+                *state = None;
+                continue;
+            }
 
             let phase = self
                 .config
+                .device_batteries
                 .phases
                 .iter()
                 .find(|p| bat.capacity >= p.from && bat.capacity <= p.to);
 
-            match (phase, &mut self.battery_phase) {
-                (Some(new), Some(status)) => {
-                    if new.name != status.name {
-                        status.enter(&new.name);
-
-                        if let Some(alert) = &new.alert {
-                            let full = alert.prepare(ALERT_GROUP_BATTERY.to_string(), variables);
-                            self.notifier.notify(full).await?;
-                        }
-                    } else {
-                        let now = SystemTime::now();
-                        let last_notified_at = status.last_notified_at.unwrap_or(now);
-
-                        let elapsed = now.duration_since(last_notified_at).unwrap();
-
-                        if let Some(alert) = &new.alert {
-                            if let Some(repeat_after) = alert.repeat_after_seconds {
-                                if elapsed.as_secs() >= repeat_after {
-                                    let full = alert.prepare(
-                                        ALERT_GROUP_BATTERY.to_string(),
-                                        variables.clone(),
-                                    );
-                                    self.notifier.notify(full).await?;
-                                    status.last_notified_at = Some(now);
-                                }
-                            }
-                        }
-                    }
-                }
-                (Some(new), None) => {
-                    let status = PhaseTransition {
-                        name: new.name.clone(),
-                        entered_at: SystemTime::now(),
-                        last_notified_at: None,
-                    };
-                    self.battery_phase = Some(status);
-
-                    if let Some(alert) = &new.alert {
-                        let full =
-                            alert.prepare(ALERT_GROUP_BATTERY.to_string(), variables.clone());
-                        self.notifier.notify(full).await?;
-                    }
-                }
-                (None, Some(_status)) => {
-                    self.battery_phase = None;
-                }
-                _ => {}
+            if let Some(alert) = PhaseTransition::update(state, phase) {
+                let device = bat
+                    .model_name
+                    .clone()
+                    .unwrap_or_else(|| supply.name.clone());
+                let variables = HashMap::from([
+                    ("capacity".to_string(), bat.capacity.to_string()),
+                    ("device".to_string(), device),
+                    ("name".to_string(), supply.name.clone()),
+                ]);
+                let group = format!("{ALERT_GROUP_DEVICE_BATTERY}.{}", supply.name);
+                let full = alert
+                    .prepare(group, variables)
+                    .initial(!self.device_batteries_observed);
+                self.notifier.notify(full).await?;
             }
         }
 
-        tracing::trace!("power tick");
+        self.device_batteries_observed = true;
 
         Ok(())
     }
 }
 
+#[derive(Clone, Copy, Debug)]
+struct SupplyEvent {
+    added: bool,
+}
+
+enum Wakeup {
+    Timeout,
+    Supplies { added: bool },
+    Command(PowerCommand),
+}
+
+#[derive(Clone, Debug)]
+struct PendingMode {
+    mode: PowerMode,
+    since: tokio::time::Instant,
+}
+
+/// Normalized status of the main power source, either read from sysfs or
+/// from a UPS.
+#[derive(Clone, Debug)]
+struct PowerSourceStatus {
+    ac_online: bool,
+    capacity: Option<u8>,
+    low_battery: bool,
+    variables: HashMap<String, String>,
+}
+
+impl PowerSourceStatus {
+    fn from_supplies(supplies: &[system::PowerSupply]) -> Self {
+        // Any online supply counts, there is one USB supply per USB-C port.
+        let ac_online = supplies
+            .iter()
+            .filter_map(|s| s.kind.as_main())
+            .any(|m| m.online);
+
+        // TODO: support multiple batteries.
+        let battery_opt = supplies.iter().find_map(|s| match &s.kind {
+            PowerSupplyType::Battery(b) => Some(b),
+            PowerSupplyType::DeviceBattery(_) | PowerSupplyType::Main(_) => None,
+        });
+
+        let mut variables = HashMap::new();
+        if let Some(bat) = &battery_opt {
+            variables.insert("capacity".to_string(), bat.capacity.to_string());
+        }
+
+        Self {
+            ac_online,
+            capacity: battery_opt.map(|b| b.capacity),
+            low_battery: false,
+            variables,
+        }
+    }
+
+    fn from_ups(status: &nut::UpsStatus) -> Self {
+        let mut variables = HashMap::new();
+        variables.insert("ups_status".to_string(), status.status.clone());
+        if let Some(charge) = status.charge {
+            variables.insert("capacity".to_string(), charge.to_string());
+        }
+        if let Some(runtime) = status.runtime_seconds {
+            variables.insert("runtime_minutes".to_string(), (runtime / 60).to_string());
+        }
+
+        Self {
+            ac_online: !status.on_battery,
+            capacity: status.charge,
+            low_battery: status.low_battery,
+            variables,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 struct PhaseTransition {
     name: String,
@@ -193,6 +520,49 @@ impl PhaseTransition {
     fn enter(&mut self, name: &str) {
         self.name = name.to_string();
         self.entered_at = SystemTime::now();
+        self.last_notified_at = None;
+    }
+
+    /// Track the phase matching the current capacity.
+    ///
+    /// Returns the alert to send, either because a new phase was entered or
+    /// because the alert of the current phase is due for repetition.
+    fn update<'a>(state: &mut Option<Self>, phase: Option<&'a BatteryPhase>) -> Option<&'a Alert> {
+        let now = SystemTime::now();
+
+        let Some(new) = phase else {
+            *state = None;
+            return None;
+        };
+
+        match state {
+            Some(status) if status.name == new.name => {
+                let alert = new.alert.as_ref()?;
+                let repeat_after = alert.repeat_after_seconds?;
+                let last_notified_at = status.last_notified_at.unwrap_or(status.entered_at);
+                let elapsed = now.duration_since(last_notified_at).unwrap_or_default();
+                if elapsed.as_secs() < repeat_after {
+                    return None;
+                }
+                status.last_notified_at = Some(now);
+                Some(alert)
+            }
+            Some(status) => {
+                status.enter(&new.name);
+                let alert = new.alert.as_ref()?;
+                status.last_notified_at = Some(now);
+                Some(alert)
+            }
+            None => {
+                let alert = new.alert.as_ref();
+                *state = Some(PhaseTransition {
+                    name: new.name.clone(),
+                    entered_at: now,
+                    last_notified_at: alert.map(|_| now),
+                });
+                alert
+            }
+        }
     }
 }
 
@@ -201,3 +571,346 @@ pub enum PowerMode {
     Battery,
     PluggedIn,
 }
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::{power::cfg::ChargeThresholds, testutil::TempDir};
+
+    use super::*;
+
+    #[test]
+    fn test_power_source_multiple_mains() {
+        let supply = |name: &str, online| system::PowerSupply {
+            name: name.to_string(),
+            kind: PowerSupplyType::Main(system::PowerSupplyMain { online }),
+        };
+        let supplies = [
+            supply("ucsi-source-psy-USBC000:001", false),
+            supply("AC", true),
+        ];
+        assert!(PowerSourceStatus::from_supplies(&supplies).ac_online);
+
+        let supplies = [supply("ucsi-source-psy-USBC000:001", false)];
+        assert!(!PowerSourceStatus::from_supplies(&supplies).ac_online);
+    }
+
+    fn manager(
+        dir: &TempDir,
+        thresholds: ChargeThresholds,
+    ) -> (PowerManager, mpsc::Receiver<crate::notify::PreparedAlert>) {
+        let (notifier, alerts) = Notifier::test_channel();
+        let (_tx, rx) = mpsc::channel(1);
+        let config = PowerConfig {
+            charge_thresholds: Some(thresholds),
+            ..Default::default()
+        };
+        let mut manager = PowerManager::new(config, notifier, rx).unwrap();
+        manager.sysfs_root = dir.path().to_path_buf();
+        (manager, alerts)
+    }
+
+    fn thresholds() -> ChargeThresholds {
+        ChargeThresholds {
+            battery: None,
+            start: Some(75),
+            end: Some(80),
+            alert_write_failed: ChargeThresholds::default_alert_write_failed(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_charge_thresholds_travel_mode() {
+        let dir = TempDir::new("power-travel");
+        dir.write("BAT0/type", "Battery\n");
+        dir.write("BAT0/charge_control_start_threshold", "0\n");
+        dir.write("BAT0/charge_control_end_threshold", "100\n");
+        let (mut manager, mut alerts) = manager(&dir, thresholds());
+        let read =
+            |file: &str| std::fs::read_to_string(dir.path().join("BAT0").join(file)).unwrap();
+
+        assert!(manager.apply_charge_thresholds().await.unwrap().is_empty());
+        assert_eq!(read("charge_control_end_threshold"), "80");
+
+        let (reply, rx) = oneshot::channel();
+        manager
+            .handle_command(PowerCommand::SetTravelMode {
+                enabled: true,
+                reply,
+            })
+            .await
+            .unwrap();
+        rx.await.unwrap().unwrap();
+        assert_eq!(read("charge_control_start_threshold"), "0");
+        assert_eq!(read("charge_control_end_threshold"), "100");
+
+        let (reply, rx) = oneshot::channel();
+        manager
+            .handle_command(PowerCommand::SetTravelMode {
+                enabled: false,
+                reply,
+            })
+            .await
+            .unwrap();
+        rx.await.unwrap().unwrap();
+        assert_eq!(read("charge_control_start_threshold"), "75");
+        assert_eq!(read("charge_control_end_threshold"), "80");
+
+        assert!(alerts.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_charge_thresholds_write_failure_alerts() {
+        let dir = TempDir::new("power-fail");
+        dir.write("BAT0/type", "Battery\n");
+        dir.write("BAT0/charge_control_end_threshold", "100\n");
+        std::fs::create_dir_all(dir.path().join("BAT0/charge_control_start_threshold")).unwrap();
+        let (mut manager, mut alerts) = manager(&dir, thresholds());
+
+        let failures = manager.apply_charge_thresholds().await.unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, "BAT0");
+
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.variables["battery"], "BAT0");
+
+        // Travel mode reports the failures of all batteries.
+        dir.write("BAT1/type", "Battery\n");
+        dir.write("BAT1/charge_control_end_threshold", "100\n");
+        std::fs::create_dir_all(dir.path().join("BAT1/charge_control_start_threshold")).unwrap();
+        let (reply, rx) = oneshot::channel();
+        manager
+            .handle_command(PowerCommand::SetTravelMode {
+                enabled: true,
+                reply,
+            })
+            .await
+            .unwrap();
+        let err = rx.await.unwrap().unwrap_err().to_string();
+        assert!(err.starts_with("battery BAT0: "), "{err}");
+        assert!(err.contains("; battery BAT1: "), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_device_battery_alerts() {
+        let dir = TempDir::new("power-device");
+        dir.write("AC/type", "Mains\n");
+        dir.write("AC/online", "1\n");
+        dir.write("hid-mouse/type", "Battery\n");
+        dir.write("hid-mouse/scope", "Device\n");
+        dir.write("hid-mouse/model_name", "Wireless Mouse\n");
+        dir.write("hid-mouse/status", "Discharging\n");
+        dir.write("hid-mouse/capacity", "50\n");
+
+        let (notifier, mut alerts) = Notifier::test_channel();
+        let (_tx, rx) = mpsc::channel(1);
+        let mut manager = PowerManager::new(PowerConfig::default(), notifier, rx).unwrap();
+        manager.sysfs_root = dir.path().to_path_buf();
+
+        manager.tick().await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        dir.write("hid-mouse/capacity", "10\n");
+        manager.tick().await.unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.variables["device"], "Wireless Mouse");
+        assert_eq!(alert.variables["capacity"], "10");
+        assert_eq!(
+            alert.group.as_deref(),
+            Some("panorama.device_battery.hid-mouse")
+        );
+
+        // Same phase - no repeated alert.
+        manager.tick().await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        // Charging resets the state.
+        dir.write("hid-mouse/status", "Charging\n");
+        manager.tick().await.unwrap();
+        dir.write("hid-mouse/status", "Discharging\n");
+        manager.tick().await.unwrap();
+        assert!(alerts.try_recv().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_ups_source() {
+        let port = nut::tests::fake_upsd(
+            "ups",
+            vec![
+                ("ups.status", "OB LB"),
+                ("battery.charge", "30"),
+                ("battery.runtime", "240"),
+            ],
+        )
+        .await;
+        let dir = TempDir::new("power-ups");
+
+        let (notifier, mut alerts) = Notifier::test_channel();
+        let (_tx, rx) = mpsc::channel(1);
+        let config = PowerConfig {
+            ups: Some(cfg::UpsConfig {
+                port,
+                ..Default::default()
+            }),
+            mode_change_dwell_seconds: 0,
+            ..Default::default()
+        };
+        let mut manager = PowerManager::new(config, notifier, rx).unwrap();
+        manager.sysfs_root = dir.path().to_path_buf();
+
+        manager.tick().await.unwrap();
+        assert_eq!(manager.mode, Some(PowerMode::Battery));
+
+        let activated = alerts.try_recv().unwrap();
+        assert_eq!(activated.variables["capacity"], "30");
+        assert_eq!(activated.variables["runtime_minutes"], "4");
+
+        // Low battery maps to the lowest phase, despite the 30% charge.
+        let phase = alerts.try_recv().unwrap();
+        assert_eq!(
+            phase.alert.summary,
+            "Battery is almost empty! (${capacity}%)"
+        );
+        assert_eq!(manager.battery_phase.unwrap().name, "almost_empty");
+    }
+
+    fn supply_dir(name: &str, ac_online: bool) -> TempDir {
+        let dir = TempDir::new(name);
+        dir.write("AC/type", "Mains\n");
+        dir.write("AC/online", if ac_online { "1\n" } else { "0\n" });
+        dir.write("BAT0/type", "Battery\n");
+        dir.write("BAT0/status", "Discharging\n");
+        dir.write("BAT0/capacity", "80\n");
+        dir
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_udev_events_debounced() {
+        let dir = supply_dir("power-debounce", true);
+        let (notifier, _alerts) = Notifier::test_channel();
+        let (_tx, rx) = mpsc::channel(1);
+        let mut manager = PowerManager::new(PowerConfig::default(), notifier, rx).unwrap();
+        manager.sysfs_root = dir.path().to_path_buf();
+
+        let (events_tx, events_rx) = futures::channel::mpsc::unbounded();
+        let mut events = events_rx;
+        let start = tokio::time::Instant::now();
+
+        tokio::spawn(async move {
+            events_tx
+                .unbounded_send(SupplyEvent { added: false })
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            events_tx
+                .unbounded_send(SupplyEvent { added: true })
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            events_tx
+                .unbounded_send(SupplyEvent { added: false })
+                .unwrap();
+            // Keep the stream open.
+            tokio::time::sleep(Duration::from_secs(3600)).await;
+            drop(events_tx);
+        });
+
+        let wakeup = manager.wait(&mut events).await;
+        assert!(matches!(wakeup, Wakeup::Supplies { added: true }));
+        assert_eq!(start.elapsed(), Duration::from_millis(500));
+
+        // All events of the burst were consumed.
+        let wakeup = manager.wait(&mut events).await;
+        assert!(matches!(wakeup, Wakeup::Timeout));
+        assert_eq!(start.elapsed(), Duration::from_millis(5500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_mode_change_dwell() {
+        let dir = supply_dir("power-dwell", true);
+        let (notifier, mut alerts) = Notifier::test_channel();
+        let (_tx, rx) = mpsc::channel(1);
+        let mut manager = PowerManager::new(PowerConfig::default(), notifier, rx).unwrap();
+        manager.sysfs_root = dir.path().to_path_buf();
+
+        manager.tick().await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        // A short unplug is not announced.
+        dir.write("AC/online", "0\n");
+        manager.tick().await.unwrap();
+        tokio::time::advance(Duration::from_secs(1)).await;
+        dir.write("AC/online", "1\n");
+        manager.tick().await.unwrap();
+        tokio::time::advance(Duration::from_secs(5)).await;
+        manager.tick().await.unwrap();
+        assert!(alerts.try_recv().is_err());
+        assert_eq!(manager.mode, Some(PowerMode::PluggedIn));
+
+        // A longer one is announced once the dwell time has passed.
+        dir.write("AC/online", "0\n");
+        manager.tick().await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        // The next wakeup happens when the dwell time expires.
+        let (_events_tx, mut events) = futures::channel::mpsc::unbounded::<SupplyEvent>();
+        let start = tokio::time::Instant::now();
+        assert!(matches!(manager.wait(&mut events).await, Wakeup::Timeout));
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+
+        manager.tick().await.unwrap();
+        assert_eq!(manager.mode, Some(PowerMode::Battery));
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(
+            alert.alert.summary,
+            "Unplugged - using battery (${capacity}%)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_on_startup() {
+        let dir = supply_dir("power-startup", false);
+        dir.write("BAT0/capacity", "15\n");
+
+        let (notifier, mut alerts) = Notifier::test_channel();
+        let (_tx, rx) = mpsc::channel(1);
+        let mut config = PowerConfig::default();
+        config.alert_battery_activated.as_mut().unwrap().on_startup = false;
+        let mut manager = PowerManager::new(config, notifier, rx).unwrap();
+        manager.sysfs_root = dir.path().to_path_buf();
+
+        // Already on battery at startup: only the "low" phase alert has
+        // on_startup set.
+        manager.tick().await.unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert!(alert.initial);
+        assert_eq!(alert.alert.summary, "Battery is low! (${capacity}%)");
+        assert!(alerts.try_recv().is_err());
+
+        // Plugged in alert has on_startup disabled by default, but later
+        // changes are always announced.
+        let dir = supply_dir("power-startup-ac", true);
+        let (notifier, mut alerts) = Notifier::test_channel();
+        let (_tx, rx) = mpsc::channel(1);
+        let config = PowerConfig {
+            mode_change_dwell_seconds: 0,
+            ..Default::default()
+        };
+        let mut manager = PowerManager::new(config, notifier, rx).unwrap();
+        manager.sysfs_root = dir.path().to_path_buf();
+
+        manager.tick().await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        dir.write("AC/online", "0\n");
+        manager.tick().await.unwrap();
+        dir.write("AC/online", "1\n");
+        manager.tick().await.unwrap();
+        assert!(!alerts.try_recv().unwrap().initial);
+        let alert = alerts.try_recv().unwrap();
+        assert!(!alert.initial);
+        assert_eq!(
+            alert.alert.summary,
+            "Plugged in! Battery is charging (${capacity}%)"
+        );
+    }
+}
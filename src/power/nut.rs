@@ -0,0 +1,258 @@
+//! Minimal client for the NUT (Network UPS Tools) `upsd` protocol.
+//! See https://networkupstools.org/docs/developer-guide.chunked/net-protocol.html
+
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::Context;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+use super::cfg::UpsConfig;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UpsStatus {
+    /// Raw `ups.status` value, eg "OL CHRG" or "OB LB".
+    pub status: String,
+    pub on_battery: bool,
+    pub low_battery: bool,
+    /// `battery.charge` in percent.
+    pub charge: Option<u8>,
+    /// `battery.runtime` in seconds.
+    pub runtime_seconds: Option<u64>,
+}
+
+/// Query the status of the configured UPS.
+pub async fn query(config: &UpsConfig) -> Result<UpsStatus, anyhow::Error> {
+    let timeout = Duration::from_secs(config.timeout_seconds);
+    let vars = tokio::time::timeout(timeout, list_vars(config))
+        .await
+        .with_context(|| format!("upsd at {}:{} timed out", config.host, config.port))??;
+    UpsStatus::from_vars(&vars)
+}
+
+async fn list_vars(config: &UpsConfig) -> Result<HashMap<String, String>, anyhow::Error> {
+    let stream = TcpStream::connect((config.host.as_str(), config.port))
+        .await
+        .with_context(|| {
+            format!(
+                "could not connect to upsd at {}:{}",
+                config.host, config.port
+            )
+        })?;
+    let (read, mut write) = stream.into_split();
+
+    write
+        .write_all(format!("LIST VAR {}\n", config.name).as_bytes())
+        .await
+        .context("could not send LIST VAR request")?;
+
+    let mut lines = BufReader::new(read).lines();
+    let mut response = String::new();
+    while let Some(line) = lines
+        .next_line()
+        .await
+        .context("could not read from upsd")?
+    {
+        let done = line.starts_with("END LIST VAR") || line.starts_with("ERR");
+        response.push_str(&line);
+        response.push('\n');
+        if done {
+            break;
+        }
+    }
+
+    write.write_all(b"LOGOUT\n").await.ok();
+
+    parse_list_var(&config.name, &response)
+}
+
+/// Parse the response to a `LIST VAR <ups>` request.
+pub fn parse_list_var(ups: &str, response: &str) -> Result<HashMap<String, String>, anyhow::Error> {
+    let mut vars = HashMap::new();
+    let mut ended = false;
+
+    for line in response.lines().map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        if let Some(err) = line.strip_prefix("ERR ") {
+            anyhow::bail!("upsd returned error for UPS '{ups}': {err}");
+        }
+        if line.starts_with("BEGIN LIST VAR") {
+            continue;
+        }
+        if line.starts_with("END LIST VAR") {
+            ended = true;
+            break;
+        }
+
+        let rest = line
+            .strip_prefix("VAR ")
+            .and_then(|x| x.strip_prefix(ups))
+            .and_then(|x| x.strip_prefix(' '))
+            .with_context(|| format!("unexpected upsd response line: '{line}'"))?;
+        let (name, value) = rest
+            .split_once(' ')
+            .with_context(|| format!("invalid VAR line: '{line}'"))?;
+        vars.insert(name.to_string(), unquote(value)?);
+    }
+
+    if !ended {
+        anyhow::bail!("incomplete LIST VAR response from upsd");
+    }
+
+    Ok(vars)
+}
+
+fn unquote(value: &str) -> Result<String, anyhow::Error> {
+    let inner = value
+        .strip_prefix('"')
+        .and_then(|x| x.strip_suffix('"'))
+        .with_context(|| format!("expected quoted value, got '{value}'"))?;
+
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            out.extend(chars.next());
+        } else {
+            out.push(c);
+        }
+    }
+    Ok(out)
+}
+
+impl UpsStatus {
+    fn from_vars(vars: &HashMap<String, String>) -> Result<Self, anyhow::Error> {
+        let status = vars
+            .get("ups.status")
+            .context("upsd did not report 'ups.status'")?
+            .clone();
+        let flags: Vec<_> = status.split_whitespace().collect();
+
+        let charge = vars
+            .get("battery.charge")
+            .map(|x| {
+                // Some drivers report fractional values.
+                x.parse::<f64>()
+                    .map(|x| x.clamp(0.0, 100.0).round() as u8)
+                    .with_context(|| format!("invalid battery.charge: '{x}'"))
+            })
+            .transpose()?;
+        let runtime_seconds = vars
+            .get("battery.runtime")
+            .map(|x| {
+                x.parse::<f64>()
+                    .map(|x| x.max(0.0) as u64)
+                    .with_context(|| format!("invalid battery.runtime: '{x}'"))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            on_battery: flags.contains(&"OB"),
+            low_battery: flags.contains(&"LB"),
+            status,
+            charge,
+            runtime_seconds,
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use pretty_assertions::assert_eq;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Start a fake upsd that answers `LIST VAR` requests with the given
+    /// variables. Returns the port it listens on.
+    pub async fn fake_upsd(ups: &'static str, vars: Vec<(&'static str, &'static str)>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let vars = vars.clone();
+                tokio::spawn(async move {
+                    let (read, mut write) = stream.into_split();
+                    let mut lines = BufReader::new(read).lines();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let response = if line == format!("LIST VAR {ups}") {
+                            let mut out = format!("BEGIN LIST VAR {ups}\n");
+                            for (name, value) in &vars {
+                                out.push_str(&format!("VAR {ups} {name} \"{value}\"\n"));
+                            }
+                            out.push_str(&format!("END LIST VAR {ups}\n"));
+                            out
+                        } else if line.starts_with("LIST VAR") {
+                            "ERR UNKNOWN-UPS\n".to_string()
+                        } else if line == "LOGOUT" {
+                            "OK Goodbye\n".to_string()
+                        } else {
+                            "ERR UNKNOWN-COMMAND\n".to_string()
+                        };
+                        if write.write_all(response.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        port
+    }
+
+    #[test]
+    fn test_parse_list_var() {
+        let response = r#"BEGIN LIST VAR myups
+VAR myups battery.charge "87"
+VAR myups device.mfr "APC \"Back-UPS\""
+VAR myups ups.status "OB DISCHRG"
+END LIST VAR myups
+"#;
+        let vars = parse_list_var("myups", response).unwrap();
+        assert_eq!(vars["battery.charge"], "87");
+        assert_eq!(vars["device.mfr"], "APC \"Back-UPS\"");
+        assert_eq!(vars["ups.status"], "OB DISCHRG");
+
+        assert!(parse_list_var("myups", "ERR UNKNOWN-UPS\n").is_err());
+        assert!(parse_list_var("myups", "BEGIN LIST VAR myups\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_query_fake_upsd() {
+        let port = fake_upsd(
+            "ups",
+            vec![
+                ("ups.status", "OB LB"),
+                ("battery.charge", "9.6"),
+                ("battery.runtime", "300"),
+            ],
+        )
+        .await;
+
+        let config = UpsConfig {
+            port,
+            ..UpsConfig::default()
+        };
+        let status = query(&config).await.unwrap();
+        assert_eq!(
+            status,
+            UpsStatus {
+                status: "OB LB".to_string(),
+                on_battery: true,
+                low_battery: true,
+                charge: Some(10),
+                runtime_seconds: Some(300),
+            }
+        );
+
+        let config = UpsConfig {
+            port,
+            name: "other".to_string(),
+            ..UpsConfig::default()
+        };
+        assert!(query(&config).await.is_err());
+    }
+}
@@ -11,6 +11,9 @@ pub struct PowerSupply {
 #[derive(Clone, Debug)]
 pub enum PowerSupplyType {
     Battery(PowerSupplyBattery),
+    /// Battery of a peripheral like a mouse, keyboard or headset.
+    /// Reported with `scope=Device`.
+    DeviceBattery(PowerSupplyDeviceBattery),
     Main(PowerSupplyMain),
 }
 
@@ -26,6 +29,14 @@ impl PowerSupplyType {
 
 #[derive(Clone, Debug)]
 pub struct PowerSupplyBattery {
+    #[allow(dead_code)]
+    pub status: BatteryStatus,
+    pub capacity: u8,
+}
+
+#[derive(Clone, Debug)]
+pub struct PowerSupplyDeviceBattery {
+    pub model_name: Option<String>,
     pub status: BatteryStatus,
     pub capacity: u8,
 }
@@ -44,17 +55,35 @@ pub struct PowerSupplyMain {
     pub online: bool,
 }
 
-pub fn read_all_supplies() -> Result<Vec<PowerSupply>, anyhow::Error> {
-    std::fs::read_dir("/sys/class/power_supply")?
-        .map(|res| {
-            let entry = res.context("could not read power supply entry")?;
-            let supply = read_supply(&entry.path())?;
-            Ok(supply)
-        })
-        .collect()
+/// Default sysfs directory containing all power supplies.
+pub const SYSFS_POWER_SUPPLY: &str = "/sys/class/power_supply";
+
+const CHARGE_START_THRESHOLD: &str = "charge_control_start_threshold";
+const CHARGE_END_THRESHOLD: &str = "charge_control_end_threshold";
+
+/// Read all power supplies.
+///
+/// Supplies that can not be parsed are skipped, so a single odd device does
+/// not break power monitoring.
+pub fn read_all_supplies(root: &Path) -> Result<Vec<PowerSupply>, anyhow::Error> {
+    let mut supplies = Vec::new();
+    for res in std::fs::read_dir(root)? {
+        let entry = res.context("could not read power supply entry")?;
+        match read_supply(&entry.path()) {
+            Ok(Some(supply)) => supplies.push(supply),
+            Ok(None) => {}
+            Err(err) => {
+                tracing::warn!(path=%entry.path().display(), error = &*err, "could not read power supply - skipping");
+            }
+        }
+    }
+    Ok(supplies)
 }
 
-pub fn read_supply(path: &Path) -> Result<PowerSupply, anyhow::Error> {
+/// Read a single power supply.
+///
+/// Returns `None` for supply types that are not relevant to panorama.
+pub fn read_supply(path: &Path) -> Result<Option<PowerSupply>, anyhow::Error> {
     let name = path
         .file_name()
         .context("could not read name from file path")?
@@ -64,21 +93,30 @@ pub fn read_supply(path: &Path) -> Result<PowerSupply, anyhow::Error> {
 
     let kind_name =
         std::fs::read_to_string(path.join("type")).context("could not read power supply type")?;
+    let scope = std::fs::read_to_string(path.join("scope")).unwrap_or_default();
+
     let kind = match kind_name.trim() {
-        "Battery" => {
-            let status_raw = std::fs::read_to_string(path.join("status"))
-                .context("could not read battery status")?;
-
-            let status = match status_raw.trim() {
-                "Unknown" => BatteryStatus::Unknown,
-                "Charging" => BatteryStatus::Charging,
-                "Discharging" => BatteryStatus::Discharging,
-                "Not charging" => BatteryStatus::NotCharging,
-                "Full" => BatteryStatus::Full,
-                other => {
-                    anyhow::bail!("unknown battery status: {}", other)
-                }
+        "Battery" if scope.trim() == "Device" => {
+            let model_name = std::fs::read_to_string(path.join("model_name"))
+                .ok()
+                .map(|x| x.trim().to_string())
+                .filter(|x| !x.is_empty());
+            // Missing status is common for peripherals.
+            let status = if path.join("status").is_file() {
+                read_battery_status(path)?
+            } else {
+                BatteryStatus::Unknown
             };
+            let capacity = read_device_capacity(path)?;
+
+            PowerSupplyType::DeviceBattery(PowerSupplyDeviceBattery {
+                model_name,
+                status,
+                capacity,
+            })
+        }
+        "Battery" => {
+            let status = read_battery_status(path)?;
 
             let capacity = std::fs::read_to_string(path.join("capacity"))
                 .context("could not read battery capacity")?
@@ -88,7 +126,8 @@ pub fn read_supply(path: &Path) -> Result<PowerSupply, anyhow::Error> {
 
             PowerSupplyType::Battery(PowerSupplyBattery { status, capacity })
         }
-        "Mains" => {
+        // USB-C chargers show up as "USB" supplies.
+        "Mains" | "USB" if scope.trim() != "Device" => {
             let online_raw = std::fs::read_to_string(path.join("online"))
                 .context("could not read main power supply status")?
                 .trim()
@@ -98,9 +137,205 @@ pub fn read_supply(path: &Path) -> Result<PowerSupply, anyhow::Error> {
             PowerSupplyType::Main(PowerSupplyMain { online })
         }
         other => {
-            anyhow::bail!("unknown power supply type: '{}'", other)
+            tracing::trace!(name, kind = other, "ignoring power supply");
+            return Ok(None);
+        }
+    };
+
+    Ok(Some(PowerSupply { name, kind }))
+}
+
+fn read_battery_status(path: &Path) -> Result<BatteryStatus, anyhow::Error> {
+    let status_raw =
+        std::fs::read_to_string(path.join("status")).context("could not read battery status")?;
+
+    let status = match status_raw.trim() {
+        "Unknown" => BatteryStatus::Unknown,
+        "Charging" => BatteryStatus::Charging,
+        "Discharging" => BatteryStatus::Discharging,
+        "Not charging" => BatteryStatus::NotCharging,
+        "Full" => BatteryStatus::Full,
+        other => {
+            anyhow::bail!("unknown battery status: {}", other)
         }
     };
+    Ok(status)
+}
 
-    Ok(PowerSupply { name, kind })
+/// Read the capacity of a peripheral battery.
+///
+/// Some devices only report a coarse `capacity_level`, which is mapped to an
+/// approximate percentage.
+fn read_device_capacity(path: &Path) -> Result<u8, anyhow::Error> {
+    if let Ok(raw) = std::fs::read_to_string(path.join("capacity")) {
+        return raw
+            .trim()
+            .parse::<u8>()
+            .context("could not parse battery capacity");
+    }
+
+    let level = std::fs::read_to_string(path.join("capacity_level"))
+        .context("could not read battery capacity or capacity_level")?;
+    let capacity = match level.trim() {
+        "Critical" => 5,
+        "Low" => 15,
+        "Normal" => 50,
+        "High" => 80,
+        "Full" => 100,
+        other => anyhow::bail!("unknown battery capacity level: '{other}'"),
+    };
+    Ok(capacity)
+}
+
+/// Returns the names of all batteries that expose charge control thresholds.
+pub fn list_charge_control_batteries(root: &Path) -> Result<Vec<String>, anyhow::Error> {
+    let mut names = Vec::new();
+    for res in std::fs::read_dir(root)? {
+        let entry = res.context("could not read power supply entry")?;
+        let path = entry.path();
+
+        let kind = std::fs::read_to_string(path.join("type")).unwrap_or_default();
+        if kind.trim() != "Battery" || !path.join(CHARGE_END_THRESHOLD).is_file() {
+            continue;
+        }
+        if let Some(name) = path.file_name().and_then(|x| x.to_str()) {
+            names.push(name.to_string());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Write the charge control thresholds of the battery at `path`.
+///
+/// The kernel rejects a start threshold that is above the current end
+/// threshold, so the order of the writes depends on the current values.
+pub fn write_charge_thresholds(
+    path: &Path,
+    start: Option<u8>,
+    end: Option<u8>,
+) -> Result<(), anyhow::Error> {
+    let current_end = std::fs::read_to_string(path.join(CHARGE_END_THRESHOLD))
+        .ok()
+        .and_then(|x| x.trim().parse::<u8>().ok());
+
+    let start_first = match (start, current_end) {
+        (Some(start), Some(current_end)) => start < current_end,
+        _ => true,
+    };
+
+    let write = |file: &str, value: Option<u8>| -> Result<(), anyhow::Error> {
+        if let Some(value) = value {
+            let file_path = path.join(file);
+            std::fs::write(&file_path, value.to_string())
+                .with_context(|| format!("could not write '{}'", file_path.display()))?;
+        }
+        Ok(())
+    };
+
+    if start_first {
+        write(CHARGE_START_THRESHOLD, start)?;
+        write(CHARGE_END_THRESHOLD, end)?;
+    } else {
+        write(CHARGE_END_THRESHOLD, end)?;
+        write(CHARGE_START_THRESHOLD, start)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::testutil::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_read_all_supplies() {
+        let dir = TempDir::new("supplies");
+        dir.write("AC/type", "Mains\n");
+        dir.write("AC/online", "0\n");
+        dir.write("ucsi-source-psy-USBC000:001/type", "USB\n");
+        dir.write("ucsi-source-psy-USBC000:001/online", "1\n");
+        dir.write("BAT0/type", "Battery\n");
+        dir.write("BAT0/status", "Discharging\n");
+        dir.write("BAT0/capacity", "42\n");
+        dir.write("hidpp_battery_0/type", "Battery\n");
+        dir.write("hidpp_battery_0/scope", "Device\n");
+        dir.write("hidpp_battery_0/model_name", "MX Master 3\n");
+        dir.write("hidpp_battery_0/status", "Discharging\n");
+        dir.write("hidpp_battery_0/capacity_level", "Low\n");
+        dir.write("wacom_battery_1/type", "Wireless\n");
+        // Broken supply that must not take down the others.
+        dir.write("BAT1/type", "Battery\n");
+
+        let mut supplies = read_all_supplies(dir.path()).unwrap();
+        supplies.sort_by(|a, b| a.name.cmp(&b.name));
+        let names: Vec<_> = supplies.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "AC",
+                "BAT0",
+                "hidpp_battery_0",
+                "ucsi-source-psy-USBC000:001"
+            ]
+        );
+
+        assert!(!supplies[0].kind.as_main().unwrap().online);
+        assert!(matches!(
+            &supplies[1].kind,
+            PowerSupplyType::Battery(PowerSupplyBattery {
+                status: BatteryStatus::Discharging,
+                capacity: 42
+            })
+        ));
+        let PowerSupplyType::DeviceBattery(dev) = &supplies[2].kind else {
+            panic!("expected device battery");
+        };
+        assert_eq!(dev.model_name.as_deref(), Some("MX Master 3"));
+        assert_eq!(dev.capacity, 15);
+        assert!(supplies[3].kind.as_main().unwrap().online);
+    }
+
+    #[test]
+    fn test_list_charge_control_batteries() {
+        let dir = TempDir::new("charge-list");
+        dir.write("AC/type", "Mains\n");
+        dir.write("BAT0/type", "Battery\n");
+        dir.write("BAT0/charge_control_end_threshold", "100\n");
+        dir.write("BAT1/type", "Battery\n");
+
+        let names = list_charge_control_batteries(dir.path()).unwrap();
+        assert_eq!(names, vec!["BAT0".to_string()]);
+    }
+
+    #[test]
+    fn test_write_charge_thresholds() {
+        let dir = TempDir::new("charge-write");
+        dir.write("BAT0/charge_control_start_threshold", "0\n");
+        dir.write("BAT0/charge_control_end_threshold", "100\n");
+        let bat = dir.path().join("BAT0");
+
+        write_charge_thresholds(&bat, Some(75), Some(80)).unwrap();
+        let read = |file: &str| std::fs::read_to_string(bat.join(file)).unwrap();
+        assert_eq!(read(CHARGE_START_THRESHOLD), "75");
+        assert_eq!(read(CHARGE_END_THRESHOLD), "80");
+
+        write_charge_thresholds(&bat, None, Some(100)).unwrap();
+        assert_eq!(read(CHARGE_START_THRESHOLD), "75");
+        assert_eq!(read(CHARGE_END_THRESHOLD), "100");
+    }
+
+    #[test]
+    fn test_write_charge_thresholds_fails() {
+        let dir = TempDir::new("charge-fail");
+        // A directory in place of the attribute makes the write fail.
+        std::fs::create_dir_all(dir.path().join("BAT0/charge_control_start_threshold")).unwrap();
+
+        let res = write_charge_thresholds(&dir.path().join("BAT0"), Some(75), Some(80));
+        assert!(res.is_err());
+    }
 }
@@ -0,0 +1,90 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::cfg::{Alert, AlertSeverity};
+
+/// Public (egress) IP address monitoring.
+///
+/// Available variables: ${old_ip}, ${new_ip}
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PublicIpConfig {
+    /// Disabled by default, since it periodically queries a third party.
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "PublicIpConfig::default_check_interval_seconds")]
+    pub check_interval_seconds: u64,
+    #[serde(default = "PublicIpConfig::default_timeout_seconds")]
+    pub timeout_seconds: u64,
+    #[serde(default)]
+    pub source: IpSource,
+
+    #[serde(default = "PublicIpConfig::default_alert_changed")]
+    pub alert_changed: Option<Alert>,
+}
+
+impl PublicIpConfig {
+    pub fn validate(self) -> Result<Self, anyhow::Error> {
+        if self.check_interval_seconds == 0 {
+            anyhow::bail!("'public_ip.check_interval_seconds' must be greater than 0");
+        }
+        if self.timeout_seconds == 0 {
+            anyhow::bail!("'public_ip.timeout_seconds' must be greater than 0");
+        }
+        Ok(self)
+    }
+
+    fn default_check_interval_seconds() -> u64 {
+        300
+    }
+
+    fn default_timeout_seconds() -> u64 {
+        10
+    }
+
+    fn default_alert_changed() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Info,
+            on_startup: false,
+            repeat_after_seconds: None,
+            expire_after_seconds: Some(30),
+            summary: "Public IP changed to ${new_ip}".to_string(),
+            message: Some("Previous address: ${old_ip}".to_string()),
+        })
+    }
+}
+
+impl Default for PublicIpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            check_interval_seconds: Self::default_check_interval_seconds(),
+            timeout_seconds: Self::default_timeout_seconds(),
+            source: IpSource::default(),
+            alert_changed: Self::default_alert_changed(),
+        }
+    }
+}
+
+/// How the public IP is determined.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum IpSource {
+    /// An HTTP endpoint returning the address as plain text or JSON.
+    Http {
+        url: url::Url,
+        /// Dot separated path of the address in a JSON response (eg "ip" or
+        /// "data.address"). The response is read as plain text if not set.
+        #[serde(default)]
+        json_field: Option<String>,
+    },
+    /// A STUN binding request (RFC 5389) to the given `host:port`.
+    Stun { server: String },
+}
+
+impl Default for IpSource {
+    fn default() -> Self {
+        Self::Http {
+            url: "https://api.ipify.org".parse().unwrap(),
+            json_field: None,
+        }
+    }
+}
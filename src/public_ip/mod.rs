@@ -0,0 +1,228 @@
+pub mod cfg;
+mod stun;
+
+use std::{collections::HashMap, net::IpAddr, time::Duration};
+
+use anyhow::Context;
+
+use crate::notify::Notifier;
+
+use self::cfg::{IpSource, PublicIpConfig};
+
+const ALERT_GROUP_PUBLIC_IP: &str = "panorama.public_ip";
+
+pub struct PublicIpManager {
+    config: PublicIpConfig,
+    notifier: Notifier,
+    /// Last successfully determined address.
+    ip: Option<IpAddr>,
+}
+
+impl PublicIpManager {
+    pub async fn start(config: PublicIpConfig, notifier: Notifier) -> Result<(), anyhow::Error> {
+        let manager = Self::new(config, notifier)?;
+        tokio::task::spawn_local(async move { manager.run().await })
+            .await
+            .context("PublicIpManager task failed")?
+            .context("PublicIpManager failed")?;
+
+        Ok(())
+    }
+
+    fn new(config: PublicIpConfig, notifier: Notifier) -> Result<Self, anyhow::Error> {
+        let config = config.validate()?;
+        Ok(Self {
+            config,
+            notifier,
+            ip: None,
+        })
+    }
+
+    async fn run(mut self) -> Result<(), anyhow::Error> {
+        let interval = Duration::from_secs(self.config.check_interval_seconds);
+        loop {
+            self.tick().await?;
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    async fn tick(&mut self) -> Result<(), anyhow::Error> {
+        let timeout = Duration::from_secs(self.config.timeout_seconds);
+        let ip = match query(&self.config.source, timeout).await {
+            Ok(ip) => ip,
+            Err(err) => {
+                // Connectivity issues are reported by the online check, so
+                // the last known address is kept.
+                tracing::warn!(error = &*err, "could not determine public IP");
+                return Ok(());
+            }
+        };
+        tracing::trace!(%ip, "determined public IP");
+
+        let Some(old_ip) = self.ip.replace(ip) else {
+            return Ok(());
+        };
+        if old_ip == ip {
+            return Ok(());
+        }
+        tracing::info!(%old_ip, new_ip=%ip, "public IP changed");
+
+        if let Some(alert) = &self.config.alert_changed {
+            let variables = HashMap::from([
+                ("old_ip".to_string(), old_ip.to_string()),
+                ("new_ip".to_string(), ip.to_string()),
+            ]);
+            let full = alert.prepare(ALERT_GROUP_PUBLIC_IP.to_string(), variables);
+            self.notifier.notify(full).await?;
+        }
+
+        Ok(())
+    }
+}
+
+async fn query(source: &IpSource, timeout: Duration) -> Result<IpAddr, anyhow::Error> {
+    match source {
+        IpSource::Http { url, json_field } => {
+            let url = url.clone();
+            let json_field = json_field.clone();
+            tokio::task::spawn_blocking(move || query_http(&url, json_field.as_deref(), timeout))
+                .await
+                .context("public IP query task failed")?
+        }
+        IpSource::Stun { server } => stun::query(server, timeout).await,
+    }
+}
+
+fn query_http(
+    url: &url::Url,
+    json_field: Option<&str>,
+    timeout: Duration,
+) -> Result<IpAddr, anyhow::Error> {
+    let body = ureq::get(url.as_str())
+        .timeout(timeout)
+        .call()
+        .context("http query failed")?
+        .into_string()
+        .context("could not read response body")?;
+    parse_response(&body, json_field)
+}
+
+fn parse_response(body: &str, json_field: Option<&str>) -> Result<IpAddr, anyhow::Error> {
+    let value = match json_field {
+        Some(field) => {
+            let json = serde_json::from_str::<serde_json::Value>(body)
+                .context("response is not valid JSON")?;
+            let value = field
+                .split('.')
+                .try_fold(&json, |value, key| value.get(key))
+                .with_context(|| format!("response has no field '{field}'"))?;
+            value
+                .as_str()
+                .with_context(|| format!("field '{field}' is not a string"))?
+                .to_string()
+        }
+        None => body.trim().to_string(),
+    };
+    value
+        .parse()
+        .with_context(|| format!("invalid IP address '{value}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::internet::tests::http_server_raw;
+
+    fn response(body: &str) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    #[test]
+    fn test_parse_response() {
+        assert_eq!(
+            parse_response("203.0.113.5\n", None).unwrap(),
+            "203.0.113.5".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            parse_response(r#"{"data": {"ip": "2001:db8::1"}}"#, Some("data.ip")).unwrap(),
+            "2001:db8::1".parse::<IpAddr>().unwrap()
+        );
+
+        let err = parse_response(r#"{"ip": "203.0.113.5"}"#, Some("address")).unwrap_err();
+        assert_eq!(err.to_string(), "response has no field 'address'");
+        let err = parse_response("<html>", None).unwrap_err();
+        assert_eq!(err.to_string(), "invalid IP address '<html>'");
+    }
+
+    #[tokio::test]
+    async fn test_ip_changed() {
+        let (notifier, mut alerts) = Notifier::test_channel();
+        let config = PublicIpConfig {
+            enabled: true,
+            source: IpSource::Http {
+                url: http_server_raw(response(r#"{"ip": "203.0.113.5"}"#)),
+                json_field: Some("ip".to_string()),
+            },
+            ..Default::default()
+        };
+        let mut manager = PublicIpManager::new(config, notifier).unwrap();
+
+        // The initial observation is not announced.
+        manager.tick().await.unwrap();
+        manager.tick().await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        manager.config.source = IpSource::Http {
+            url: http_server_raw(response("198.51.100.7")),
+            json_field: None,
+        };
+        manager.tick().await.unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.alert.summary, "Public IP changed to ${new_ip}");
+        assert_eq!(alert.variables["old_ip"], "203.0.113.5");
+        assert_eq!(alert.variables["new_ip"], "198.51.100.7");
+
+        // Failures keep the last known address.
+        manager.config.source = IpSource::Http {
+            url: http_server_raw("HTTP/1.1 500 Oops\r\ncontent-length: 0\r\n\r\n".to_string()),
+            json_field: None,
+        };
+        manager.tick().await.unwrap();
+        assert!(alerts.try_recv().is_err());
+        assert_eq!(manager.ip, Some("198.51.100.7".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_stun_source() {
+        let server = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            for ip in ["203.0.113.5", "198.51.100.7"] {
+                let (_, peer) = server.recv_from(&mut buf).unwrap();
+                let msg = stun::tests::binding_response(&buf[8..20], ip.parse().unwrap(), true);
+                server.send_to(&msg, peer).unwrap();
+            }
+        });
+
+        let (notifier, mut alerts) = Notifier::test_channel();
+        let config = PublicIpConfig {
+            enabled: true,
+            source: IpSource::Stun {
+                server: addr.to_string(),
+            },
+            ..Default::default()
+        };
+        let mut manager = PublicIpManager::new(config, notifier).unwrap();
+        manager.tick().await.unwrap();
+        manager.tick().await.unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.variables["old_ip"], "203.0.113.5");
+        assert_eq!(alert.variables["new_ip"], "198.51.100.7");
+    }
+}
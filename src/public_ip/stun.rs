@@ -0,0 +1,224 @@
+//! Minimal STUN client (RFC 5389) for binding requests.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use anyhow::Context;
+
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_RESPONSE: u16 = 0x0101;
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+const HEADER_LEN: usize = 20;
+
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+
+/// Query the public address of this host from a STUN server.
+pub async fn query(server: &str, timeout: Duration) -> Result<IpAddr, anyhow::Error> {
+    let fut = async {
+        let addr = tokio::net::lookup_host(server)
+            .await
+            .with_context(|| format!("could not resolve STUN server '{server}'"))?
+            .next()
+            .with_context(|| format!("STUN server '{server}' has no addresses"))?;
+        let bind: SocketAddr = if addr.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = tokio::net::UdpSocket::bind(bind).await?;
+        socket.connect(addr).await?;
+
+        let transaction_id = transaction_id();
+        socket.send(&binding_request(&transaction_id)).await?;
+        let mut buf = [0u8; 512];
+        loop {
+            let len = socket.recv(&mut buf).await?;
+            if let Some(ip) = parse_binding_response(&buf[..len], &transaction_id)? {
+                return Ok(ip);
+            }
+        }
+    };
+    tokio::time::timeout(timeout, fut)
+        .await
+        .context("STUN request timed out")?
+}
+
+fn transaction_id() -> [u8; 12] {
+    use std::hash::{BuildHasher, Hasher};
+
+    let mut id = [0u8; 12];
+    for chunk in id.chunks_mut(4) {
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u32(std::process::id());
+        chunk.copy_from_slice(&hasher.finish().to_be_bytes()[..4]);
+    }
+    id
+}
+
+pub fn binding_request(transaction_id: &[u8; 12]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(HEADER_LEN);
+    msg.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+    msg.extend_from_slice(&0u16.to_be_bytes());
+    msg.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    msg.extend_from_slice(transaction_id);
+    msg
+}
+
+/// Extract the mapped address from a binding response.
+///
+/// Returns `None` for messages belonging to other transactions.
+pub fn parse_binding_response(
+    msg: &[u8],
+    transaction_id: &[u8; 12],
+) -> Result<Option<IpAddr>, anyhow::Error> {
+    if msg.len() < HEADER_LEN || &msg[8..20] != transaction_id {
+        return Ok(None);
+    }
+    let kind = u16::from_be_bytes([msg[0], msg[1]]);
+    if kind != BINDING_RESPONSE {
+        anyhow::bail!("unexpected STUN message type {kind:#06x}");
+    }
+    let len = u16::from_be_bytes([msg[2], msg[3]]) as usize;
+    let body = msg
+        .get(HEADER_LEN..HEADER_LEN + len)
+        .context("truncated STUN message")?;
+
+    let mut mapped = None;
+    let mut offset = 0;
+    while offset + 4 <= body.len() {
+        let kind = u16::from_be_bytes([body[offset], body[offset + 1]]);
+        let len = u16::from_be_bytes([body[offset + 2], body[offset + 3]]) as usize;
+        let value = body
+            .get(offset + 4..offset + 4 + len)
+            .context("truncated STUN attribute")?;
+        match kind {
+            ATTR_XOR_MAPPED_ADDRESS => {
+                let mut mask = MAGIC_COOKIE.to_be_bytes().to_vec();
+                mask.extend_from_slice(transaction_id);
+                return parse_address(value, Some(&mask)).map(Some);
+            }
+            ATTR_MAPPED_ADDRESS => mapped = Some(parse_address(value, None)?),
+            _ => {}
+        }
+        // Attributes are padded to a multiple of 4 bytes.
+        offset += 4 + len.div_ceil(4) * 4;
+    }
+    mapped
+        .map(Some)
+        .context("STUN response contains no mapped address")
+}
+
+/// Parse a (XOR-)MAPPED-ADDRESS attribute, unmasking it with `mask` if set.
+fn parse_address(value: &[u8], mask: Option<&[u8]>) -> Result<IpAddr, anyhow::Error> {
+    let family = *value.get(1).context("truncated STUN address")?;
+    let len = match family {
+        0x01 => 4,
+        0x02 => 16,
+        _ => anyhow::bail!("unknown STUN address family {family}"),
+    };
+    let mut octets = value
+        .get(4..4 + len)
+        .context("truncated STUN address")?
+        .to_vec();
+    if let Some(mask) = mask {
+        for (octet, mask) in octets.iter_mut().zip(mask) {
+            *octet ^= mask;
+        }
+    }
+    Ok(match <[u8; 16]>::try_from(octets.as_slice()) {
+        Ok(v6) => IpAddr::from(v6),
+        Err(_) => IpAddr::from(<[u8; 4]>::try_from(octets.as_slice()).unwrap()),
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    const ID: [u8; 12] = *b"0123456789ab";
+
+    /// Build a binding response with a single address attribute.
+    pub fn binding_response(transaction_id: &[u8], ip: IpAddr, xor: bool) -> Vec<u8> {
+        let mut octets = match ip {
+            IpAddr::V4(ip) => ip.octets().to_vec(),
+            IpAddr::V6(ip) => ip.octets().to_vec(),
+        };
+        let mut port = 54321u16.to_be_bytes();
+        let kind = if xor {
+            let mut mask = MAGIC_COOKIE.to_be_bytes().to_vec();
+            mask.extend_from_slice(transaction_id);
+            for (octet, mask) in octets.iter_mut().zip(&mask) {
+                *octet ^= mask;
+            }
+            port[0] ^= mask[0];
+            port[1] ^= mask[1];
+            ATTR_XOR_MAPPED_ADDRESS
+        } else {
+            ATTR_MAPPED_ADDRESS
+        };
+        let family = if ip.is_ipv4() { 1 } else { 2 };
+
+        let mut attr = vec![0, family];
+        attr.extend_from_slice(&port);
+        attr.extend_from_slice(&octets);
+
+        let mut msg = BINDING_RESPONSE.to_be_bytes().to_vec();
+        msg.extend_from_slice(&(4 + attr.len() as u16).to_be_bytes());
+        msg.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        msg.extend_from_slice(transaction_id);
+        msg.extend_from_slice(&kind.to_be_bytes());
+        msg.extend_from_slice(&(attr.len() as u16).to_be_bytes());
+        msg.extend_from_slice(&attr);
+        msg
+    }
+
+    #[test]
+    fn test_binding_request() {
+        let msg = binding_request(&ID);
+        assert_eq!(&msg[..8], &[0x00, 0x01, 0x00, 0x00, 0x21, 0x12, 0xA4, 0x42]);
+        assert_eq!(&msg[8..], &ID);
+    }
+
+    #[test]
+    fn test_parse_binding_response() {
+        let v4: IpAddr = "203.0.113.5".parse().unwrap();
+        let v6: IpAddr = "2001:db8::1".parse().unwrap();
+        for (ip, xor) in [(v4, true), (v4, false), (v6, true), (v6, false)] {
+            let msg = binding_response(&ID, ip, xor);
+            assert_eq!(parse_binding_response(&msg, &ID).unwrap(), Some(ip));
+        }
+
+        // Responses to other requests are ignored.
+        let msg = binding_response(b"ba9876543210", v4, true);
+        assert_eq!(parse_binding_response(&msg, &ID).unwrap(), None);
+
+        let mut msg = binding_response(&ID, v4, true);
+        msg.truncate(24);
+        assert!(parse_binding_response(&msg, &ID).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_query() {
+        let server = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (len, peer) = server.recv_from(&mut buf).unwrap();
+            assert_eq!(len, HEADER_LEN);
+            let ip = "198.51.100.7".parse().unwrap();
+            server
+                .send_to(&binding_response(&buf[8..20], ip, true), peer)
+                .unwrap();
+        });
+
+        let ip = query(&addr.to_string(), Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(ip, "198.51.100.7".parse::<IpAddr>().unwrap());
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::cfg::Alert;
+
+use super::Action;
+
+/// Alerts for arbitrary udev events.
+///
+/// Example watchers: monitor hotplug (`subsystem: drm`, `actions: [change]`),
+/// SD cards (`subsystem: block`, `properties: {ID_DRIVE_FLASH_SD: "1"}`),
+/// Thunderbolt devices (`subsystem: thunderbolt`) or input devices
+/// (`subsystem: input`, `properties: {ID_INPUT_KEYBOARD: "1"}`).
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct UdevConfig {
+    #[serde(default)]
+    pub watchers: Vec<UdevWatcher>,
+}
+
+impl UdevConfig {
+    pub fn validate(self) -> Result<Self, anyhow::Error> {
+        let mut names = std::collections::HashSet::new();
+        for watcher in &self.watchers {
+            if !names.insert(&watcher.name) {
+                anyhow::bail!("udev watcher '{}' is defined multiple times", watcher.name);
+            }
+            if watcher.actions.is_empty() {
+                anyhow::bail!("udev watcher '{}' has no actions", watcher.name);
+            }
+        }
+        Ok(self)
+    }
+}
+
+/// Sends an alert for udev events matching all of the given conditions.
+///
+/// Available variables: all udev properties of the device (eg ${ID_MODEL},
+/// ${DEVNAME}), read attributes as ${attr.<name>}, plus ${watcher},
+/// ${action}, ${subsystem}, ${devtype} and ${syspath}.
+/// Attributes are not available in `remove` events.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UdevWatcher {
+    pub name: String,
+    pub subsystem: String,
+    #[serde(default)]
+    pub devtype: Option<String>,
+    #[serde(default = "UdevWatcher::default_actions")]
+    pub actions: Vec<Action>,
+    /// Properties that must be present and match, eg `ID_BUS: usb`.
+    #[serde(default)]
+    pub properties: BTreeMap<String, ValueMatcher>,
+    /// sysfs attributes that must be present and match.
+    #[serde(default)]
+    pub attributes: BTreeMap<String, ValueMatcher>,
+    /// Additional attributes to read for use in the alert.
+    #[serde(default)]
+    pub read_attributes: Vec<String>,
+    pub alert: Alert,
+}
+
+impl UdevWatcher {
+    fn default_actions() -> Vec<Action> {
+        vec![Action::Add, Action::Remove]
+    }
+
+    /// All attributes that need to be read for this watcher.
+    pub fn attribute_names(&self) -> impl Iterator<Item = &str> {
+        self.attributes
+            .keys()
+            .chain(&self.read_attributes)
+            .map(String::as_str)
+    }
+}
+
+/// Matches a udev value with a glob (`*` and `?` wildcards) or, with a `re:`
+/// prefix, a regex.
+#[derive(Clone, Debug)]
+pub struct ValueMatcher {
+    source: String,
+    regex: regex::Regex,
+}
+
+impl ValueMatcher {
+    pub fn is_match(&self, value: &str) -> bool {
+        self.regex.is_match(value)
+    }
+}
+
+impl std::str::FromStr for ValueMatcher {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let regex = match s.strip_prefix("re:") {
+            Some(regex) => regex::Regex::new(regex)
+                .map_err(|err| anyhow::anyhow!("invalid regex '{regex}': {err}"))?,
+            None => {
+                let glob = regex::escape(s).replace(r"\*", ".*").replace(r"\?", ".");
+                regex::Regex::new(&format!("^{glob}$"))?
+            }
+        };
+        Ok(Self {
+            source: s.to_string(),
+            regex,
+        })
+    }
+}
+
+impl serde::Serialize for ValueMatcher {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for ValueMatcher {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
@@ -0,0 +1,268 @@
+//! A single udev monitor shared by all managers.
+
+use std::{cell::RefCell, collections::BTreeSet, rc::Rc, sync::Arc};
+
+use anyhow::Context as _;
+use futures::{Stream, StreamExt};
+use tokio::sync::broadcast;
+use tokio_udev::{AsyncMonitorSocket, MonitorBuilder};
+
+use super::{DeviceEvent, Filter};
+
+/// Events buffered before the slowest subscriber starts missing events.
+/// Docking stations easily produce hundreds of events at once.
+const CAPACITY: usize = 4096;
+
+/// A subscriber missed events because it fell behind by more than the
+/// buffer size.
+///
+/// Subscribers that keep device state should re-enumerate the devices, eg
+/// with [`super::resync_events`].
+#[derive(Debug)]
+pub struct Lagged(pub u64);
+
+impl std::fmt::Display for Lagged {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "udev subscriber missed {} events", self.0)
+    }
+}
+
+impl std::error::Error for Lagged {}
+
+/// Owns the udev monitor socket and fans the events out to subscribers.
+///
+/// The monitor receives the events of all subsystems, so subscribers can be
+/// added at any time, also after the hub started.
+#[derive(Clone)]
+pub struct UdevHub {
+    sender: broadcast::Sender<Arc<DeviceEvent>>,
+    /// sysfs attributes requested by any subscriber. They must be read while
+    /// handling the event, since the device may be gone afterwards.
+    attributes: Rc<RefCell<BTreeSet<String>>>,
+    /// The filters of all subscribers, so events nobody is interested in
+    /// don't take up buffer space. `None` if a subscriber wants all events.
+    filters: Rc<RefCell<Option<Vec<Filter>>>>,
+}
+
+impl UdevHub {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        Self {
+            sender,
+            attributes: Rc::new(RefCell::new(BTreeSet::new())),
+            filters: Rc::new(RefCell::new(Some(Vec::new()))),
+        }
+    }
+
+    /// Subscribe to the events matching any of `filters`, or to all events
+    /// if empty.
+    ///
+    /// Subscribe before enumerating the current devices, so no device added
+    /// in between is missed. Such devices may be seen twice though.
+    pub fn subscribe(&self, filters: Vec<Filter>, attributes: &[&str]) -> Subscriber {
+        self.attributes
+            .borrow_mut()
+            .extend(attributes.iter().map(|a| a.to_string()));
+        let mut all_filters = self.filters.borrow_mut();
+        match all_filters.as_mut() {
+            Some(_) if filters.is_empty() => *all_filters = None,
+            Some(all_filters) => all_filters.extend(filters.iter().cloned()),
+            None => {}
+        }
+        Subscriber {
+            filters,
+            receiver: self.sender.subscribe(),
+        }
+    }
+
+    pub fn has_subscribers(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    pub async fn run(self) -> Result<(), anyhow::Error> {
+        let mut stream: AsyncMonitorSocket = MonitorBuilder::new()
+            .context("could not create udev monitor")?
+            .listen()
+            .context("Couldn't listen on udev socket")?
+            .try_into()
+            .context("could not create udev monitor socket")?;
+
+        while let Some(res) = stream.next().await {
+            let event = res.context("failed to read udev event")?;
+            let attributes = self.attributes.borrow().clone();
+            let attributes = attributes.iter().map(String::as_str).collect::<Vec<_>>();
+            self.publish(DeviceEvent::from_device(
+                event.event_type().into(),
+                &event.device(),
+                &attributes,
+            ));
+        }
+
+        anyhow::bail!("udev monitor socket closed")
+    }
+
+    fn publish(&self, event: DeviceEvent) {
+        tracing::trace!(syspath=%event.syspath, action=?event.action, "udev event");
+        if let Some(filters) = &*self.filters.borrow() {
+            if !matches(filters, &event) {
+                return;
+            }
+        }
+        // Fails only without subscribers.
+        let _ = self.sender.send(Arc::new(event));
+    }
+}
+
+/// Receives the events matching its filters from a [`UdevHub`].
+pub struct Subscriber {
+    filters: Vec<Filter>,
+    receiver: broadcast::Receiver<Arc<DeviceEvent>>,
+}
+
+impl Subscriber {
+    /// Wait for the next matching event.
+    ///
+    /// Fails with [`Lagged`] if events were missed, and once the hub stopped.
+    pub async fn recv(&mut self) -> Result<DeviceEvent, anyhow::Error> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) if self.filters.is_empty() || matches(&self.filters, &event) => {
+                    return Ok((*event).clone())
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(count)) => {
+                    return Err(Lagged(count).into());
+                }
+                Err(broadcast::error::RecvError::Closed) => anyhow::bail!("udev hub stopped"),
+            }
+        }
+    }
+
+    /// The stream ends after the hub stopped.
+    pub fn into_stream(self) -> impl Stream<Item = Result<DeviceEvent, anyhow::Error>> + Unpin {
+        Box::pin(futures::stream::unfold(
+            Some(self),
+            |subscriber| async move {
+                let mut subscriber = subscriber?;
+                match subscriber.recv().await {
+                    Err(err) if !err.is::<Lagged>() => Some((Err(err), None)),
+                    res => Some((res, Some(subscriber))),
+                }
+            },
+        ))
+    }
+}
+
+fn matches(filters: &[Filter], event: &DeviceEvent) -> bool {
+    filters.iter().any(|(subsystem, devtype)| {
+        event.subsystem.as_ref() == Some(subsystem)
+            && devtype
+                .as_ref()
+                .is_none_or(|devtype| event.devtype.as_ref() == Some(devtype))
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::collections::HashMap;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::udev::Action;
+
+    pub fn event(subsystem: &str, devtype: Option<&str>) -> DeviceEvent {
+        DeviceEvent {
+            action: Action::Add,
+            syspath: format!("/sys/devices/test/{subsystem}"),
+            subsystem: Some(subsystem.to_string()),
+            devtype: devtype.map(String::from),
+            properties: HashMap::new(),
+            attributes: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fan_out() {
+        let hub = UdevHub::new();
+        assert!(!hub.has_subscribers());
+        let mut usb = hub.subscribe(
+            vec![("usb".to_string(), Some("usb_device".to_string()))],
+            &["bDeviceClass"],
+        );
+        let mut all = hub.subscribe(Vec::new(), &[]);
+        assert!(hub.has_subscribers());
+
+        hub.publish(event("usb", Some("usb_interface")));
+        hub.publish(event("usb", Some("usb_device")));
+
+        assert_eq!(
+            usb.recv().await.unwrap().devtype.as_deref(),
+            Some("usb_device")
+        );
+        assert_eq!(
+            all.recv().await.unwrap().devtype.as_deref(),
+            Some("usb_interface")
+        );
+        assert_eq!(
+            all.recv().await.unwrap().devtype.as_deref(),
+            Some("usb_device")
+        );
+
+        // Subscribers added later receive the following events.
+        let mut power = hub.subscribe(vec![("power_supply".to_string(), None)], &[]);
+        hub.publish(event("power_supply", None));
+        assert_eq!(
+            power.recv().await.unwrap().subsystem.as_deref(),
+            Some("power_supply")
+        );
+        assert_eq!(
+            *hub.attributes.borrow(),
+            BTreeSet::from(["bDeviceClass".to_string()])
+        );
+
+        drop(hub);
+        let err = usb.recv().await.unwrap_err();
+        assert_eq!(err.to_string(), "udev hub stopped");
+    }
+
+    #[tokio::test]
+    async fn test_lagging_subscriber() {
+        let hub = UdevHub::new();
+        let mut subscriber = hub.subscribe(Vec::new(), &[]);
+        for _ in 0..CAPACITY + 10 {
+            hub.publish(event("input", None));
+        }
+        hub.publish(event("drm", None));
+
+        let err = subscriber.recv().await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<Lagged>(), Some(Lagged(11))));
+
+        let mut count = 0;
+        loop {
+            count += 1;
+            if subscriber.recv().await.unwrap().subsystem.as_deref() == Some("drm") {
+                break;
+            }
+        }
+        assert_eq!(count, CAPACITY);
+    }
+
+    #[tokio::test]
+    async fn test_unwanted_events_dropped() {
+        let hub = UdevHub::new();
+        let subscriber = hub.subscribe(vec![("block".to_string(), None)], &[]);
+        for _ in 0..CAPACITY + 10 {
+            hub.publish(event("input", None));
+        }
+        hub.publish(event("block", Some("disk")));
+        drop(hub);
+
+        let mut stream = subscriber.into_stream();
+        let event = stream.next().await.unwrap().unwrap();
+        assert_eq!(event.devtype.as_deref(), Some("disk"));
+        let err = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "udev hub stopped");
+        assert!(stream.next().await.is_none());
+    }
+}
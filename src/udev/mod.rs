@@ -1,24 +1,322 @@
+//! udev device events.
+
+pub mod cfg;
+mod hub;
+mod rules;
+
+use std::collections::{HashMap, HashSet};
+
+use crate::notify::Notifier;
 use anyhow::Context as _;
-use futures::StreamExt;
-use tokio_udev::{AsyncMonitorSocket, MonitorBuilder};
+use futures::{Stream, StreamExt};
+use serde_derive::{Deserialize, Serialize};
+
+use self::cfg::UdevConfig;
+pub use self::hub::{Lagged, Subscriber, UdevHub};
+
+const ALERT_GROUP_UDEV_PREFIX: &str = "panorama.udev.";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Add,
+    Remove,
+    Change,
+    Bind,
+    Unbind,
+    Unknown,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Add => "add",
+            Self::Remove => "remove",
+            Self::Change => "change",
+            Self::Bind => "bind",
+            Self::Unbind => "unbind",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+impl From<tokio_udev::EventType> for Action {
+    fn from(kind: tokio_udev::EventType) -> Self {
+        match kind {
+            tokio_udev::EventType::Add => Self::Add,
+            tokio_udev::EventType::Remove => Self::Remove,
+            tokio_udev::EventType::Change => Self::Change,
+            tokio_udev::EventType::Bind => Self::Bind,
+            tokio_udev::EventType::Unbind => Self::Unbind,
+            tokio_udev::EventType::Unknown => Self::Unknown,
+        }
+    }
+}
+
+/// A udev device event, decoupled from libudev so it can be constructed in
+/// tests.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceEvent {
+    pub action: Action,
+    pub syspath: String,
+    pub subsystem: Option<String>,
+    pub devtype: Option<String>,
+    pub properties: HashMap<String, String>,
+    /// sysfs attributes, only the ones requested when converting.
+    pub attributes: HashMap<String, String>,
+}
+
+impl DeviceEvent {
+    /// Convert a udev device, reading the given sysfs attributes.
+    pub fn from_device(action: Action, device: &tokio_udev::Device, attributes: &[&str]) -> Self {
+        let string = |value: &std::ffi::OsStr| value.to_string_lossy().to_string();
+        Self {
+            action,
+            syspath: device.syspath().display().to_string(),
+            subsystem: device.subsystem().map(string),
+            devtype: device.devtype().map(string),
+            properties: device
+                .properties()
+                .map(|entry| (string(entry.name()), string(entry.value())))
+                .collect(),
+            attributes: attributes
+                .iter()
+                .filter_map(|name| {
+                    let value = device.attribute_value(name)?;
+                    Some((name.to_string(), string(value).trim().to_string()))
+                })
+                .collect(),
+        }
+    }
+
+    pub fn property(&self, name: &str) -> Option<&str> {
+        self.properties.get(name).map(String::as_str)
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(String::as_str)
+    }
+}
 
-pub async fn run() -> Result<(), anyhow::Error> {
-    let builder = MonitorBuilder::new()
-        .expect("Couldn't create builder")
-        .match_subsystem_devtype("usb", "usb_device")
-        .context("Failed to add filter for USB devices")?
-        .match_subsystem("power_supply")
-        .context("could not create power_supply filter")?;
+/// A subsystem and optional devtype to listen to.
+pub type Filter = (String, Option<String>);
 
-    let mut stream: AsyncMonitorSocket = builder
-        .listen()
-        .context("Couldn't listen on udev socket")?
-        .try_into()
-        .context("could not create udev monitor socket")?;
+/// List the current devices of a subsystem as `add` events.
+///
+/// Call it after [`UdevHub::subscribe`], see there.
+pub fn enumerate(
+    subsystem: &str,
+    devtype: Option<&str>,
+    attributes: &[&str],
+) -> Result<Vec<DeviceEvent>, anyhow::Error> {
+    let mut enumerator =
+        tokio_udev::Enumerator::new().context("could not create udev enumerator")?;
+    enumerator.match_subsystem(subsystem)?;
+    let devices = enumerator
+        .scan_devices()
+        .context("could not enumerate udev devices")?;
+    Ok(devices
+        .filter(|device| {
+            devtype.is_none_or(|devtype| device.devtype().is_some_and(|d| d == devtype))
+        })
+        .map(|device| DeviceEvent::from_device(Action::Add, &device, attributes))
+        .collect())
+}
+
+/// Events that bring a subscriber back in sync after it missed events.
+///
+/// Returns `add` events for the `current` devices that are not `known`,
+/// `change` events for the known ones, and `remove` events without subsystem
+/// and properties for the known devices that are gone.
+pub fn resync_events(current: Vec<DeviceEvent>, known: &HashSet<String>) -> Vec<DeviceEvent> {
+    let present = current
+        .iter()
+        .map(|event| event.syspath.clone())
+        .collect::<HashSet<_>>();
+    let removed = known
+        .iter()
+        .filter(|syspath| !present.contains(*syspath))
+        .map(|syspath| DeviceEvent {
+            action: Action::Remove,
+            syspath: syspath.clone(),
+            subsystem: None,
+            devtype: None,
+            properties: HashMap::new(),
+            attributes: HashMap::new(),
+        })
+        .collect::<Vec<_>>();
+    current
+        .into_iter()
+        .map(|event| DeviceEvent {
+            action: if known.contains(&event.syspath) {
+                Action::Change
+            } else {
+                Action::Add
+            },
+            ..event
+        })
+        .chain(removed)
+        .collect()
+}
+
+/// Sends alerts for the configured udev watchers.
+pub struct UdevManager {
+    config: UdevConfig,
+    notifier: Notifier,
+}
+
+impl UdevManager {
+    /// Subscribe to the events of the configured watchers.
+    pub fn subscribe(config: &UdevConfig, hub: &UdevHub) -> Subscriber {
+        let mut filters = Vec::<Filter>::new();
+        let mut attributes = Vec::<&str>::new();
+        for watcher in &config.watchers {
+            let filter = (watcher.subsystem.clone(), watcher.devtype.clone());
+            if !filters.contains(&filter) {
+                filters.push(filter);
+            }
+            attributes.extend(watcher.attribute_names());
+        }
+        hub.subscribe(filters, &attributes)
+    }
+
+    pub async fn start(
+        config: UdevConfig,
+        notifier: Notifier,
+        events: Subscriber,
+    ) -> Result<(), anyhow::Error> {
+        let manager = Self::new(config, notifier)?;
+        tokio::task::spawn_local(async move { manager.run_events(events.into_stream()).await })
+            .await
+            .context("UdevManager task failed")?
+            .context("UdevManager failed")?;
+
+        Ok(())
+    }
+
+    fn new(config: UdevConfig, notifier: Notifier) -> Result<Self, anyhow::Error> {
+        let config = config.validate()?;
+        Ok(Self { config, notifier })
+    }
+
+    async fn run_events(
+        self,
+        mut events: impl Stream<Item = Result<DeviceEvent, anyhow::Error>> + Unpin,
+    ) -> Result<(), anyhow::Error> {
+        while let Some(event) = events.next().await {
+            match event {
+                Ok(event) => self.handle(&event).await?,
+                // The watchers are stateless, missed events are just lost.
+                Err(err) if err.is::<Lagged>() => tracing::warn!(error = &*err, "udev events lost"),
+                Err(err) => return Err(err),
+            }
+        }
+        anyhow::bail!("udev event stream ended")
+    }
 
-    while let Some(res) = stream.next().await {
-        let _ev = res.context("failed to read udev event")?;
+    async fn handle(&self, event: &DeviceEvent) -> Result<(), anyhow::Error> {
+        for watcher in &self.config.watchers {
+            if !rules::matches(watcher, event) {
+                continue;
+            }
+            tracing::debug!(watcher=%watcher.name, syspath=%event.syspath, action=?event.action, "udev watcher matched");
+            let group = format!(
+                "{ALERT_GROUP_UDEV_PREFIX}{}.{}",
+                watcher.name, event.syspath
+            );
+            let full = watcher
+                .alert
+                .prepare(group, rules::variables(watcher, event));
+            self.notifier.notify(full).await?;
+        }
+        Ok(())
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
 
-    Ok(())
+    #[tokio::test]
+    async fn test_watchers() {
+        let config: UdevConfig = serde_yaml::from_str(
+            r#"
+watchers:
+  - name: keyboard
+    subsystem: input
+    properties:
+      ID_INPUT_KEYBOARD: "1"
+    alert:
+      severity: info
+      on_startup: false
+      repeat_after_seconds: null
+      expire_after_seconds: 10
+      summary: "Keyboard ${action}: ${NAME}"
+      message: null
+"#,
+        )
+        .unwrap();
+        let (notifier, mut alerts) = Notifier::test_channel();
+        let manager = UdevManager::new(config, notifier).unwrap();
+
+        let event = |action, keyboard: &str| {
+            Ok(DeviceEvent {
+                action,
+                syspath: "/sys/devices/virtual/input/input7".to_string(),
+                subsystem: Some("input".to_string()),
+                devtype: None,
+                properties: HashMap::from([
+                    ("ID_INPUT_KEYBOARD".to_string(), keyboard.to_string()),
+                    ("NAME".to_string(), "\"Keychron K2\"".to_string()),
+                ]),
+                attributes: HashMap::new(),
+            })
+        };
+        let events = futures::stream::iter([
+            event(Action::Add, "1"),
+            event(Action::Change, "1"),
+            event(Action::Add, "0"),
+            event(Action::Remove, "1"),
+        ]);
+        let err = manager.run_events(events).await.unwrap_err();
+        assert_eq!(err.to_string(), "udev event stream ended");
+
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(alert.alert.summary, "Keyboard ${action}: ${NAME}");
+        assert_eq!(alert.variables["action"], "add");
+        assert_eq!(
+            alert.group.as_deref(),
+            Some("panorama.udev.keyboard./sys/devices/virtual/input/input7")
+        );
+        assert_eq!(alerts.try_recv().unwrap().variables["action"], "remove");
+        assert!(alerts.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_resync_events() {
+        let event = |syspath: &str| DeviceEvent {
+            action: Action::Change,
+            syspath: syspath.to_string(),
+            subsystem: Some("usb".to_string()),
+            devtype: Some("usb_device".to_string()),
+            properties: HashMap::new(),
+            attributes: HashMap::new(),
+        };
+        let known = HashSet::from(["/sys/a".to_string(), "/sys/b".to_string()]);
+        let events = resync_events(vec![event("/sys/b"), event("/sys/c")], &known);
+        let summary = events
+            .iter()
+            .map(|ev| (ev.action, ev.syspath.as_str(), ev.subsystem.is_some()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (Action::Change, "/sys/b", true),
+                (Action::Add, "/sys/c", true),
+                (Action::Remove, "/sys/a", false),
+            ]
+        );
+    }
 }
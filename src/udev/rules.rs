@@ -0,0 +1,140 @@
+//! Matching of udev events against configured watchers.
+
+use std::collections::HashMap;
+
+use super::{cfg::UdevWatcher, DeviceEvent};
+
+/// Whether `event` matches all conditions of `watcher`.
+pub fn matches(watcher: &UdevWatcher, event: &DeviceEvent) -> bool {
+    event.subsystem.as_deref() == Some(watcher.subsystem.as_str())
+        && watcher
+            .devtype
+            .as_ref()
+            .is_none_or(|devtype| event.devtype.as_ref() == Some(devtype))
+        && watcher.actions.contains(&event.action)
+        && watcher.properties.iter().all(|(name, matcher)| {
+            event
+                .property(name)
+                .is_some_and(|value| matcher.is_match(value))
+        })
+        && watcher.attributes.iter().all(|(name, matcher)| {
+            event
+                .attribute(name)
+                .is_some_and(|value| matcher.is_match(value))
+        })
+}
+
+/// Alert variables for an event.
+pub fn variables(watcher: &UdevWatcher, event: &DeviceEvent) -> HashMap<String, String> {
+    let mut variables = event.properties.clone();
+    for (name, value) in &event.attributes {
+        variables.insert(format!("attr.{name}"), value.clone());
+    }
+    variables.extend([
+        ("watcher".to_string(), watcher.name.clone()),
+        ("action".to_string(), event.action.as_str().to_string()),
+        ("syspath".to_string(), event.syspath.clone()),
+        (
+            "subsystem".to_string(),
+            event.subsystem.clone().unwrap_or_default(),
+        ),
+        (
+            "devtype".to_string(),
+            event.devtype.clone().unwrap_or_default(),
+        ),
+    ]);
+    variables
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::udev::Action;
+
+    fn watcher(yaml: &str) -> UdevWatcher {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn event(action: Action) -> DeviceEvent {
+        DeviceEvent {
+            action,
+            syspath: "/sys/devices/pci0000:00/0000:00:02.0/drm/card1".to_string(),
+            subsystem: Some("drm".to_string()),
+            devtype: Some("drm_minor".to_string()),
+            properties: HashMap::from([
+                ("HOTPLUG".to_string(), "1".to_string()),
+                ("DEVNAME".to_string(), "/dev/dri/card1".to_string()),
+            ]),
+            attributes: HashMap::from([("dev".to_string(), "226:1".to_string())]),
+        }
+    }
+
+    const DRM: &str = r#"
+name: monitor
+subsystem: drm
+actions: [change]
+properties:
+  HOTPLUG: "1"
+  DEVNAME: /dev/dri/card*
+alert:
+  severity: info
+  on_startup: false
+  repeat_after_seconds: null
+  expire_after_seconds: 10
+  summary: "Display configuration changed on ${DEVNAME}"
+  message: null
+"#;
+
+    #[test]
+    fn test_matches() {
+        let w = watcher(DRM);
+        assert!(matches(&w, &event(Action::Change)));
+        assert!(!matches(&w, &event(Action::Add)));
+
+        let mut other = event(Action::Change);
+        other.subsystem = Some("input".to_string());
+        assert!(!matches(&w, &other));
+
+        // Missing properties never match.
+        let mut other = event(Action::Change);
+        other.properties.remove("HOTPLUG");
+        assert!(!matches(&w, &other));
+
+        let mut w = watcher(DRM);
+        w.devtype = Some("drm_connector".to_string());
+        assert!(!matches(&w, &event(Action::Change)));
+    }
+
+    #[test]
+    fn test_value_matchers() {
+        let mut w = watcher(DRM);
+        w.properties
+            .insert("DEVNAME".to_string(), "re:card[0-9]$".parse().unwrap());
+        w.attributes
+            .insert("dev".to_string(), "226:?".parse().unwrap());
+        assert!(matches(&w, &event(Action::Change)));
+
+        w.attributes
+            .insert("dev".to_string(), "226:1?".parse().unwrap());
+        assert!(!matches(&w, &event(Action::Change)));
+
+        // Globs match the whole value and treat other characters literally.
+        let glob: crate::udev::cfg::ValueMatcher = "a.c".parse().unwrap();
+        assert!(glob.is_match("a.c"));
+        assert!(!glob.is_match("abc"));
+        assert!(!glob.is_match("a.cd"));
+    }
+
+    #[test]
+    fn test_variables() {
+        let w = watcher(DRM);
+        let variables = variables(&w, &event(Action::Change));
+        assert_eq!(variables["DEVNAME"], "/dev/dri/card1");
+        assert_eq!(variables["attr.dev"], "226:1");
+        assert_eq!(variables["action"], "change");
+        assert_eq!(variables["watcher"], "monitor");
+        assert_eq!(variables["subsystem"], "drm");
+    }
+}
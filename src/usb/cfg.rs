@@ -0,0 +1,190 @@
+use std::path::PathBuf;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::cfg::{Alert, AlertSeverity};
+
+/// USB device attach/detach notifications.
+///
+/// Available variables: ${vendor}, ${product}, ${serial}, ${id_vendor},
+/// ${id_product}
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UsbConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Only devices matching any of these rules are announced.
+    /// All devices are announced if empty.
+    #[serde(default)]
+    pub include: Vec<UsbMatch>,
+    /// Devices matching any of these rules are not announced.
+    /// Defaults to ignoring hubs, including the internal root hubs.
+    #[serde(default = "UsbConfig::default_ignore")]
+    pub ignore: Vec<UsbMatch>,
+
+    #[serde(default = "UsbConfig::default_alert_attached")]
+    pub alert_attached: Option<Alert>,
+    #[serde(default = "UsbConfig::default_alert_detached")]
+    pub alert_detached: Option<Alert>,
+
+    #[serde(default)]
+    pub policy: UsbPolicyConfig,
+}
+
+impl UsbConfig {
+    fn default_ignore() -> Vec<UsbMatch> {
+        vec![
+            // Hubs, including the internal root hubs.
+            UsbMatch {
+                device_class: Some("09".to_string()),
+                ..Default::default()
+            },
+            // Linux Foundation (virtual root hubs).
+            UsbMatch {
+                id_vendor: Some("1d6b".to_string()),
+                ..Default::default()
+            },
+        ]
+    }
+
+    fn default_alert_attached() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Info,
+            on_startup: false,
+            repeat_after_seconds: None,
+            expire_after_seconds: Some(10),
+            summary: "USB device connected: ${vendor} ${product}".to_string(),
+            message: None,
+        })
+    }
+
+    fn default_alert_detached() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Info,
+            on_startup: false,
+            repeat_after_seconds: None,
+            expire_after_seconds: Some(10),
+            summary: "USB device disconnected: ${vendor} ${product}".to_string(),
+            message: None,
+        })
+    }
+}
+
+impl Default for UsbConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            include: Vec::new(),
+            ignore: Self::default_ignore(),
+            alert_attached: Self::default_alert_attached(),
+            alert_detached: Self::default_alert_detached(),
+            policy: UsbPolicyConfig::default(),
+        }
+    }
+}
+
+/// Security alerts for USB devices that are not on an allowlist, to detect
+/// eg. BadUSB devices posing as keyboards.
+///
+/// The allowlist has one `vendor:product:serial` entry per line, like
+/// `046d:c52b:*`, where `*` matches any value. Lines starting with `#` are
+/// ignored. Run `panorama --learn-usb-devices` to add the attached devices.
+///
+/// Available variables: as for [`UsbConfig`], plus ${hid_type} ("keyboard",
+/// "mouse" or "input device") for HID alerts.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UsbPolicyConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Defaults to `$XDG_CONFIG_HOME/panorama/usb-allowlist`.
+    #[serde(default)]
+    pub allowlist_path: Option<PathBuf>,
+
+    /// Sent when a device that is not on the allowlist is attached.
+    #[serde(default = "UsbPolicyConfig::default_alert_unknown_device")]
+    pub alert_unknown_device: Option<Alert>,
+    /// Sent when an unknown device registers a HID interface (class 03), ie.
+    /// acts as a keyboard or mouse.
+    #[serde(default = "UsbPolicyConfig::default_alert_hid")]
+    pub alert_hid: Option<Alert>,
+}
+
+impl UsbPolicyConfig {
+    pub fn allowlist_path(&self) -> PathBuf {
+        if let Some(path) = &self.allowlist_path {
+            return path.clone();
+        }
+
+        let dir = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => match std::env::var_os("HOME") {
+                Some(home) => PathBuf::from(home).join(".config"),
+                None => std::env::temp_dir(),
+            },
+        };
+        dir.join("panorama").join("usb-allowlist")
+    }
+
+    fn default_alert_unknown_device() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Critical,
+            // Devices may have been attached while panorama was not running.
+            on_startup: true,
+            repeat_after_seconds: None,
+            expire_after_seconds: None,
+            summary: "Unknown USB device attached: ${vendor} ${product}".to_string(),
+            message: Some(
+                "${id_vendor}:${id_product}:${serial} is not on the USB allowlist".to_string(),
+            ),
+        })
+    }
+
+    fn default_alert_hid() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Critical,
+            on_startup: true,
+            repeat_after_seconds: None,
+            expire_after_seconds: None,
+            summary: "Unknown USB ${hid_type} attached: ${vendor} ${product}".to_string(),
+            message: Some(
+                "${id_vendor}:${id_product}:${serial} is not on the USB allowlist and can send input"
+                    .to_string(),
+            ),
+        })
+    }
+}
+
+impl Default for UsbPolicyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            allowlist_path: None,
+            alert_unknown_device: Self::default_alert_unknown_device(),
+            alert_hid: Self::default_alert_hid(),
+        }
+    }
+}
+
+/// Matches USB devices. All specified fields must match.
+///
+/// IDs and classes are hexadecimal (eg "046d"). Names may end with a `*`
+/// wildcard.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct UsbMatch {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_vendor: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_product: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vendor: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product: Option<String>,
+    /// The `bDeviceClass` of the device, eg "09" for hubs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_class: Option<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
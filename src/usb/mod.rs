@@ -0,0 +1,316 @@
+pub mod cfg;
+pub mod policy;
+
+use std::collections::HashMap;
+
+use anyhow::Context;
+use futures::{Stream, StreamExt};
+
+use crate::{
+    network::cfg::interface_matches,
+    notify::Notifier,
+    udev::{resync_events, Action, DeviceEvent, Lagged, Subscriber, UdevHub},
+};
+
+use self::cfg::{UsbConfig, UsbMatch};
+
+const ALERT_GROUP_USB_PREFIX: &str = "panorama.usb.";
+
+const SUBSYSTEM: &str = "usb";
+const DEVTYPE: &str = "usb_device";
+/// Attributes used for matching. They are unavailable in remove events.
+const ATTRIBUTES: &[&str] = &["bDeviceClass", "manufacturer", "product"];
+
+/// Identification of a USB device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct UsbDevice {
+    vendor: String,
+    product: String,
+    serial: String,
+    id_vendor: String,
+    id_product: String,
+    device_class: String,
+}
+
+impl UsbDevice {
+    fn from_event(event: &DeviceEvent) -> Self {
+        let property = |name: &str| event.property(name).unwrap_or_default().to_string();
+        // The udev hardware database has the nicest names, the sanitized
+        // ID_VENDOR/ID_MODEL have underscores instead of spaces.
+        let name = |db: &str, attribute: &str, fallback: &str| {
+            event
+                .property(db)
+                .or(event.attribute(attribute))
+                .map(String::from)
+                .unwrap_or_else(|| property(fallback).replace('_', " "))
+        };
+        Self {
+            vendor: name("ID_VENDOR_FROM_DATABASE", "manufacturer", "ID_VENDOR"),
+            product: name("ID_MODEL_FROM_DATABASE", "product", "ID_MODEL"),
+            serial: property("ID_SERIAL_SHORT"),
+            id_vendor: property("ID_VENDOR_ID"),
+            id_product: property("ID_MODEL_ID"),
+            device_class: event
+                .attribute("bDeviceClass")
+                .unwrap_or_default()
+                .to_string(),
+        }
+    }
+
+    fn matches(&self, rule: &UsbMatch) -> bool {
+        let hex = |expected: &Option<String>, value: &str| {
+            expected
+                .as_ref()
+                .is_none_or(|expected| expected.eq_ignore_ascii_case(value))
+        };
+        let name = |expected: &Option<String>, value: &str| {
+            expected
+                .as_ref()
+                .is_none_or(|expected| interface_matches(expected, value))
+        };
+        hex(&rule.id_vendor, &self.id_vendor)
+            && hex(&rule.id_product, &self.id_product)
+            && hex(&rule.device_class, &self.device_class)
+            && name(&rule.serial, &self.serial)
+            && name(&rule.vendor, &self.vendor)
+            && name(&rule.product, &self.product)
+    }
+
+    fn variables(&self) -> HashMap<String, String> {
+        HashMap::from([
+            ("vendor".to_string(), self.vendor.clone()),
+            ("product".to_string(), self.product.clone()),
+            ("serial".to_string(), self.serial.clone()),
+            ("id_vendor".to_string(), self.id_vendor.clone()),
+            ("id_product".to_string(), self.id_product.clone()),
+        ])
+    }
+}
+
+pub struct UsbManager {
+    config: UsbConfig,
+    notifier: Notifier,
+    /// Attached devices by syspath, since remove events lack the attributes.
+    devices: HashMap<String, UsbDevice>,
+}
+
+impl UsbManager {
+    pub fn subscribe(hub: &UdevHub) -> Subscriber {
+        let filter = (SUBSYSTEM.to_string(), Some(DEVTYPE.to_string()));
+        hub.subscribe(vec![filter], ATTRIBUTES)
+    }
+
+    pub async fn start(
+        config: UsbConfig,
+        notifier: Notifier,
+        events: Subscriber,
+    ) -> Result<(), anyhow::Error> {
+        let manager = Self::new(config, notifier);
+        tokio::task::spawn_local(async move { manager.run(events).await })
+            .await
+            .context("UsbManager task failed")?
+            .context("UsbManager failed")?;
+
+        Ok(())
+    }
+
+    fn new(config: UsbConfig, notifier: Notifier) -> Self {
+        Self {
+            config,
+            notifier,
+            devices: HashMap::new(),
+        }
+    }
+
+    async fn run(mut self, events: Subscriber) -> Result<(), anyhow::Error> {
+        for event in crate::udev::enumerate(SUBSYSTEM, Some(DEVTYPE), ATTRIBUTES)? {
+            self.devices
+                .insert(event.syspath.clone(), UsbDevice::from_event(&event));
+        }
+        tracing::debug!(count = self.devices.len(), "found attached USB devices");
+
+        self.run_events(events.into_stream()).await
+    }
+
+    async fn run_events(
+        mut self,
+        mut events: impl Stream<Item = Result<DeviceEvent, anyhow::Error>> + Unpin,
+    ) -> Result<(), anyhow::Error> {
+        while let Some(event) = events.next().await {
+            match event {
+                Ok(event) => self.handle(event).await?,
+                Err(err) if err.is::<Lagged>() => {
+                    tracing::warn!(error = &*err, "re-enumerating USB devices");
+                    let current = crate::udev::enumerate(SUBSYSTEM, Some(DEVTYPE), ATTRIBUTES)?;
+                    let known = self.devices.keys().cloned().collect();
+                    for event in resync_events(current, &known) {
+                        self.handle(event).await?;
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        anyhow::bail!("udev event stream ended")
+    }
+
+    async fn handle(&mut self, event: DeviceEvent) -> Result<(), anyhow::Error> {
+        let (device, alert) = match event.action {
+            Action::Add => {
+                let device = UsbDevice::from_event(&event);
+                self.devices.insert(event.syspath.clone(), device.clone());
+                (device, &self.config.alert_attached)
+            }
+            Action::Remove => {
+                let device = self
+                    .devices
+                    .remove(&event.syspath)
+                    .unwrap_or_else(|| UsbDevice::from_event(&event));
+                (device, &self.config.alert_detached)
+            }
+            _ => return Ok(()),
+        };
+        tracing::debug!(?device, action=?event.action, "USB device event");
+
+        if !self.is_announced(&device) {
+            return Ok(());
+        }
+        if let Some(alert) = alert {
+            let group = format!("{ALERT_GROUP_USB_PREFIX}{}", event.syspath);
+            let full = alert.prepare(group, device.variables());
+            self.notifier.notify(full).await?;
+        }
+
+        Ok(())
+    }
+
+    fn is_announced(&self, device: &UsbDevice) -> bool {
+        let included = self.config.include.is_empty()
+            || self.config.include.iter().any(|rule| device.matches(rule));
+        included && !self.config.ignore.iter().any(|rule| device.matches(rule))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    /// A udev event of a USB flash drive.
+    fn usb_event(action: Action, syspath: &str) -> DeviceEvent {
+        let mut properties = HashMap::from(
+            [
+                ("ID_VENDOR", "SanDisk"),
+                ("ID_VENDOR_ID", "0781"),
+                ("ID_MODEL", "Ultra_Fit"),
+                ("ID_MODEL_ID", "5583"),
+                ("ID_SERIAL_SHORT", "4C530001"),
+            ]
+            .map(|(k, v)| (k.to_string(), v.to_string())),
+        );
+        let mut attributes = HashMap::new();
+        if action == Action::Add {
+            properties.insert(
+                "ID_VENDOR_FROM_DATABASE".to_string(),
+                "SanDisk Corp.".to_string(),
+            );
+            attributes.insert("bDeviceClass".to_string(), "00".to_string());
+        }
+        DeviceEvent {
+            action,
+            syspath: syspath.to_string(),
+            subsystem: Some(SUBSYSTEM.to_string()),
+            devtype: Some(DEVTYPE.to_string()),
+            properties,
+            attributes,
+        }
+    }
+
+    fn hub_event(action: Action) -> DeviceEvent {
+        let mut event = usb_event(action, "/sys/devices/pci0000:00/usb1/1-2");
+        event
+            .properties
+            .insert("ID_VENDOR_ID".to_string(), "05e3".to_string());
+        event
+            .attributes
+            .insert("bDeviceClass".to_string(), "09".to_string());
+        event
+    }
+
+    #[tokio::test]
+    async fn test_attach_and_detach() {
+        let (notifier, mut alerts) = Notifier::test_channel();
+        let mut manager = UsbManager::new(UsbConfig::default(), notifier);
+        let syspath = "/sys/devices/pci0000:00/usb1/1-1";
+
+        manager
+            .handle(usb_event(Action::Add, syspath))
+            .await
+            .unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(
+            alert.alert.summary,
+            "USB device connected: ${vendor} ${product}"
+        );
+        assert_eq!(alert.variables["vendor"], "SanDisk Corp.");
+        assert_eq!(alert.variables["product"], "Ultra Fit");
+        assert_eq!(alert.variables["serial"], "4C530001");
+        assert_eq!(alert.variables["id_vendor"], "0781");
+        assert_eq!(alert.variables["id_product"], "5583");
+
+        // Bind events of the same device are not announced.
+        manager
+            .handle(usb_event(Action::Bind, syspath))
+            .await
+            .unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        // Remembered from the add event.
+        manager
+            .handle(usb_event(Action::Remove, syspath))
+            .await
+            .unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(
+            alert.alert.summary,
+            "USB device disconnected: ${vendor} ${product}"
+        );
+        assert_eq!(alert.variables["vendor"], "SanDisk Corp.");
+        assert!(manager.devices.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_hubs_ignored() {
+        let (notifier, mut alerts) = Notifier::test_channel();
+        let mut manager = UsbManager::new(UsbConfig::default(), notifier);
+        manager.handle(hub_event(Action::Add)).await.unwrap();
+        manager.handle(hub_event(Action::Remove)).await.unwrap();
+        assert!(alerts.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_include_rules() {
+        let (notifier, mut alerts) = Notifier::test_channel();
+        let config = UsbConfig {
+            include: vec![UsbMatch {
+                id_vendor: Some("0781".to_string()),
+                product: Some("Ultra*".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut manager = UsbManager::new(config, notifier);
+        manager
+            .handle(usb_event(Action::Add, "/sys/devices/usb1/1-1"))
+            .await
+            .unwrap();
+        assert!(alerts.try_recv().is_ok());
+
+        let mut other = usb_event(Action::Add, "/sys/devices/usb1/1-3");
+        other
+            .properties
+            .insert("ID_VENDOR_ID".to_string(), "046D".to_string());
+        manager.handle(other).await.unwrap();
+        assert!(alerts.try_recv().is_err());
+    }
+}
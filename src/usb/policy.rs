@@ -0,0 +1,560 @@
+//! USB security policy: alerts for devices that are not on the allowlist.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use futures::{Stream, StreamExt};
+
+use crate::{
+    notify::Notifier,
+    udev::{resync_events, Action, DeviceEvent, Lagged, Subscriber, UdevHub},
+};
+
+use super::{cfg::UsbPolicyConfig, UsbDevice, ATTRIBUTES, DEVTYPE, SUBSYSTEM};
+
+const ALERT_GROUP_USB_POLICY_PREFIX: &str = "panorama.usb_policy.";
+
+const DEVTYPE_INTERFACE: &str = "usb_interface";
+const INTERFACE_ATTRIBUTES: &[&str] = &["bInterfaceClass", "bInterfaceProtocol"];
+const INTERFACE_CLASS_HID: &str = "03";
+
+/// An allowlist entry. `None` matches any value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct AllowEntry {
+    id_vendor: Option<String>,
+    id_product: Option<String>,
+    serial: Option<String>,
+}
+
+impl AllowEntry {
+    fn parse(entry: &str) -> Result<Self, anyhow::Error> {
+        let field = |value: &str| (value != "*").then(|| value.to_string());
+        // Serials may contain colons. All fields are required, so a truncated
+        // entry does not silently allow more devices than intended.
+        let mut parts = entry.splitn(3, ':');
+        let (Some(id_vendor), Some(id_product), Some(serial)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            anyhow::bail!("expected 'vendor:product:serial', with '*' matching any value");
+        };
+        let (id_vendor, id_product, serial) = (field(id_vendor), field(id_product), field(serial));
+        for id in [&id_vendor, &id_product].into_iter().flatten() {
+            if id.len() != 4 || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+                anyhow::bail!("invalid USB ID '{id}' - expected 4 hex digits or '*'");
+            }
+        }
+        Ok(Self {
+            id_vendor,
+            id_product,
+            serial,
+        })
+    }
+
+    fn for_device(device: &UsbDevice) -> Self {
+        Self {
+            id_vendor: Some(device.id_vendor.clone()),
+            id_product: Some(device.id_product.clone()),
+            serial: Some(device.serial.clone()),
+        }
+    }
+
+    fn matches(&self, device: &UsbDevice) -> bool {
+        let hex = |expected: &Option<String>, value: &str| {
+            expected
+                .as_ref()
+                .is_none_or(|expected| expected.eq_ignore_ascii_case(value))
+        };
+        hex(&self.id_vendor, &device.id_vendor)
+            && hex(&self.id_product, &device.id_product)
+            && self
+                .serial
+                .as_ref()
+                .is_none_or(|serial| *serial == device.serial)
+    }
+}
+
+impl std::fmt::Display for AllowEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let field = |value: &Option<String>| value.clone().unwrap_or_else(|| "*".to_string());
+        write!(
+            f,
+            "{}:{}:{}",
+            field(&self.id_vendor),
+            field(&self.id_product),
+            field(&self.serial)
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Allowlist {
+    entries: Vec<AllowEntry>,
+}
+
+impl Allowlist {
+    fn parse(content: &str) -> Result<Self, anyhow::Error> {
+        let entries = content
+            .lines()
+            .enumerate()
+            .filter_map(|(index, line)| {
+                let entry = line.split('#').next().unwrap_or_default().trim();
+                (!entry.is_empty()).then_some((index, entry))
+            })
+            .map(|(index, entry)| {
+                AllowEntry::parse(entry)
+                    .with_context(|| format!("invalid allowlist entry on line {}", index + 1))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { entries })
+    }
+
+    fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        Self::parse(&read_allowlist(path)?)
+            .with_context(|| format!("could not parse USB allowlist at '{}'", path.display()))
+    }
+
+    fn allows(&self, device: &UsbDevice) -> bool {
+        self.entries.iter().any(|entry| entry.matches(device))
+    }
+}
+
+/// Read the allowlist file, which is empty if it does not exist.
+fn read_allowlist(path: &Path) -> Result<String, anyhow::Error> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(content),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(err) => Err(err)
+            .with_context(|| format!("could not read USB allowlist at '{}'", path.display())),
+    }
+}
+
+/// Append entries for the devices that are not allowed yet, keeping the
+/// existing content. Returns the new content and the number of added devices.
+fn append_devices(content: &str, devices: &[UsbDevice]) -> Result<(String, usize), anyhow::Error> {
+    let mut allowlist = Allowlist::parse(content)?;
+    let mut out = content.to_string();
+    let mut added = 0;
+    for device in devices {
+        if allowlist.allows(device) {
+            continue;
+        }
+        if !out.is_empty() && !out.ends_with('\n') {
+            out.push('\n');
+        }
+        let entry = AllowEntry::for_device(device);
+        out.push_str(&format!("{entry} # {} {}\n", device.vendor, device.product));
+        allowlist.entries.push(entry);
+        added += 1;
+    }
+    Ok((out, added))
+}
+
+/// Add the currently attached USB devices to the allowlist.
+///
+/// Returns the path of the allowlist and the number of added devices.
+pub fn learn_devices(config: &UsbPolicyConfig) -> Result<(PathBuf, usize), anyhow::Error> {
+    let path = config.allowlist_path();
+    let content = read_allowlist(&path)?;
+    let devices = crate::udev::enumerate(SUBSYSTEM, Some(DEVTYPE), ATTRIBUTES)?
+        .iter()
+        .map(UsbDevice::from_event)
+        .collect::<Vec<_>>();
+    let (content, added) = append_devices(&content, &devices)
+        .with_context(|| format!("could not parse USB allowlist at '{}'", path.display()))?;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("could not create directory '{}'", dir.display()))?;
+    }
+    std::fs::write(&path, content)
+        .with_context(|| format!("could not write USB allowlist at '{}'", path.display()))?;
+    Ok((path, added))
+}
+
+/// Describe a HID interface, based on its boot protocol.
+fn hid_type(event: &DeviceEvent) -> &'static str {
+    match event.attribute("bInterfaceProtocol") {
+        Some("01") => "keyboard",
+        Some("02") => "mouse",
+        _ => "input device",
+    }
+}
+
+pub struct UsbPolicyManager {
+    config: UsbPolicyConfig,
+    notifier: Notifier,
+    allowlist_path: PathBuf,
+    allowlist: Allowlist,
+    /// Attached devices that are not on the allowlist, by syspath.
+    unknown: HashMap<String, UsbDevice>,
+    /// HID interfaces of unknown devices, which were already alerted.
+    hid_interfaces: HashSet<String>,
+}
+
+impl UsbPolicyManager {
+    pub fn subscribe(hub: &UdevHub) -> Subscriber {
+        let filters = [DEVTYPE, DEVTYPE_INTERFACE]
+            .map(|devtype| (SUBSYSTEM.to_string(), Some(devtype.to_string())))
+            .to_vec();
+        hub.subscribe(filters, &[ATTRIBUTES, INTERFACE_ATTRIBUTES].concat())
+    }
+
+    pub async fn start(
+        config: UsbPolicyConfig,
+        notifier: Notifier,
+        events: Subscriber,
+    ) -> Result<(), anyhow::Error> {
+        let manager = Self::new(config, notifier)?;
+        tokio::task::spawn_local(async move { manager.run(events).await })
+            .await
+            .context("UsbPolicyManager task failed")?
+            .context("UsbPolicyManager failed")?;
+
+        Ok(())
+    }
+
+    fn new(config: UsbPolicyConfig, notifier: Notifier) -> Result<Self, anyhow::Error> {
+        let allowlist_path = config.allowlist_path();
+        let allowlist = Allowlist::load(&allowlist_path)?;
+        if allowlist.entries.is_empty() {
+            tracing::warn!(
+                path=%allowlist_path.display(),
+                "USB allowlist is empty - run 'panorama --learn-usb-devices' to add the attached devices"
+            );
+        }
+        Ok(Self {
+            config,
+            notifier,
+            allowlist_path,
+            allowlist,
+            unknown: HashMap::new(),
+            hid_interfaces: HashSet::new(),
+        })
+    }
+
+    /// Devices attached before startup are checked as well, since they may
+    /// have been attached while panorama was not running.
+    async fn run(mut self, events: Subscriber) -> Result<(), anyhow::Error> {
+        for event in enumerate_devices()? {
+            self.handle(event, true).await?;
+        }
+
+        self.run_events(events.into_stream()).await
+    }
+
+    async fn run_events(
+        mut self,
+        mut events: impl Stream<Item = Result<DeviceEvent, anyhow::Error>> + Unpin,
+    ) -> Result<(), anyhow::Error> {
+        while let Some(event) = events.next().await {
+            match event {
+                Ok(event) => self.handle(event, false).await?,
+                Err(err) if err.is::<Lagged>() => {
+                    tracing::warn!(error = &*err, "re-enumerating USB devices");
+                    let current = enumerate_devices()?;
+                    let known = self.known(&current);
+                    for event in resync_events(current, &known) {
+                        self.handle(event, false).await?;
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        anyhow::bail!("udev event stream ended")
+    }
+
+    async fn handle(&mut self, event: DeviceEvent, initial: bool) -> Result<(), anyhow::Error> {
+        match (event.devtype.as_deref(), event.action) {
+            (Some(DEVTYPE), Action::Add) => self.device_added(event, initial).await,
+            // Resynced removals lack the devtype.
+            (_, Action::Remove) => {
+                self.unknown.remove(&event.syspath);
+                self.hid_interfaces.remove(&event.syspath);
+                self.hid_interfaces
+                    .retain(|interface| parent_syspath(interface) != event.syspath);
+                Ok(())
+            }
+            (Some(DEVTYPE_INTERFACE), Action::Add) => self.interface_added(event, initial).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// The unknown devices and the `current` interfaces of them, which were
+    /// already checked.
+    fn known(&self, current: &[DeviceEvent]) -> HashSet<String> {
+        let interfaces = current
+            .iter()
+            .filter(|event| event.devtype.as_deref() == Some(DEVTYPE_INTERFACE))
+            .filter(|event| self.unknown.contains_key(&parent_syspath(&event.syspath)));
+        self.unknown
+            .keys()
+            .cloned()
+            .chain(interfaces.map(|event| event.syspath.clone()))
+            .collect()
+    }
+
+    async fn device_added(
+        &mut self,
+        event: DeviceEvent,
+        initial: bool,
+    ) -> Result<(), anyhow::Error> {
+        // Reloaded for every device, so learned devices are allowed without a
+        // restart.
+        match Allowlist::load(&self.allowlist_path) {
+            Ok(allowlist) => self.allowlist = allowlist,
+            Err(err) => tracing::warn!(error = &*err, "keeping the previous USB allowlist"),
+        }
+
+        // Devices attached during the startup enumeration are seen twice.
+        if self.unknown.contains_key(&event.syspath) {
+            return Ok(());
+        }
+        let device = UsbDevice::from_event(&event);
+        if self.allowlist.allows(&device) {
+            return Ok(());
+        }
+        tracing::warn!(?device, "unknown USB device attached");
+        self.unknown.insert(event.syspath.clone(), device.clone());
+
+        if let Some(alert) = &self.config.alert_unknown_device {
+            let group = format!("{ALERT_GROUP_USB_POLICY_PREFIX}{}", event.syspath);
+            let full = alert.prepare(group, device.variables()).initial(initial);
+            self.notifier.notify(full).await?;
+        }
+        Ok(())
+    }
+
+    async fn interface_added(
+        &mut self,
+        event: DeviceEvent,
+        initial: bool,
+    ) -> Result<(), anyhow::Error> {
+        if event.attribute("bInterfaceClass") != Some(INTERFACE_CLASS_HID) {
+            return Ok(());
+        }
+        let device_syspath = parent_syspath(&event.syspath);
+        let Some(device) = self.unknown.get(&device_syspath) else {
+            return Ok(());
+        };
+        if !self.hid_interfaces.insert(event.syspath.clone()) {
+            return Ok(());
+        }
+        tracing::warn!(?device, "unknown USB device has a HID interface");
+
+        if let Some(alert) = &self.config.alert_hid {
+            let mut variables = device.variables();
+            variables.insert("hid_type".to_string(), hid_type(&event).to_string());
+            // Replaces the unknown device alert.
+            let group = format!("{ALERT_GROUP_USB_POLICY_PREFIX}{device_syspath}");
+            let full = alert.prepare(group, variables).initial(initial);
+            self.notifier.notify(full).await?;
+        }
+        Ok(())
+    }
+}
+
+/// The attached devices, followed by their interfaces.
+fn enumerate_devices() -> Result<Vec<DeviceEvent>, anyhow::Error> {
+    let attributes = [ATTRIBUTES, INTERFACE_ATTRIBUTES].concat();
+    let mut events = crate::udev::enumerate(SUBSYSTEM, Some(DEVTYPE), &attributes)?;
+    events.extend(crate::udev::enumerate(
+        SUBSYSTEM,
+        Some(DEVTYPE_INTERFACE),
+        &attributes,
+    )?);
+    Ok(events)
+}
+
+/// Interfaces are children of their device.
+fn parent_syspath(syspath: &str) -> String {
+    Path::new(syspath)
+        .parent()
+        .map(|parent| parent.display().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{notify::PreparedAlert, testutil::TempDir};
+
+    const SYSPATH: &str = "/sys/devices/pci0000:00/usb1/1-1";
+
+    fn device(id_vendor: &str, id_product: &str, serial: &str) -> UsbDevice {
+        UsbDevice {
+            vendor: "Logitech".to_string(),
+            product: "Receiver".to_string(),
+            serial: serial.to_string(),
+            id_vendor: id_vendor.to_string(),
+            id_product: id_product.to_string(),
+            device_class: "00".to_string(),
+        }
+    }
+
+    fn device_event(action: Action, id_vendor: &str) -> DeviceEvent {
+        DeviceEvent {
+            action,
+            syspath: SYSPATH.to_string(),
+            subsystem: Some(SUBSYSTEM.to_string()),
+            devtype: Some(DEVTYPE.to_string()),
+            properties: HashMap::from(
+                [
+                    ("ID_VENDOR", "Evil"),
+                    ("ID_MODEL", "Keyboard"),
+                    ("ID_VENDOR_ID", id_vendor),
+                    ("ID_MODEL_ID", "0001"),
+                    ("ID_SERIAL_SHORT", "1234"),
+                ]
+                .map(|(k, v)| (k.to_string(), v.to_string())),
+            ),
+            attributes: HashMap::new(),
+        }
+    }
+
+    fn interface_event(class: &str) -> DeviceEvent {
+        DeviceEvent {
+            action: Action::Add,
+            syspath: format!("{SYSPATH}/1-1:1.0"),
+            subsystem: Some(SUBSYSTEM.to_string()),
+            devtype: Some(DEVTYPE_INTERFACE.to_string()),
+            properties: HashMap::new(),
+            attributes: HashMap::from([
+                ("bInterfaceClass".to_string(), class.to_string()),
+                ("bInterfaceProtocol".to_string(), "01".to_string()),
+            ]),
+        }
+    }
+
+    fn manager(
+        dir: &TempDir,
+        allowlist: &str,
+    ) -> (UsbPolicyManager, mpsc::Receiver<PreparedAlert>) {
+        let config = UsbPolicyConfig {
+            enabled: true,
+            allowlist_path: Some(dir.write("usb-allowlist", allowlist)),
+            ..Default::default()
+        };
+        let (notifier, alerts) = Notifier::test_channel();
+        (UsbPolicyManager::new(config, notifier).unwrap(), alerts)
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let allowlist = Allowlist::parse(
+            "# Allowed devices\n\n046d:c52b:* # Logitech receivers\n0781:5583:4C53:01\n1d6b:*:*\n",
+        )
+        .unwrap();
+        assert!(allowlist.allows(&device("046D", "c52b", "anything")));
+        assert!(allowlist.allows(&device("0781", "5583", "4C53:01")));
+        assert!(!allowlist.allows(&device("0781", "5583", "4C53")));
+        assert!(allowlist.allows(&device("1d6b", "0002", "")));
+        assert!(!allowlist.allows(&device("046d", "c52c", "")));
+
+        let err = Allowlist::parse("046d:c52b:*\nlogitech:*:*\n").unwrap_err();
+        assert_eq!(err.to_string(), "invalid allowlist entry on line 2");
+
+        // No implicit wildcards for missing fields.
+        for entry in ["046d:c52b", "1d6b"] {
+            let err = Allowlist::parse(entry).unwrap_err();
+            assert_eq!(
+                format!("{err:#}"),
+                "invalid allowlist entry on line 1: \
+                 expected 'vendor:product:serial', with '*' matching any value"
+            );
+        }
+    }
+
+    #[test]
+    fn test_append_devices() {
+        let devices = [
+            device("046d", "c52b", "A1"),
+            device("0781", "5583", ""),
+            device("0781", "5583", ""),
+        ];
+        let (content, added) = append_devices("046d:c52b:* # receivers", &devices).unwrap();
+        assert_eq!(added, 1);
+        assert_eq!(
+            content,
+            "046d:c52b:* # receivers\n0781:5583: # Logitech Receiver\n"
+        );
+
+        // Learning again adds nothing.
+        let (again, added) = append_devices(&content, &devices).unwrap();
+        assert_eq!(added, 0);
+        assert_eq!(again, content);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_hid_device() {
+        let dir = TempDir::new("usb-policy");
+        let (mut manager, mut alerts) = manager(&dir, "046d:*:*\n");
+
+        manager
+            .handle(device_event(Action::Add, "dead"), false)
+            .await
+            .unwrap();
+        let alert = alerts.try_recv().unwrap();
+        assert_eq!(
+            alert.alert.summary,
+            "Unknown USB device attached: ${vendor} ${product}"
+        );
+        assert_eq!(alert.variables["id_vendor"], "dead");
+        assert_eq!(alert.variables["serial"], "1234");
+
+        // Mass storage interfaces are not alerted separately.
+        manager.handle(interface_event("08"), false).await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        manager.handle(interface_event("03"), false).await.unwrap();
+        let hid = alerts.try_recv().unwrap();
+        assert_eq!(
+            hid.alert.summary,
+            "Unknown USB ${hid_type} attached: ${vendor} ${product}"
+        );
+        assert_eq!(hid.variables["hid_type"], "keyboard");
+        assert_eq!(hid.group, alert.group);
+
+        // Seen by both the enumeration and the subscription on startup.
+        manager
+            .handle(device_event(Action::Add, "dead"), false)
+            .await
+            .unwrap();
+        manager.handle(interface_event("03"), false).await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        manager
+            .handle(device_event(Action::Remove, "dead"), false)
+            .await
+            .unwrap();
+        assert!(manager.unknown.is_empty());
+        assert!(manager.hid_interfaces.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_allowed_device() {
+        let dir = TempDir::new("usb-policy");
+        let (mut manager, mut alerts) = manager(&dir, "");
+
+        // Learned while running.
+        dir.write("usb-allowlist", "dead:0001:1234\n");
+        manager
+            .handle(device_event(Action::Add, "dead"), true)
+            .await
+            .unwrap();
+        manager.handle(interface_event("03"), true).await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        // Unknown devices present at startup are reported as well.
+        manager
+            .handle(device_event(Action::Add, "beef"), true)
+            .await
+            .unwrap();
+        assert!(alerts.try_recv().unwrap().initial);
+    }
+}
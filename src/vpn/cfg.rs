@@ -0,0 +1,123 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    cfg::{Alert, AlertSeverity},
+    internet::cfg::Probe,
+};
+
+/// VPN tunnel monitoring.
+///
+/// Available variables: ${tunnel}, plus ${reason} for `alert_down`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VpnConfig {
+    #[serde(default = "VpnConfig::default_check_interval_seconds")]
+    pub check_interval_seconds: u64,
+    /// Tunnels to monitor. Monitoring is disabled if empty.
+    #[serde(default)]
+    pub tunnels: Vec<Tunnel>,
+    /// Command used to read WireGuard handshakes (`wg show <interface> dump`).
+    /// Usually requires root or `CAP_NET_ADMIN`, without them only the
+    /// interface state and the probe are checked.
+    #[serde(default = "VpnConfig::default_wg_command")]
+    pub wg_command: String,
+
+    #[serde(default = "VpnConfig::default_alert_up")]
+    pub alert_up: Option<Alert>,
+    #[serde(default = "VpnConfig::default_alert_down")]
+    pub alert_down: Option<Alert>,
+}
+
+impl VpnConfig {
+    pub fn validate(self) -> Result<Self, anyhow::Error> {
+        if self.check_interval_seconds == 0 {
+            anyhow::bail!("'vpn.check_interval_seconds' must be greater than 0");
+        }
+        let mut names = std::collections::HashSet::new();
+        for tunnel in &self.tunnels {
+            if !names.insert(&tunnel.interface) {
+                anyhow::bail!(
+                    "VPN tunnel '{}' is defined multiple times",
+                    tunnel.interface
+                );
+            }
+        }
+        Ok(self)
+    }
+
+    fn default_check_interval_seconds() -> u64 {
+        30
+    }
+
+    fn default_wg_command() -> String {
+        "wg".to_string()
+    }
+
+    fn default_alert_up() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Info,
+            on_startup: false,
+            repeat_after_seconds: None,
+            expire_after_seconds: Some(10),
+            summary: "VPN ${tunnel} is connected".to_string(),
+            message: None,
+        })
+    }
+
+    fn default_alert_down() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Warning,
+            on_startup: true,
+            repeat_after_seconds: None,
+            expire_after_seconds: None,
+            summary: "VPN ${tunnel} is down".to_string(),
+            message: Some("${reason}".to_string()),
+        })
+    }
+}
+
+impl Default for VpnConfig {
+    fn default() -> Self {
+        Self {
+            check_interval_seconds: Self::default_check_interval_seconds(),
+            tunnels: Vec::new(),
+            wg_command: Self::default_wg_command(),
+            alert_up: Self::default_alert_up(),
+            alert_down: Self::default_alert_down(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Tunnel {
+    /// Name of the tunnel interface (eg "wg0" or "tun0").
+    pub interface: String,
+    #[serde(default)]
+    pub kind: TunnelKind,
+    /// Consider a WireGuard tunnel down if no peer completed a handshake
+    /// within this time. WireGuard re-handshakes every two minutes while
+    /// traffic is flowing.
+    #[serde(default = "Tunnel::default_max_handshake_age_seconds")]
+    pub max_handshake_age_seconds: u64,
+    /// Probe of an internal host that is only reachable through the tunnel.
+    ///
+    /// The probe is not bound to the tunnel interface, since that requires
+    /// `CAP_NET_RAW`, but follows the routing table. A target that is also
+    /// routed outside of the tunnel would keep the tunnel reported as up.
+    #[serde(default)]
+    pub probe: Option<Probe>,
+}
+
+impl Tunnel {
+    fn default_max_handshake_age_seconds() -> u64 {
+        180
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TunnelKind {
+    #[default]
+    Wireguard,
+    /// OpenVPN or any other tunnel, only the interface state is checked.
+    Openvpn,
+}
@@ -0,0 +1,374 @@
+pub mod cfg;
+mod wireguard;
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+
+use crate::{internet::probe, notify::Notifier};
+
+use self::cfg::{Tunnel, TunnelKind, VpnConfig};
+
+const ALERT_GROUP_VPN_PREFIX: &str = "panorama.vpn.";
+
+/// `IFF_UP` from `linux/if.h`.
+const IFF_UP: u32 = 0x1;
+
+pub struct VpnManager {
+    config: VpnConfig,
+    notifier: Notifier,
+    sysfs_root: PathBuf,
+    /// Last known state of each tunnel, keyed by interface.
+    tunnels_up: HashMap<String, bool>,
+    /// Cleared once `wg` lacked the permissions, so handshakes are not
+    /// checked anymore.
+    wg_permitted: bool,
+}
+
+impl VpnManager {
+    pub async fn start(config: VpnConfig, notifier: Notifier) -> Result<(), anyhow::Error> {
+        let manager = Self::new(config, notifier)?;
+        tokio::task::spawn_local(async move { manager.run().await })
+            .await
+            .context("VpnManager task failed")?
+            .context("VpnManager failed")?;
+
+        Ok(())
+    }
+
+    fn new(config: VpnConfig, notifier: Notifier) -> Result<Self, anyhow::Error> {
+        let config = config.validate()?;
+        Ok(Self {
+            config,
+            notifier,
+            sysfs_root: PathBuf::from("/sys"),
+            tunnels_up: HashMap::new(),
+            wg_permitted: true,
+        })
+    }
+
+    async fn run(mut self) -> Result<(), anyhow::Error> {
+        let interval = Duration::from_secs(self.config.check_interval_seconds);
+        loop {
+            self.tick().await?;
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    async fn tick(&mut self) -> Result<(), anyhow::Error> {
+        for tunnel in self.config.tunnels.clone() {
+            let res = self.check(&tunnel).await;
+            if let Err(reason) = &res {
+                tracing::debug!(tunnel=%tunnel.interface, %reason, "VPN tunnel is down");
+            }
+
+            let up = res.is_ok();
+            let previous = self.tunnels_up.insert(tunnel.interface.clone(), up);
+            if previous == Some(up) {
+                continue;
+            }
+
+            let alert = if up {
+                &self.config.alert_up
+            } else {
+                &self.config.alert_down
+            };
+            let Some(alert) = alert else {
+                continue;
+            };
+            let variables = HashMap::from([
+                ("tunnel".to_string(), tunnel.interface.clone()),
+                ("reason".to_string(), res.err().unwrap_or_default()),
+            ]);
+            let group = format!("{ALERT_GROUP_VPN_PREFIX}{}", tunnel.interface);
+            let full = alert.prepare(group, variables).initial(previous.is_none());
+            self.notifier.notify(full).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Check a single tunnel, returning the reason if it is down.
+    async fn check(&mut self, tunnel: &Tunnel) -> Result<(), String> {
+        match interface_up(&self.sysfs_root, &tunnel.interface) {
+            Ok(true) => {}
+            Ok(false) => return Err(format!("interface {} is down", tunnel.interface)),
+            Err(err) => {
+                tracing::trace!(error = &*err, "could not read interface flags");
+                return Err(format!("interface {} does not exist", tunnel.interface));
+            }
+        }
+
+        if tunnel.kind == TunnelKind::Wireguard && self.wg_permitted {
+            match self.latest_handshake(&tunnel.interface).await {
+                Ok(handshake) => {
+                    let age =
+                        handshake.map(|h| SystemTime::now().duration_since(h).unwrap_or_default());
+                    match age {
+                        None => return Err("no WireGuard handshake completed".to_string()),
+                        Some(age) if age.as_secs() > tunnel.max_handshake_age_seconds => {
+                            return Err(format!(
+                                "last WireGuard handshake was {} seconds ago",
+                                age.as_secs()
+                            ));
+                        }
+                        Some(_) => {}
+                    }
+                }
+                // The handshake is unknown then, which is no reason to
+                // consider the tunnel down.
+                Err(err) if err.is::<PermissionDenied>() => {
+                    tracing::warn!(
+                        error = &*err,
+                        "not checking WireGuard handshakes - run panorama as root or with CAP_NET_ADMIN"
+                    );
+                    self.wg_permitted = false;
+                }
+                Err(err) => return Err(format!("could not read WireGuard status: {err:#}")),
+            }
+        }
+
+        if let Some(probe) = &tunnel.probe {
+            probe::run(probe)
+                .await
+                .map_err(|err| format!("probe {} failed: {:#}", probe.target, err.into_inner()))?;
+        }
+
+        Ok(())
+    }
+
+    async fn latest_handshake(&self, interface: &str) -> Result<Option<SystemTime>, anyhow::Error> {
+        let output = match tokio::process::Command::new(&self.config.wg_command)
+            .args(["show", interface, "dump"])
+            .output()
+            .await
+        {
+            Ok(output) => output,
+            Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied => {
+                return Err(PermissionDenied(err.to_string()).into());
+            }
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("could not run '{}'", self.config.wg_command));
+            }
+        };
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            // Reading the interface over netlink fails with EPERM.
+            if stderr.contains("Operation not permitted") {
+                return Err(PermissionDenied(stderr).into());
+            }
+            anyhow::bail!("wg failed: {stderr}");
+        }
+        let peers = wireguard::parse_dump(&String::from_utf8_lossy(&output.stdout))?;
+        Ok(wireguard::latest_handshake(&peers))
+    }
+}
+
+/// `wg` lacks the permissions to read the WireGuard status.
+#[derive(Debug)]
+struct PermissionDenied(String);
+
+impl std::fmt::Display for PermissionDenied {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "wg lacks permissions: {}", self.0)
+    }
+}
+
+impl std::error::Error for PermissionDenied {}
+
+/// Whether the interface is administratively up.
+fn interface_up(sysfs_root: &Path, interface: &str) -> Result<bool, anyhow::Error> {
+    let path = sysfs_root.join("class/net").join(interface).join("flags");
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("could not read '{}'", path.display()))?;
+    let flags = u32::from_str_radix(content.trim().trim_start_matches("0x"), 16)
+        .with_context(|| format!("invalid interface flags '{}'", content.trim()))?;
+    Ok(flags & IFF_UP != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::PermissionsExt, time::UNIX_EPOCH};
+
+    use pretty_assertions::assert_eq;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{
+        internet::cfg::{Probe, ProbeTarget},
+        notify::PreparedAlert,
+        testutil::TempDir,
+    };
+
+    fn tunnel(interface: &str, kind: TunnelKind) -> Tunnel {
+        Tunnel {
+            interface: interface.to_string(),
+            kind,
+            max_handshake_age_seconds: 180,
+            probe: None,
+        }
+    }
+
+    fn manager(dir: &TempDir, tunnels: Vec<Tunnel>) -> (VpnManager, mpsc::Receiver<PreparedAlert>) {
+        let config = VpnConfig {
+            tunnels,
+            wg_command: dir.path().join("wg").display().to_string(),
+            ..Default::default()
+        };
+        let (notifier, alerts) = Notifier::test_channel();
+        let mut manager = VpnManager::new(config, notifier).unwrap();
+        manager.sysfs_root = dir.path().to_path_buf();
+        (manager, alerts)
+    }
+
+    /// Install a fake `wg` that prints the `dump` file next to it.
+    fn install_wg(dir: &TempDir) {
+        let path = dir.write("wg", "#!/bin/sh\ncat \"$(dirname \"$0\")/dump\"\n");
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    /// Report a single peer with the given handshake age.
+    fn write_dump(dir: &TempDir, handshake_age: Option<u64>) {
+        let handshake = handshake_age
+            .map(|age| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs()
+                    - age
+            })
+            .unwrap_or(0);
+        dir.write(
+            "dump",
+            format!("priv\tpub\t51820\toff\npeer\t(none)\t203.0.113.7:51820\t0.0.0.0/0\t{handshake}\t0\t0\toff\n"),
+        );
+    }
+
+    fn next(alerts: &mut mpsc::Receiver<PreparedAlert>) -> (String, HashMap<String, String>) {
+        let alert = alerts.try_recv().unwrap();
+        (alert.alert.summary, alert.variables)
+    }
+
+    #[tokio::test]
+    async fn test_wireguard_handshake() {
+        let dir = TempDir::new("vpn-wireguard");
+        dir.write("class/net/wg0/flags", "0x1091\n");
+        install_wg(&dir);
+        write_dump(&dir, Some(10));
+        let (mut manager, mut alerts) = manager(&dir, vec![tunnel("wg0", TunnelKind::Wireguard)]);
+
+        // Connected at startup, which is not announced by default.
+        manager.tick().await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        write_dump(&dir, Some(600));
+        manager.tick().await.unwrap();
+        let (summary, variables) = next(&mut alerts);
+        assert_eq!(summary, "VPN ${tunnel} is down");
+        assert_eq!(variables["tunnel"], "wg0");
+        assert!(variables["reason"].starts_with("last WireGuard handshake was 60"));
+
+        // Still down, no repeated alert.
+        write_dump(&dir, None);
+        manager.tick().await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        write_dump(&dir, Some(5));
+        manager.tick().await.unwrap();
+        assert_eq!(next(&mut alerts).0, "VPN ${tunnel} is connected");
+    }
+
+    #[tokio::test]
+    async fn test_wireguard_without_permissions() {
+        let dir = TempDir::new("vpn-wireguard-eperm");
+        dir.write("class/net/wg0/flags", "0x1091\n");
+        let path = dir.write(
+            "wg",
+            "#!/bin/sh\necho 'Unable to access interface: Operation not permitted' >&2\nexit 1\n",
+        );
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let mut tunnel = tunnel("wg0", TunnelKind::Wireguard);
+        tunnel.max_handshake_age_seconds = 0;
+        let (mut manager, mut alerts) = manager(&dir, vec![tunnel]);
+        manager.config.alert_up.as_mut().unwrap().on_startup = true;
+
+        // Only the interface state is checked then.
+        manager.tick().await.unwrap();
+        assert_eq!(next(&mut alerts).0, "VPN ${tunnel} is connected");
+        assert!(!manager.wg_permitted);
+
+        dir.write("class/net/wg0/flags", "0x1090\n");
+        manager.tick().await.unwrap();
+        assert_eq!(next(&mut alerts).1["reason"], "interface wg0 is down");
+    }
+
+    #[tokio::test]
+    async fn test_interface_state() {
+        let dir = TempDir::new("vpn-interface");
+        dir.write("class/net/tun0/flags", "0x1090\n");
+        let (mut manager, mut alerts) = manager(
+            &dir,
+            vec![
+                tunnel("tun0", TunnelKind::Openvpn),
+                tunnel("tun1", TunnelKind::Openvpn),
+            ],
+        );
+
+        manager.tick().await.unwrap();
+        let (_, variables) = next(&mut alerts);
+        assert_eq!(variables["reason"], "interface tun0 is down");
+        let (_, variables) = next(&mut alerts);
+        assert_eq!(variables["reason"], "interface tun1 does not exist");
+
+        dir.write("class/net/tun0/flags", "0x1091\n");
+        manager.tick().await.unwrap();
+        let (summary, variables) = next(&mut alerts);
+        assert_eq!(summary, "VPN ${tunnel} is connected");
+        assert_eq!(variables["tunnel"], "tun0");
+        assert!(alerts.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_probe_through_tunnel() {
+        let dir = TempDir::new("vpn-probe");
+        dir.write("class/net/tun0/flags", "0x1091\n");
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut tunnel = tunnel("tun0", TunnelKind::Openvpn);
+        tunnel.probe = Some(Probe::new(ProbeTarget::Tcp {
+            host: "127.0.0.1".to_string(),
+            port,
+        }));
+        let (mut manager, mut alerts) = manager(&dir, vec![tunnel]);
+
+        manager.tick().await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        drop(listener);
+        manager.tick().await.unwrap();
+        let (summary, variables) = next(&mut alerts);
+        assert_eq!(summary, "VPN ${tunnel} is down");
+        assert!(variables["reason"].starts_with(&format!("probe tcp://127.0.0.1:{port} failed")));
+    }
+
+    #[test]
+    fn test_duplicate_tunnels() {
+        let config = VpnConfig {
+            tunnels: vec![
+                tunnel("wg0", TunnelKind::Wireguard),
+                tunnel("wg0", TunnelKind::Wireguard),
+            ],
+            ..Default::default()
+        };
+        let err = config.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "VPN tunnel 'wg0' is defined multiple times"
+        );
+    }
+}
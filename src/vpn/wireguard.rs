@@ -0,0 +1,91 @@
+//! Parsing of `wg show <interface> dump` output.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Peer {
+    pub public_key: String,
+    pub endpoint: Option<String>,
+    /// `None` if no handshake happened yet.
+    pub latest_handshake: Option<SystemTime>,
+}
+
+/// Parse the peers of a `wg show <interface> dump`.
+///
+/// The first line describes the interface, each following line a peer with
+/// the tab separated fields public key, preshared key, endpoint, allowed ips,
+/// latest handshake, rx bytes, tx bytes and persistent keepalive.
+pub fn parse_dump(output: &str) -> Result<Vec<Peer>, anyhow::Error> {
+    output
+        .lines()
+        .skip(1)
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let fields = line.split('\t').collect::<Vec<_>>();
+            if fields.len() < 8 {
+                anyhow::bail!("invalid wg dump peer line: '{line}'");
+            }
+            let handshake = fields[4]
+                .parse::<u64>()
+                .map_err(|_| anyhow::anyhow!("invalid latest handshake '{}'", fields[4]))?;
+            Ok(Peer {
+                public_key: fields[0].to_string(),
+                endpoint: (fields[2] != "(none)").then(|| fields[2].to_string()),
+                latest_handshake: (handshake > 0)
+                    .then(|| UNIX_EPOCH + Duration::from_secs(handshake)),
+            })
+        })
+        .collect()
+}
+
+/// The most recent handshake of any peer.
+pub fn latest_handshake(peers: &[Peer]) -> Option<SystemTime> {
+    peers.iter().filter_map(|peer| peer.latest_handshake).max()
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    const DUMP: &str = "\
+yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=\tHIgo9xNzJMWLKASShiTqIybxZ0U3wGLiUeJ1PKf8ykw=\t51820\toff
+xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=\t(none)\t203.0.113.7:51820\t10.0.0.0/8\t1700000000\t123456\t654321\t25
+TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=\t(none)\t(none)\t10.1.0.2/32\t0\t0\t0\toff
+";
+
+    #[test]
+    fn test_parse_dump() {
+        let peers = parse_dump(DUMP).unwrap();
+        assert_eq!(
+            peers,
+            vec![
+                Peer {
+                    public_key: "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=".to_string(),
+                    endpoint: Some("203.0.113.7:51820".to_string()),
+                    latest_handshake: Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+                },
+                Peer {
+                    public_key: "TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=".to_string(),
+                    endpoint: None,
+                    latest_handshake: None,
+                },
+            ]
+        );
+        assert_eq!(
+            latest_handshake(&peers),
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+    }
+
+    #[test]
+    fn test_parse_dump_invalid() {
+        let err = parse_dump("iface\nkey\t(none)\n").unwrap_err();
+        assert_eq!(err.to_string(), "invalid wg dump peer line: 'key\t(none)'");
+
+        // No peers.
+        assert_eq!(parse_dump(DUMP.lines().next().unwrap()).unwrap(), vec![]);
+        assert_eq!(latest_handshake(&[]), None);
+    }
+}
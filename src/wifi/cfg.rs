@@ -0,0 +1,106 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::cfg::{Alert, AlertSeverity};
+
+/// Wi-Fi connection and signal strength notifications.
+///
+/// Available variables: ${interface}, ${ssid}, ${signal_dbm}
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WifiConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "WifiConfig::default_check_interval_seconds")]
+    pub check_interval_seconds: u64,
+    /// Signal strength below which the signal is considered weak.
+    #[serde(default = "WifiConfig::default_weak_signal_dbm")]
+    pub weak_signal_dbm: i32,
+    /// How far the signal must rise above `weak_signal_dbm` to be considered
+    /// recovered.
+    #[serde(default = "WifiConfig::default_signal_hysteresis_db")]
+    pub signal_hysteresis_db: i32,
+
+    #[serde(default = "WifiConfig::default_alert_connected")]
+    pub alert_connected: Option<Alert>,
+    #[serde(default = "WifiConfig::default_alert_disconnected")]
+    pub alert_disconnected: Option<Alert>,
+    #[serde(default = "WifiConfig::default_alert_weak_signal")]
+    pub alert_weak_signal: Option<Alert>,
+    #[serde(default)]
+    pub alert_signal_recovered: Option<Alert>,
+}
+
+impl WifiConfig {
+    pub fn validate(self) -> Result<Self, anyhow::Error> {
+        if self.check_interval_seconds == 0 {
+            anyhow::bail!("'wifi.check_interval_seconds' must be greater than 0");
+        }
+        if self.signal_hysteresis_db < 0 {
+            anyhow::bail!("'wifi.signal_hysteresis_db' must not be negative");
+        }
+        Ok(self)
+    }
+
+    fn default_check_interval_seconds() -> u64 {
+        10
+    }
+
+    fn default_weak_signal_dbm() -> i32 {
+        -75
+    }
+
+    fn default_signal_hysteresis_db() -> i32 {
+        5
+    }
+
+    fn default_alert_connected() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Info,
+            on_startup: false,
+            repeat_after_seconds: None,
+            expire_after_seconds: Some(10),
+            summary: "Wi-Fi connected to ${ssid}".to_string(),
+            message: None,
+        })
+    }
+
+    fn default_alert_disconnected() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Warning,
+            on_startup: false,
+            repeat_after_seconds: None,
+            expire_after_seconds: Some(30),
+            summary: "Wi-Fi disconnected from ${ssid}".to_string(),
+            message: None,
+        })
+    }
+
+    fn default_alert_weak_signal() -> Option<Alert> {
+        Some(Alert {
+            severity: AlertSeverity::Warning,
+            on_startup: true,
+            repeat_after_seconds: None,
+            expire_after_seconds: Some(30),
+            summary: "Weak Wi-Fi signal on ${ssid} (${signal_dbm} dBm)".to_string(),
+            message: None,
+        })
+    }
+}
+
+impl Default for WifiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            check_interval_seconds: Self::default_check_interval_seconds(),
+            weak_signal_dbm: Self::default_weak_signal_dbm(),
+            signal_hysteresis_db: Self::default_signal_hysteresis_db(),
+            alert_connected: Self::default_alert_connected(),
+            alert_disconnected: Self::default_alert_disconnected(),
+            alert_weak_signal: Self::default_alert_weak_signal(),
+            alert_signal_recovered: None,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
@@ -0,0 +1,344 @@
+pub mod cfg;
+mod nl80211;
+mod parse;
+
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::Context;
+
+use crate::notify::Notifier;
+
+use self::{cfg::WifiConfig, nl80211::Nl80211};
+
+const ALERT_GROUP_WIFI_PREFIX: &str = "panorama.wifi.";
+
+/// Connection state of a wireless interface.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct WifiStatus {
+    interface: String,
+    ssid: Option<String>,
+    signal_dbm: Option<i32>,
+}
+
+#[derive(Default, Debug)]
+struct InterfaceState {
+    ssid: Option<String>,
+    weak_signal: bool,
+}
+
+pub struct WifiManager {
+    config: WifiConfig,
+    notifier: Notifier,
+    /// `None` if nl80211 is unavailable, in which case `/proc/net/wireless`
+    /// and `iw` are used.
+    nl80211: Option<Nl80211>,
+    interfaces: HashMap<String, InterfaceState>,
+    initial_observed: bool,
+}
+
+impl WifiManager {
+    pub async fn start(config: WifiConfig, notifier: Notifier) -> Result<(), anyhow::Error> {
+        let manager = Self::new(config, notifier)?;
+        tokio::task::spawn_local(async move { manager.run().await })
+            .await
+            .context("WifiManager task failed")?
+            .context("WifiManager failed")?;
+
+        Ok(())
+    }
+
+    fn new(config: WifiConfig, notifier: Notifier) -> Result<Self, anyhow::Error> {
+        let config = config.validate()?;
+        Ok(Self {
+            config,
+            notifier,
+            nl80211: None,
+            interfaces: HashMap::new(),
+            initial_observed: false,
+        })
+    }
+
+    async fn run(mut self) -> Result<(), anyhow::Error> {
+        self.nl80211 = match Nl80211::open() {
+            Ok(nl) => Some(nl),
+            Err(err) => {
+                tracing::warn!(
+                    error = &*err,
+                    "nl80211 unavailable - falling back to /proc/net/wireless and iw"
+                );
+                None
+            }
+        };
+
+        let interval = Duration::from_secs(self.config.check_interval_seconds);
+        loop {
+            let nl80211 = self.nl80211.take();
+            let (nl80211, statuses) = tokio::task::spawn_blocking(move || read_statuses(nl80211))
+                .await
+                .context("Wi-Fi status task failed")?;
+            self.nl80211 = nl80211;
+
+            match statuses {
+                Ok(statuses) => self.tick(statuses).await?,
+                Err(err) => tracing::warn!(error = &*err, "could not read Wi-Fi status"),
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    async fn tick(&mut self, statuses: Vec<WifiStatus>) -> Result<(), anyhow::Error> {
+        let initial = !self.initial_observed;
+        self.initial_observed = true;
+
+        // Interfaces that disappeared, eg removed USB adapters.
+        let gone = self
+            .interfaces
+            .keys()
+            .filter(|name| !statuses.iter().any(|s| &s.interface == *name))
+            .cloned()
+            .collect::<Vec<_>>();
+        for status in statuses {
+            self.update(status, initial).await?;
+        }
+        for interface in gone {
+            let status = WifiStatus {
+                interface: interface.clone(),
+                ssid: None,
+                signal_dbm: None,
+            };
+            self.update(status, initial).await?;
+            self.interfaces.remove(&interface);
+        }
+
+        Ok(())
+    }
+
+    async fn update(&mut self, status: WifiStatus, initial: bool) -> Result<(), anyhow::Error> {
+        let state = self.interfaces.entry(status.interface.clone()).or_default();
+        let previous_ssid = std::mem::replace(&mut state.ssid, status.ssid.clone());
+
+        let mut alerts = Vec::new();
+        if previous_ssid != status.ssid {
+            state.weak_signal = false;
+            match (&previous_ssid, &status.ssid) {
+                (Some(_), None) => alerts.push((&self.config.alert_disconnected, "connection")),
+                (_, Some(_)) => alerts.push((&self.config.alert_connected, "connection")),
+                (None, None) => {}
+            }
+        }
+
+        if let (Some(_), Some(signal)) = (&status.ssid, status.signal_dbm) {
+            if !state.weak_signal && signal < self.config.weak_signal_dbm {
+                state.weak_signal = true;
+                alerts.push((&self.config.alert_weak_signal, "signal"));
+            } else if state.weak_signal
+                && signal >= self.config.weak_signal_dbm + self.config.signal_hysteresis_db
+            {
+                state.weak_signal = false;
+                alerts.push((&self.config.alert_signal_recovered, "signal"));
+            }
+        }
+
+        for (alert, kind) in alerts {
+            let Some(alert) = alert else {
+                continue;
+            };
+            let ssid = status.ssid.clone().or(previous_ssid.clone());
+            let variables = HashMap::from([
+                ("interface".to_string(), status.interface.clone()),
+                ("ssid".to_string(), ssid.unwrap_or_default()),
+                (
+                    "signal_dbm".to_string(),
+                    status.signal_dbm.map(|s| s.to_string()).unwrap_or_default(),
+                ),
+            ]);
+            let group = format!("{ALERT_GROUP_WIFI_PREFIX}{}.{kind}", status.interface);
+            let full = alert.prepare(group, variables).initial(initial);
+            self.notifier.notify(full).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Read the status of all wireless interfaces.
+///
+/// Takes and returns the nl80211 client so it can be used on the blocking
+/// thread pool.
+fn read_statuses(
+    mut nl80211: Option<Nl80211>,
+) -> (Option<Nl80211>, Result<Vec<WifiStatus>, anyhow::Error>) {
+    let proc = match std::fs::read_to_string(parse::PROC_NET_WIRELESS) {
+        Ok(content) => parse::parse_proc_wireless(&content),
+        Err(err) => {
+            tracing::trace!(
+                error = &err as &dyn std::error::Error,
+                "could not read /proc/net/wireless"
+            );
+            Vec::new()
+        }
+    };
+    let proc_signal = |interface: &str| {
+        proc.iter()
+            .find(|p| p.interface == interface)
+            .and_then(|p| p.signal_dbm)
+    };
+
+    let res = match &mut nl80211 {
+        Some(nl) => read_nl80211(nl).map(|mut statuses| {
+            for status in &mut statuses {
+                if status.ssid.is_some() && status.signal_dbm.is_none() {
+                    status.signal_dbm = proc_signal(&status.interface);
+                }
+            }
+            statuses
+        }),
+        None => Ok(proc
+            .iter()
+            .map(|p| {
+                let link = read_iw_link(&p.interface).unwrap_or_else(|err| {
+                    tracing::debug!(error = &*err, interface=%p.interface, "iw failed");
+                    parse::IwLink {
+                        ssid: None,
+                        signal_dbm: None,
+                    }
+                });
+                WifiStatus {
+                    interface: p.interface.clone(),
+                    ssid: link.ssid,
+                    signal_dbm: link.signal_dbm.or(p.signal_dbm),
+                }
+            })
+            .collect()),
+    };
+    (nl80211, res)
+}
+
+fn read_nl80211(nl: &mut Nl80211) -> Result<Vec<WifiStatus>, anyhow::Error> {
+    let mut statuses = Vec::new();
+    for interface in nl.interfaces()? {
+        let signal_dbm = match interface.ssid {
+            Some(_) => nl.signal(interface.index)?,
+            None => None,
+        };
+        statuses.push(WifiStatus {
+            interface: interface.name,
+            ssid: interface.ssid,
+            signal_dbm,
+        });
+    }
+    Ok(statuses)
+}
+
+fn read_iw_link(interface: &str) -> Result<parse::IwLink, anyhow::Error> {
+    let output = std::process::Command::new("iw")
+        .args(["dev", interface, "link"])
+        .output()
+        .context("could not run iw")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "iw failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(parse::parse_iw_link(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::notify::PreparedAlert;
+
+    fn manager() -> (WifiManager, mpsc::Receiver<PreparedAlert>) {
+        let config = WifiConfig {
+            alert_signal_recovered: WifiConfig::default().alert_weak_signal.map(|a| {
+                crate::cfg::Alert {
+                    summary: "Wi-Fi signal recovered (${signal_dbm} dBm)".to_string(),
+                    ..a
+                }
+            }),
+            ..Default::default()
+        };
+        let (notifier, alerts) = Notifier::test_channel();
+        (WifiManager::new(config, notifier).unwrap(), alerts)
+    }
+
+    fn status(ssid: Option<&str>, signal_dbm: Option<i32>) -> Vec<WifiStatus> {
+        vec![WifiStatus {
+            interface: "wlan0".to_string(),
+            ssid: ssid.map(String::from),
+            signal_dbm,
+        }]
+    }
+
+    fn next(alerts: &mut mpsc::Receiver<PreparedAlert>) -> (String, HashMap<String, String>) {
+        let alert = alerts.try_recv().unwrap();
+        (alert.alert.summary, alert.variables)
+    }
+
+    #[tokio::test]
+    async fn test_connect_and_disconnect() {
+        let (mut manager, mut alerts) = manager();
+
+        // Already connected at startup, which is not announced by default.
+        manager.tick(status(Some("Home"), Some(-50))).await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        manager.tick(status(None, None)).await.unwrap();
+        let (summary, variables) = next(&mut alerts);
+        assert_eq!(summary, "Wi-Fi disconnected from ${ssid}");
+        assert_eq!(variables["ssid"], "Home");
+        assert_eq!(variables["interface"], "wlan0");
+
+        manager.tick(status(Some("Cafe"), Some(-60))).await.unwrap();
+        let (summary, variables) = next(&mut alerts);
+        assert_eq!(summary, "Wi-Fi connected to ${ssid}");
+        assert_eq!(variables["ssid"], "Cafe");
+        assert_eq!(variables["signal_dbm"], "-60");
+
+        // Roaming to another network.
+        manager
+            .tick(status(Some("Office"), Some(-60)))
+            .await
+            .unwrap();
+        assert_eq!(next(&mut alerts).1["ssid"], "Office");
+        assert!(alerts.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_weak_signal_hysteresis() {
+        let (mut manager, mut alerts) = manager();
+        manager.tick(status(Some("Home"), Some(-70))).await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        manager.tick(status(Some("Home"), Some(-80))).await.unwrap();
+        let (summary, variables) = next(&mut alerts);
+        assert_eq!(summary, "Weak Wi-Fi signal on ${ssid} (${signal_dbm} dBm)");
+        assert_eq!(variables["signal_dbm"], "-80");
+
+        // Within the hysteresis.
+        manager.tick(status(Some("Home"), Some(-72))).await.unwrap();
+        manager.tick(status(Some("Home"), Some(-78))).await.unwrap();
+        assert!(alerts.try_recv().is_err());
+
+        manager.tick(status(Some("Home"), Some(-70))).await.unwrap();
+        let (summary, _) = next(&mut alerts);
+        assert_eq!(summary, "Wi-Fi signal recovered (${signal_dbm} dBm)");
+    }
+
+    #[tokio::test]
+    async fn test_interface_removed() {
+        let (mut manager, mut alerts) = manager();
+        manager.tick(status(Some("Home"), Some(-50))).await.unwrap();
+        manager.tick(Vec::new()).await.unwrap();
+        let (summary, variables) = next(&mut alerts);
+        assert_eq!(summary, "Wi-Fi disconnected from ${ssid}");
+        assert_eq!(variables["ssid"], "Home");
+        assert!(manager.interfaces.is_empty());
+    }
+}
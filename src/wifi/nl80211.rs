@@ -0,0 +1,289 @@
+//! Minimal nl80211 client for the SSID and signal of station interfaces.
+
+use std::{io::Read, time::Duration};
+
+use anyhow::Context;
+
+use crate::network::netlink::{self, attributes, parse_u32};
+
+const NETLINK_GENERIC: i32 = 16;
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+
+const NL80211_CMD_GET_INTERFACE: u8 = 5;
+const NL80211_CMD_GET_STATION: u8 = 17;
+const NL80211_ATTR_IFINDEX: u16 = 3;
+const NL80211_ATTR_IFNAME: u16 = 4;
+const NL80211_ATTR_IFTYPE: u16 = 5;
+const NL80211_ATTR_STA_INFO: u16 = 21;
+const NL80211_ATTR_SSID: u16 = 52;
+const NL80211_STA_INFO_SIGNAL: u16 = 7;
+const NL80211_IFTYPE_STATION: u32 = 2;
+
+const GENL_HDR_LEN: usize = 4;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Interface {
+    pub index: u32,
+    pub name: String,
+    /// SSID of the network the interface is associated with.
+    pub ssid: Option<String>,
+}
+
+pub struct Nl80211 {
+    socket: socket2::Socket,
+    family: u16,
+    seq: u32,
+    buf: Vec<u8>,
+}
+
+impl Nl80211 {
+    pub fn open() -> Result<Self, anyhow::Error> {
+        let socket = netlink::open_socket(NETLINK_GENERIC, 0)?;
+        socket.set_read_timeout(Some(Duration::from_secs(2)))?;
+        let mut client = Self {
+            socket,
+            family: GENL_ID_CTRL,
+            seq: 0,
+            buf: vec![0; 64 * 1024],
+        };
+
+        let name = netlink::attribute(CTRL_ATTR_FAMILY_NAME, b"nl80211\0");
+        let responses = client.request(GENL_ID_CTRL, CTRL_CMD_GETFAMILY, false, &[name])?;
+        client.family = responses
+            .iter()
+            .find_map(|payload| parse_family_id(payload))
+            .context("nl80211 is not available")?;
+        Ok(client)
+    }
+
+    /// All station (client) interfaces.
+    pub fn interfaces(&mut self) -> Result<Vec<Interface>, anyhow::Error> {
+        let responses = self.request(self.family, NL80211_CMD_GET_INTERFACE, true, &[])?;
+        Ok(responses
+            .iter()
+            .filter_map(|payload| parse_interface(payload))
+            .collect())
+    }
+
+    /// Signal strength of the access point the interface is associated with,
+    /// in dBm.
+    pub fn signal(&mut self, index: u32) -> Result<Option<i32>, anyhow::Error> {
+        let attr = netlink::attribute(NL80211_ATTR_IFINDEX, &index.to_ne_bytes());
+        let responses = self.request(self.family, NL80211_CMD_GET_STATION, true, &[attr])?;
+        Ok(responses
+            .iter()
+            .find_map(|payload| parse_station_signal(payload)))
+    }
+
+    /// Send a request and collect the payloads of the responses, without the
+    /// generic netlink header.
+    fn request(
+        &mut self,
+        family: u16,
+        cmd: u8,
+        dump: bool,
+        attrs: &[Vec<u8>],
+    ) -> Result<Vec<Vec<u8>>, anyhow::Error> {
+        self.seq += 1;
+        let mut body = vec![cmd, 1, 0, 0];
+        for attr in attrs {
+            body.extend_from_slice(attr);
+        }
+        let flags = if dump {
+            netlink::NLM_F_REQUEST | netlink::NLM_F_DUMP
+        } else {
+            netlink::NLM_F_REQUEST
+        };
+        self.socket
+            .send(&netlink::message(family, flags, self.seq, &body))
+            .context("could not send nl80211 request")?;
+
+        let mut responses = Vec::new();
+        loop {
+            let len = (&self.socket)
+                .read(&mut self.buf)
+                .context("could not read nl80211 response")?;
+            if collect_responses(&self.buf[..len], family, self.seq, dump, &mut responses)? {
+                return Ok(responses);
+            }
+        }
+    }
+}
+
+/// Collect the payloads of the responses to request `seq` in a datagram,
+/// returning whether the request is complete. Late responses to earlier
+/// requests that timed out are skipped.
+fn collect_responses(
+    datagram: &[u8],
+    family: u16,
+    seq: u32,
+    dump: bool,
+    responses: &mut Vec<Vec<u8>>,
+) -> Result<bool, anyhow::Error> {
+    for message in netlink::split_messages(datagram)? {
+        if message.seq != seq {
+            continue;
+        }
+        let payload = message.payload;
+        match message.kind {
+            netlink::NLMSG_DONE => return Ok(true),
+            netlink::NLMSG_ERROR => {
+                let errno = netlink::parse_error(payload)?;
+                if errno != 0 {
+                    return Err(std::io::Error::from_raw_os_error(-errno))
+                        .context("nl80211 request failed");
+                }
+                return Ok(true);
+            }
+            kind if kind == family => {
+                responses.push(payload.get(GENL_HDR_LEN..).unwrap_or_default().to_vec());
+                if !dump {
+                    return Ok(true);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(false)
+}
+
+fn parse_family_id(payload: &[u8]) -> Option<u16> {
+    attributes(payload)
+        .find(|(kind, _)| *kind == CTRL_ATTR_FAMILY_ID)
+        .and_then(|(_, value)| Some(u16::from_ne_bytes(value.try_into().ok()?)))
+}
+
+/// Parse a `NL80211_CMD_NEW_INTERFACE` response, skipping non-station
+/// interfaces.
+fn parse_interface(payload: &[u8]) -> Option<Interface> {
+    let mut index = None;
+    let mut name = None;
+    let mut iftype = None;
+    let mut ssid = None;
+    for (kind, value) in attributes(payload) {
+        match kind {
+            NL80211_ATTR_IFINDEX => index = parse_u32(value),
+            NL80211_ATTR_IFNAME => {
+                let value = value.split(|b| *b == 0).next().unwrap_or_default();
+                name = Some(String::from_utf8_lossy(value).into_owned());
+            }
+            NL80211_ATTR_IFTYPE => iftype = parse_u32(value),
+            NL80211_ATTR_SSID => ssid = Some(String::from_utf8_lossy(value).into_owned()),
+            _ => {}
+        }
+    }
+    if iftype != Some(NL80211_IFTYPE_STATION) {
+        return None;
+    }
+    Some(Interface {
+        index: index?,
+        name: name?,
+        ssid,
+    })
+}
+
+/// Parse the signal from a `NL80211_CMD_NEW_STATION` response.
+fn parse_station_signal(payload: &[u8]) -> Option<i32> {
+    let (_, info) = attributes(payload).find(|(kind, _)| *kind == NL80211_ATTR_STA_INFO)?;
+    let (_, signal) = attributes(info).find(|(kind, _)| *kind == NL80211_STA_INFO_SIGNAL)?;
+    Some(i32::from(*signal.first()? as i8))
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::network::netlink::attribute;
+
+    fn payload(attrs: &[Vec<u8>]) -> Vec<u8> {
+        attrs.concat()
+    }
+
+    #[test]
+    fn test_collect_responses() {
+        let body = |ifindex: u32| {
+            let mut body = vec![NL80211_CMD_GET_INTERFACE, 1, 0, 0];
+            body.extend(attribute(NL80211_ATTR_IFINDEX, &ifindex.to_ne_bytes()));
+            body
+        };
+        // A late response to the previous request is skipped.
+        let datagram = [
+            netlink::message(0x1c, 0, 6, &body(2)),
+            netlink::message(netlink::NLMSG_DONE, 0, 6, &[0; 4]),
+            netlink::message(0x1c, 0, 7, &body(3)),
+        ]
+        .concat();
+        let mut responses = Vec::new();
+        assert!(!collect_responses(&datagram, 0x1c, 7, true, &mut responses).unwrap());
+        assert_eq!(
+            responses,
+            vec![attribute(NL80211_ATTR_IFINDEX, &3u32.to_ne_bytes())]
+        );
+
+        let done = netlink::message(netlink::NLMSG_DONE, 0, 7, &[0; 4]);
+        assert!(collect_responses(&done, 0x1c, 7, true, &mut responses).unwrap());
+        assert_eq!(responses.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_family_id() {
+        let payload = payload(&[
+            attribute(CTRL_ATTR_FAMILY_NAME, b"nl80211\0"),
+            attribute(CTRL_ATTR_FAMILY_ID, &0x1cu16.to_ne_bytes()),
+        ]);
+        assert_eq!(parse_family_id(&payload), Some(0x1c));
+    }
+
+    #[test]
+    fn test_parse_interface() {
+        let connected = payload(&[
+            attribute(NL80211_ATTR_IFINDEX, &3u32.to_ne_bytes()),
+            attribute(NL80211_ATTR_IFNAME, b"wlan0\0"),
+            attribute(NL80211_ATTR_IFTYPE, &NL80211_IFTYPE_STATION.to_ne_bytes()),
+            attribute(NL80211_ATTR_SSID, b"Office Net"),
+        ]);
+        assert_eq!(
+            parse_interface(&connected),
+            Some(Interface {
+                index: 3,
+                name: "wlan0".to_string(),
+                ssid: Some("Office Net".to_string()),
+            })
+        );
+
+        let disconnected = payload(&[
+            attribute(NL80211_ATTR_IFINDEX, &3u32.to_ne_bytes()),
+            attribute(NL80211_ATTR_IFNAME, b"wlan0\0"),
+            attribute(NL80211_ATTR_IFTYPE, &NL80211_IFTYPE_STATION.to_ne_bytes()),
+        ]);
+        assert_eq!(parse_interface(&disconnected).unwrap().ssid, None);
+
+        // Access point interface.
+        let ap = payload(&[
+            attribute(NL80211_ATTR_IFINDEX, &4u32.to_ne_bytes()),
+            attribute(NL80211_ATTR_IFNAME, b"ap0\0"),
+            attribute(NL80211_ATTR_IFTYPE, &3u32.to_ne_bytes()),
+        ]);
+        assert_eq!(parse_interface(&ap), None);
+    }
+
+    #[test]
+    fn test_parse_station_signal() {
+        let info = payload(&[
+            attribute(1, &1000u32.to_ne_bytes()),
+            attribute(NL80211_STA_INFO_SIGNAL, &[(-67i8) as u8]),
+        ]);
+        let station = payload(&[
+            attribute(NL80211_ATTR_IFINDEX, &3u32.to_ne_bytes()),
+            attribute(NL80211_ATTR_STA_INFO, &info),
+        ]);
+        assert_eq!(parse_station_signal(&station), Some(-67));
+
+        let no_info = payload(&[attribute(NL80211_ATTR_IFINDEX, &3u32.to_ne_bytes())]);
+        assert_eq!(parse_station_signal(&no_info), None);
+    }
+}
@@ -0,0 +1,125 @@
+//! Parsers for `/proc/net/wireless` and `iw dev <interface> link` output,
+//! used when nl80211 is not available.
+
+/// A line of `/proc/net/wireless`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProcWireless {
+    pub interface: String,
+    /// Signal level in dBm, `None` if not associated.
+    pub signal_dbm: Option<i32>,
+}
+
+pub const PROC_NET_WIRELESS: &str = "/proc/net/wireless";
+
+pub fn parse_proc_wireless(content: &str) -> Vec<ProcWireless> {
+    content
+        .lines()
+        // Two header lines.
+        .skip(2)
+        .filter_map(|line| {
+            let (interface, rest) = line.split_once(':')?;
+            // status, link quality, signal level, noise, ...
+            let level = rest.split_whitespace().nth(2)?;
+            let level = level.trim_end_matches('.').parse::<f64>().ok()?;
+            Some(ProcWireless {
+                interface: interface.trim().to_string(),
+                // Drivers report 0 (or the unsigned equivalent) while not
+                // associated.
+                signal_dbm: (level < 0.0).then_some(level as i32),
+            })
+        })
+        .collect()
+}
+
+/// Parsed `iw dev <interface> link` output.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IwLink {
+    pub ssid: Option<String>,
+    pub signal_dbm: Option<i32>,
+}
+
+pub fn parse_iw_link(output: &str) -> IwLink {
+    let mut link = IwLink {
+        ssid: None,
+        signal_dbm: None,
+    };
+    if !output.starts_with("Connected to") {
+        return link;
+    }
+    for line in output.lines().skip(1) {
+        let Some((key, value)) = line.trim().split_once(": ") else {
+            continue;
+        };
+        match key {
+            "SSID" => link.ssid = Some(value.to_string()),
+            "signal" => {
+                link.signal_dbm = value.trim_end_matches("dBm").trim().parse::<i32>().ok();
+            }
+            _ => {}
+        }
+    }
+    link
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_proc_wireless() {
+        let content = "\
+Inter-| sta-|   Quality        |   Discarded packets               | Missed | WE
+ face | tus | link level noise |  nwid  crypt   frag  retry   misc | beacon | 22
+wlp3s0: 0000   54.  -56.  -256        0      0      0      0     12        0
+ wlan1: 0000    0.    0.     0        0      0      0      0      0        0
+";
+        assert_eq!(
+            parse_proc_wireless(content),
+            vec![
+                ProcWireless {
+                    interface: "wlp3s0".to_string(),
+                    signal_dbm: Some(-56),
+                },
+                ProcWireless {
+                    interface: "wlan1".to_string(),
+                    signal_dbm: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_iw_link() {
+        let output = "\
+Connected to 00:11:22:33:44:55 (on wlp3s0)
+\tSSID: Home: 5GHz
+\tfreq: 5180
+\tRX: 1234567 bytes (8910 packets)
+\tTX: 123456 bytes (789 packets)
+\tsignal: -61 dBm
+\trx bitrate: 433.3 MBit/s VHT-MCS 9 80MHz short GI VHT-NSS 1
+\ttx bitrate: 390.0 MBit/s VHT-MCS 8 80MHz short GI VHT-NSS 1
+
+\tbss flags:\tshort-slot-time
+\tdtim period:\t1
+\tbeacon int:\t100
+";
+        assert_eq!(
+            parse_iw_link(output),
+            IwLink {
+                ssid: Some("Home: 5GHz".to_string()),
+                signal_dbm: Some(-61),
+            }
+        );
+
+        assert_eq!(
+            parse_iw_link("Not connected.\n"),
+            IwLink {
+                ssid: None,
+                signal_dbm: None,
+            }
+        );
+    }
+}